@@ -1,14 +1,23 @@
 use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use bevy::time::common_conditions::on_timer;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
 
+use crate::fragmentation::{FragmentBuffers, FragmentationConfig};
+use crate::heartbeat::{HeartbeatConfig, PeerLiveness};
+use crate::input_buffer::{InputBufferConfig, InputBufferDiagnostics, InputBuffers, InputTick};
+use crate::instantiation::{InstantiateOptions, NetworkedInstances, PrefabRegistry};
+use crate::lobby::{JoinAttempt, LobbyAdmission, LobbyConfig};
+use crate::player_inputs::PlayerInputs;
+use crate::protocol::P2PProtocol;
+use crate::recording::{NetworkRecorder, RecordedRoute};
+use crate::simulator::{NetworkConditions, NetworkSimulator};
 use crate::state::{
-    InstantiationData, InstantiationDataNet, IsHost, NetworkedEntity, NetworkedId, P2PData,
-    P2PLobbyState, PlayerInfo, SyncedEventRegister, SyncedStateRegister,
+    InstantiationData, InstantiationDataNet, IsHost, NetTransform, NetworkedEntity, NetworkedId,
+    P2PData, P2PLobbyState, PlayerInfo, SyncedEventRegister, SyncedStateRegister,
 };
+use crate::transport::{ConnectedPeers, P2PTransport, drive_transport, poll_transport};
 use crate::updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+use crate::validation::{ClientStrikes, ClientValidation};
 use crate::{ClientId, networked_transform};
 
 #[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone, Copy)]
@@ -37,33 +46,46 @@ where
     S: States + Clone + Send + Sync + 'static;
 
 #[derive(Message)]
-pub(crate) struct OnRosterUpdate<
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
->(pub Vec<PlayerInfo<PlayerData>>);
+pub(crate) struct OnRosterUpdate<P: P2PProtocol>(pub Vec<PlayerInfo<P::PlayerData>>);
 #[derive(Message, Clone)]
-pub(crate) struct OnRelayToAllExcept<PlayerData, PlayerInputData, Instantiations>(
-    pub ClientId,
-    pub P2PData<PlayerData, PlayerInputData, Instantiations>,
-);
+pub(crate) struct OnRelayToAllExcept<P: P2PProtocol>(pub ClientId, pub P2PData<P>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ExitReason {
     Disconnected,
     Kicked,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LobbyFailureReason {
+    RoomNotFound,
+    Timeout,
+    Full,
+    VersionMismatch,
+    TransportError(String),
+}
+
+impl core::fmt::Display for LobbyFailureReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LobbyFailureReason::RoomNotFound => write!(f, "Lobby not found"),
+            LobbyFailureReason::Timeout => write!(f, "Timed out"),
+            LobbyFailureReason::Full => write!(f, "Lobby is full"),
+            LobbyFailureReason::VersionMismatch => write!(f, "Game version mismatch"),
+            LobbyFailureReason::TransportError(err) => write!(f, "Connection error: {}", err),
+        }
+    }
 }
 
 #[derive(Message, Clone)]
 pub(crate) struct OnLobbyExit(pub ExitReason);
 #[derive(Message, Clone)]
-pub(crate) struct OnTransportRosterChanged(pub Vec<String>);
+pub(crate) struct OnJoinFailed(pub LobbyFailureReason);
+#[derive(Message, Clone)]
+pub(crate) struct OnCreateFailed(pub LobbyFailureReason);
+#[derive(Message, Clone)]
+pub(crate) struct OnTransportRosterChanged(pub Vec<ClientId>);
 #[derive(Message, Clone)]
 pub(crate) struct OnTransportSendToHost(pub String);
 #[derive(Message, Clone)]
@@ -77,34 +99,25 @@ pub(crate) struct OnTransportIncomingFromClient(pub ClientId, pub String);
 #[derive(Message, Clone)]
 pub(crate) struct OnTransportIncomingFromHost(pub String);
 #[derive(Message, Clone)]
-pub(crate) struct HandleInstantiation<Instantiations>(pub InstantiationData<Instantiations>);
-#[derive(Message, Clone)]
-pub(crate) struct OnInternalClientData<PlayerData, PlayerInputData, Instantiations>(
-    pub ClientId,
-    pub P2PData<PlayerData, PlayerInputData, Instantiations>,
+pub(crate) struct HandleInstantiation<P: P2PProtocol>(
+    pub Entity,
+    pub InstantiationData<P::Instantiations>,
 );
 #[derive(Message, Clone)]
-pub(crate) struct OnInternalHostData<PlayerData, PlayerInputData, Instantiations>(
-    pub P2PData<PlayerData, PlayerInputData, Instantiations>,
-);
+pub(crate) struct OnInternalClientData<P: P2PProtocol>(pub ClientId, pub P2PData<P>);
+#[derive(Message, Clone)]
+pub(crate) struct OnInternalHostData<P: P2PProtocol>(pub P2PData<P>);
 
 #[derive(Message, Clone)]
 pub(crate) struct OnCreateLobbyReq;
 #[derive(Message, Clone)]
 pub(crate) struct OnJoinLobbyReq(pub String);
 #[derive(Message, Clone)]
-pub(crate) struct OnSendToHostReq<PlayerData, PlayerInputData, Instantiations>(
-    pub P2PData<PlayerData, PlayerInputData, Instantiations>,
-);
+pub(crate) struct OnSendToHostReq<P: P2PProtocol>(pub P2PData<P>);
 #[derive(Message, Clone)]
-pub(crate) struct OnSendToAllReq<PlayerData, PlayerInputData, Instantiations>(
-    pub P2PData<PlayerData, PlayerInputData, Instantiations>,
-);
+pub(crate) struct OnSendToAllReq<P: P2PProtocol>(pub P2PData<P>);
 #[derive(Message, Clone)]
-pub(crate) struct OnSendToClientReq<PlayerData, PlayerInputData, Instantiations>(
-    pub ClientId,
-    pub P2PData<PlayerData, PlayerInputData, Instantiations>,
-);
+pub(crate) struct OnSendToClientReq<P: P2PProtocol>(pub ClientId, pub P2PData<P>);
 #[derive(Message, Clone)]
 pub(crate) struct OnExitLobbyReq;
 #[derive(Message, Clone)]
@@ -113,68 +126,27 @@ pub(crate) struct OnKickReq(pub ClientId);
 #[derive(Message)]
 pub struct PingUpdate(pub std::time::Duration);
 
-pub trait P2PTransport: Send + Sync + 'static {
-    type Error;
-
-    fn create_lobby(world: &mut World) -> Result<String, Self::Error>;
-    fn join_lobby(world: &mut World, code: &str) -> Result<(), Self::Error>;
-    fn exit_lobby(world: &mut World);
-    fn send_to_host(world: &mut World, text: String);
-    fn send_to_all(world: &mut World, text: String);
-    fn kick(world: &mut World, client_id: ClientId);
-    fn poll_transport(world: &mut World);
-}
-
 #[derive(SystemParam)]
-pub struct EasyP2P<
-    'w,
-    's,
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-> {
+pub struct EasyP2P<'w, 's, P: P2PProtocol> {
     create_w: MessageWriter<'w, OnCreateLobbyReq>,
     join_w: MessageWriter<'w, OnJoinLobbyReq>,
     exit_w: MessageWriter<'w, OnExitLobbyReq>,
-    send_host_w: MessageWriter<'w, OnSendToHostReq<PlayerData, PlayerInputData, Instantiations>>,
-    send_all_w: MessageWriter<'w, OnSendToAllReq<PlayerData, PlayerInputData, Instantiations>>,
+    send_host_w: MessageWriter<'w, OnSendToHostReq<P>>,
+    send_all_w: MessageWriter<'w, OnSendToAllReq<P>>,
     kick_w: MessageWriter<'w, OnKickReq>,
-    instantiation_set: ParamSet<
-        'w,
-        's,
-        (
-            MessageWriter<'w, HandleInstantiation<Instantiations>>,
-            MessageReader<'w, 's, HandleInstantiation<Instantiations>>,
-        ),
-    >,
-    state: ResMut<'w, crate::state::EasyP2PState<PlayerData>>,
-    updates: ResMut<'w, EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>,
+    instantiation_w: MessageWriter<'w, HandleInstantiation<P>>,
+    instances: ResMut<'w, NetworkedInstances>,
+    commands: Commands<'w, 's>,
+    state: ResMut<'w, crate::state::EasyP2PState<<P as P2PProtocol>::PlayerData>>,
+    updates: ResMut<'w, EasyP2PUpdateQueue<P>>,
+    input_tick: ResMut<'w, InputTick>,
+    player_inputs: ResMut<'w, PlayerInputs<P>>,
     children_q: Query<'w, 's, &'static ChildOf>,
     network_entities_q: Query<'w, 's, &'static NetworkedEntity>,
-    _marker: std::marker::PhantomData<&'s T>,
-    roster_w: MessageWriter<'w, OnRosterUpdate<PlayerData>>,
+    roster_w: MessageWriter<'w, OnRosterUpdate<P>>,
 }
 
-impl<'w, 's, T, PlayerData: Default + PartialEq, PlayerInputData, Instantiations>
-    EasyP2P<'w, 's, T, PlayerData, PlayerInputData, Instantiations>
-where
-    T: P2PTransport,
-    PlayerData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+impl<'w, 's, P: P2PProtocol> EasyP2P<'w, 's, P> {
     pub fn create_lobby(&mut self) {
         self.create_w.write(OnCreateLobbyReq);
     }
@@ -196,9 +168,13 @@ where
         info!("sending message to all: {:?}", &msg);
         self.send_all_w.write(OnSendToAllReq(msg));
     }
-    pub fn send_inputs(&mut self, input: PlayerInputData) {
-        let msg = P2PData::ClientInput(input.clone());
+    /// Sends the local player's input for this step. Call it once per fixed step when the host
+    /// uses an `InputBufferConfig`.
+    pub fn send_inputs(&mut self, input: P::PlayerInputData) {
+        self.input_tick.0 = self.input_tick.0.wrapping_add(1);
+        let msg = P2PData::ClientInput(self.input_tick.0, input.clone());
         if self.is_host() {
+            self.player_inputs.receive(NetworkedId::Host, input.clone());
             self.updates.push(EasyP2PUpdate::ClientInput {
                 sender: NetworkedId::Host,
                 input,
@@ -207,29 +183,41 @@ where
             self.send_host_w.write(OnSendToHostReq(msg));
         }
     }
-    pub fn instantiate(&mut self, instantiation: Instantiations, transform: Transform) {
-        self.instantiation_set
-            .p0()
-            .write(HandleInstantiation(InstantiationData {
-                transform: transform.clone(),
-                instantiation: instantiation.clone(),
-            }));
+    /// Spawns `instantiation` through its registered `Prefab`, on every peer when called by the
+    /// host and locally otherwise. The returned entity is filled in during the next
+    /// `EasyP2PSystemSet::Core`.
+    pub fn instantiate(
+        &mut self,
+        instantiation: P::Instantiations,
+        transform: Transform,
+    ) -> Entity {
+        self.instantiate_with(instantiation, transform, InstantiateOptions::default())
+    }
+    pub fn instantiate_with(
+        &mut self,
+        instantiation: P::Instantiations,
+        transform: Transform,
+        options: InstantiateOptions,
+    ) -> Entity {
+        let entity = self.commands.spawn_empty().id();
+        let data = options.into_data(instantiation, transform);
         if self.state.is_host {
-            let net: InstantiationDataNet<Instantiations> =
-                InstantiationDataNet::from(&InstantiationData {
-                    transform,
-                    instantiation,
-                });
+            let net = InstantiationDataNet {
+                transform: NetTransform::from(&data.transform),
+                instantiation: data.instantiation.clone(),
+                owner: data.owner,
+                despawn_on_leave: data.despawn_on_leave,
+                instance: self.instances.allocate(entity),
+                parent: data
+                    .parent
+                    .and_then(|parent| self.instances.instance_of(parent)),
+            };
             self.send_all_w
                 .write(OnSendToAllReq(P2PData::HostInstantiation(net)));
         }
-    }
-    pub fn get_instantiations(&mut self) -> Vec<InstantiationData<Instantiations>> {
-        self.instantiation_set
-            .p1()
-            .read()
-            .map(|inst| inst.0.clone())
-            .collect()
+        self.instantiation_w
+            .write(HandleInstantiation(entity, data));
+        entity
     }
     pub fn kick(&mut self, client_id: ClientId) {
         self.kick_w.write(OnKickReq(client_id));
@@ -237,13 +225,16 @@ where
     pub fn is_host(&self) -> bool {
         self.state.is_host
     }
-    pub fn get_players(&self) -> Vec<PlayerInfo<PlayerData>> {
+    pub fn is_dedicated_host(&self) -> bool {
+        self.state.dedicated_host
+    }
+    pub fn get_players(&self) -> Vec<PlayerInfo<P::PlayerData>> {
         self.state.get_players(self.is_host())
     }
-    pub fn get_local_player_data(&self) -> PlayerData {
+    pub fn get_local_player_data(&self) -> P::PlayerData {
         self.state.local_player_data.clone()
     }
-    pub fn set_local_player_data(&mut self, data: PlayerData) {
+    pub fn set_local_player_data(&mut self, data: P::PlayerData) {
         self.state.local_player_data = data.clone();
         if self.state.is_host {
             let players = self.state.get_players(self.state.is_host);
@@ -258,12 +249,7 @@ where
                 .write(OnSendToHostReq(P2PData::ClientDataUpdate(data)));
         }
     }
-    pub fn read_updates(
-        &mut self,
-    ) -> impl Iterator<Item = EasyP2PUpdate<PlayerData, PlayerInputData, Instantiations>> {
-        self.updates.drain()
-    }
-    pub fn get_player_data(&self, id: NetworkedId) -> PlayerData {
+    pub fn get_player_data(&self, id: NetworkedId) -> P::PlayerData {
         self.get_players()
             .iter()
             .find(|player| player.id == id)
@@ -290,13 +276,7 @@ where
 }
 
 #[derive(SystemParam)]
-pub struct EasyP2PTransportIo<
-    'w,
-    's,
-    PlayerData: 'static,
-    PlayerInputData: 'static,
-    Instantiations: 'static,
-> {
+pub(crate) struct EasyP2PTransportIo<'w, 's, P: P2PProtocol> {
     create_r: MessageReader<'w, 's, OnCreateLobbyReq>,
     join_r: MessageReader<'w, 's, OnJoinLobbyReq>,
     exit_r: MessageReader<'w, 's, OnExitLobbyReq>,
@@ -316,15 +296,21 @@ pub struct EasyP2PTransportIo<
             MessageReader<'w, 's, OnLobbyExit>,
         ),
     >,
+    join_failed_w: MessageWriter<'w, OnJoinFailed>,
+    create_failed_w: MessageWriter<'w, OnCreateFailed>,
     roster_changed_w: MessageWriter<'w, OnTransportRosterChanged>,
     incoming_client_w: MessageWriter<'w, OnTransportIncomingFromClient>,
     incoming_host_w: MessageWriter<'w, OnTransportIncomingFromHost>,
-    _marker: std::marker::PhantomData<(PlayerData, PlayerInputData, Instantiations)>,
+    fragmentation: Res<'w, FragmentationConfig>,
+    fragments: ResMut<'w, FragmentBuffers>,
+    conditions: Res<'w, NetworkConditions>,
+    simulator: ResMut<'w, NetworkSimulator>,
+    time: Res<'w, Time<Real>>,
+    recorder: ResMut<'w, NetworkRecorder>,
+    _marker: std::marker::PhantomData<P>,
 }
 
-impl<'w, 's, PlayerData: 'static, PlayerInputData: 'static, Instantiations: 'static>
-    EasyP2PTransportIo<'w, 's, PlayerData, PlayerInputData, Instantiations>
-{
+impl<'w, 's, P: P2PProtocol> EasyP2PTransportIo<'w, 's, P> {
     pub fn take_create_requests(&mut self) -> usize {
         self.create_r.read().count()
     }
@@ -350,96 +336,263 @@ impl<'w, 's, PlayerData: 'static, PlayerInputData: 'static, Instantiations: 'sta
     }
 
     pub fn take_send_to_host(&mut self) -> Vec<String> {
-        self.send_host_r
+        let payloads: Vec<String> = self
+            .send_host_r
             .read()
             .map(|OnTransportSendToHost(text)| text.clone())
-            .collect()
+            .collect();
+        self.record_all(&payloads, RecordedRoute::ToHost);
+        let fragments = self.fragment_all(payloads);
+        let now = self.time.elapsed_secs_f64();
+        self.simulator.route(
+            |queues| &mut queues.to_host,
+            self.conditions.enabled,
+            &self.conditions.outgoing,
+            now,
+            fragments,
+        )
     }
 
     pub fn take_send_to_all(&mut self) -> Vec<String> {
-        self.send_all_r
+        let payloads: Vec<String> = self
+            .send_all_r
             .read()
             .map(|OnTransportSendToAll(text)| text.clone())
-            .collect()
+            .collect();
+        self.record_all(&payloads, RecordedRoute::ToAll);
+        let fragments = self.fragment_all(payloads);
+        let now = self.time.elapsed_secs_f64();
+        self.simulator.route(
+            |queues| &mut queues.to_all,
+            self.conditions.enabled,
+            &self.conditions.outgoing,
+            now,
+            fragments,
+        )
     }
 
     pub fn take_send_to_client(&mut self) -> Vec<(ClientId, String)> {
-        self.send_client_r
+        let payloads: Vec<(ClientId, String)> = self
+            .send_client_r
             .read()
             .map(|OnTransportSendToClient(client_id, text)| (*client_id, text.clone()))
-            .collect()
+            .collect();
+        self.record_all_addressed(&payloads, RecordedRoute::ToClient);
+        let fragments = self.fragment_all_addressed(payloads);
+        let now = self.time.elapsed_secs_f64();
+        self.simulator.route(
+            |queues| &mut queues.to_client,
+            self.conditions.enabled,
+            &self.conditions.outgoing,
+            now,
+            fragments,
+        )
     }
 
     pub fn take_relay_to_all_except(&mut self) -> Vec<(ClientId, String)> {
-        self.relay_except_r
+        let payloads: Vec<(ClientId, String)> = self
+            .relay_except_r
             .read()
             .map(|OnTransportRelayToAllExcept(client_id, text)| (*client_id, text.clone()))
+            .collect();
+        self.record_all_addressed(&payloads, RecordedRoute::RelayToAllExcept);
+        let fragments = self.fragment_all_addressed(payloads);
+        let now = self.time.elapsed_secs_f64();
+        self.simulator.route(
+            |queues| &mut queues.relay_except,
+            self.conditions.enabled,
+            &self.conditions.outgoing,
+            now,
+            fragments,
+        )
+    }
+
+    fn record(&mut self, route: RecordedRoute, payload: &str) {
+        if !self.recorder.is_recording() {
+            return;
+        }
+        let now = self.time.elapsed_secs_f64();
+        self.recorder.record(now, route, payload);
+    }
+
+    fn record_all(&mut self, payloads: &[String], route: RecordedRoute) {
+        for payload in payloads {
+            self.record(route.clone(), payload);
+        }
+    }
+
+    fn record_all_addressed(
+        &mut self,
+        payloads: &[(ClientId, String)],
+        route: impl Fn(ClientId) -> RecordedRoute,
+    ) {
+        for (client_id, payload) in payloads {
+            self.record(route(*client_id), payload);
+        }
+    }
+
+    fn fragment_all(&mut self, payloads: Vec<String>) -> Vec<String> {
+        let config = *self.fragmentation;
+        payloads
+            .into_iter()
+            .flat_map(|text| self.fragments.split(&config, text))
+            .collect()
+    }
+
+    fn fragment_all_addressed(
+        &mut self,
+        payloads: Vec<(ClientId, String)>,
+    ) -> Vec<(ClientId, String)> {
+        let config = *self.fragmentation;
+        payloads
+            .into_iter()
+            .flat_map(|(client_id, text)| {
+                self.fragments
+                    .split(&config, text)
+                    .into_iter()
+                    .map(move |chunk| (client_id, chunk))
+            })
             .collect()
     }
 
     pub fn emit_lobby_created(&mut self, code: impl Into<String>) {
-        self.lobby_created_w.write(OnLobbyCreated(code.into()));
+        let code = code.into();
+        self.record(RecordedRoute::LobbyCreated, &code);
+        self.lobby_created_w.write(OnLobbyCreated(code));
     }
 
     pub fn emit_lobby_joined(&mut self, code: impl Into<String>) {
-        self.lobby_joined_w.write(OnLobbyJoined(code.into()));
+        let code = code.into();
+        self.record(RecordedRoute::LobbyJoined, &code);
+        self.lobby_joined_w.write(OnLobbyJoined(code));
     }
 
     pub fn emit_lobby_entered(&mut self, code: impl Into<String>) {
-        self.lobby_entered_w.write(OnLobbyEntered(code.into()));
+        let code = code.into();
+        self.record(RecordedRoute::LobbyEntered, &code);
+        self.lobby_entered_w.write(OnLobbyEntered(code));
     }
 
     pub fn emit_lobby_exit(&mut self, reason: ExitReason) {
+        self.record(RecordedRoute::LobbyExited(reason), "");
         self.lobby_exit_rw.p0().write(OnLobbyExit(reason));
     }
 
-    pub fn emit_roster_changed(&mut self, roster: Vec<String>) {
+    pub fn emit_join_failed(&mut self, reason: LobbyFailureReason) {
+        self.join_failed_w.write(OnJoinFailed(reason));
+    }
+
+    pub fn emit_create_failed(&mut self, reason: LobbyFailureReason) {
+        self.create_failed_w.write(OnCreateFailed(reason));
+    }
+
+    pub fn emit_roster_changed(&mut self, roster: Vec<ClientId>) {
         self.roster_changed_w
             .write(OnTransportRosterChanged(roster));
     }
 
     pub fn emit_incoming_from_client(&mut self, client_id: ClientId, payload: impl Into<String>) {
-        self.incoming_client_w
-            .write(OnTransportIncomingFromClient(client_id, payload.into()));
+        let now = self.time.elapsed_secs_f64();
+        let due = self.simulator.route(
+            |queues| &mut queues.from_client,
+            self.conditions.enabled,
+            &self.conditions.incoming,
+            now,
+            vec![(client_id, payload.into())],
+        );
+        for (client_id, payload) in due {
+            self.deliver_from_client(client_id, payload);
+        }
     }
 
     pub fn emit_incoming_from_host(&mut self, payload: impl Into<String>) {
+        let now = self.time.elapsed_secs_f64();
+        let due = self.simulator.route(
+            |queues| &mut queues.from_host,
+            self.conditions.enabled,
+            &self.conditions.incoming,
+            now,
+            vec![payload.into()],
+        );
+        for payload in due {
+            self.deliver_from_host(payload);
+        }
+    }
+
+    pub(crate) fn release_simulated_incoming(&mut self) {
+        let now = self.time.elapsed_secs_f64();
+        let from_host = self.simulator.route(
+            |queues| &mut queues.from_host,
+            self.conditions.enabled,
+            &self.conditions.incoming,
+            now,
+            Vec::new(),
+        );
+        for payload in from_host {
+            self.deliver_from_host(payload);
+        }
+        let from_client = self.simulator.route(
+            |queues| &mut queues.from_client,
+            self.conditions.enabled,
+            &self.conditions.incoming,
+            now,
+            Vec::new(),
+        );
+        for (client_id, payload) in from_client {
+            self.deliver_from_client(client_id, payload);
+        }
+    }
+
+    fn deliver_from_client(&mut self, client_id: ClientId, payload: String) {
+        let Some(payload) = self
+            .fragments
+            .reassemble(NetworkedId::ClientId(client_id), payload)
+        else {
+            return;
+        };
+        self.record(RecordedRoute::FromClient(client_id), &payload);
+        self.incoming_client_w
+            .write(OnTransportIncomingFromClient(client_id, payload));
+    }
+
+    fn deliver_from_host(&mut self, payload: String) {
+        let Some(payload) = self.fragments.reassemble(NetworkedId::Host, payload) else {
+            return;
+        };
+        self.record(RecordedRoute::FromHost, &payload);
         self.incoming_host_w
-            .write(OnTransportIncomingFromHost(payload.into()));
+            .write(OnTransportIncomingFromHost(payload));
     }
 }
 
-pub struct EasyP2PPlugin<T: P2PTransport, PlayerData, PlayerInputData, Instantiations>(
-    std::marker::PhantomData<(T, PlayerData, PlayerInputData, Instantiations)>,
-);
+// `fn() -> T` keeps the plugin `Send + Sync` for transports that aren't.
+pub struct EasyP2PPlugin<T: P2PTransport, P: P2PProtocol>(std::marker::PhantomData<(fn() -> T, P)>);
 
-impl<T: P2PTransport, PlayerData, PlayerInputData, Instantiations> Default
-    for EasyP2PPlugin<T, PlayerData, PlayerInputData, Instantiations>
-{
+impl<T: P2PTransport, P: P2PProtocol> Default for EasyP2PPlugin<T, P> {
     fn default() -> Self {
         Self(std::marker::PhantomData)
     }
 }
 
-impl<T, PlayerData, PlayerInputData, Instantiations> Plugin
-    for EasyP2PPlugin<T, PlayerData, PlayerInputData, Instantiations>
+impl<T, P> Plugin for EasyP2PPlugin<T, P>
 where
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
+    T: P2PTransport + Default,
+    P: P2PProtocol,
 {
     fn build(&self, app: &mut App) {
+        // A transport inserted beforehand (e.g. with custom settings) is kept as is.
+        if !app.world().contains_non_send::<T>() {
+            app.insert_non_send_resource(T::default());
+        }
+        let max_message_size = app.world().non_send_resource::<T>().max_message_size();
+        // A limit set by the app is kept, as long as the transport can deliver it.
+        let max_message_size = app
+            .world()
+            .get_resource::<FragmentationConfig>()
+            .map_or(max_message_size, |config| {
+                config.max_message_size.min(max_message_size)
+            });
+        T::setup(app);
         // Configure Network system sets for PreUpdate (receiving messages early)
         app.configure_sets(
             PreUpdate,
@@ -460,25 +613,47 @@ where
             )
                 .chain(),
         )
-        .init_resource::<crate::state::EasyP2PState<PlayerData>>()
+        .init_resource::<crate::state::EasyP2PState<P::PlayerData>>()
         .init_resource::<IsHost>()
         .init_resource::<SyncedStateRegister>()
         .init_resource::<SyncedEventRegister>()
-        .init_resource::<EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>()
+        .init_resource::<EasyP2PUpdateQueue<P>>()
+        .insert_resource(FragmentationConfig::new(max_message_size))
+        .init_resource::<ConnectedPeers>()
+        .init_resource::<FragmentBuffers>()
+        .init_resource::<NetworkConditions>()
+        .init_resource::<NetworkSimulator>()
+        .init_resource::<NetworkRecorder>()
+        .init_resource::<LobbyConfig>()
+        .init_resource::<JoinAttempt>()
+        .init_resource::<LobbyAdmission>()
+        .init_resource::<HeartbeatConfig>()
+        .init_resource::<PeerLiveness>()
+        .init_resource::<NetworkedInstances>()
+        .init_resource::<ClientValidation<P>>()
+        .init_resource::<ClientStrikes>()
+        .init_resource::<InputBufferConfig>()
+        .init_resource::<InputBufferDiagnostics>()
+        .init_resource::<InputBuffers<P>>()
+        .init_resource::<InputTick>()
+        .init_resource::<PlayerInputs<P>>()
+        .init_resource::<PrefabRegistry<P>>()
         .init_state::<P2PLobbyState>()
         .add_message::<OnCreateLobbyReq>()
         .add_message::<OnJoinLobbyReq>()
-        .add_message::<OnSendToHostReq<PlayerData, PlayerInputData, Instantiations>>()
-        .add_message::<OnSendToAllReq<PlayerData, PlayerInputData, Instantiations>>()
-        .add_message::<OnSendToClientReq<PlayerData, PlayerInputData, Instantiations>>()
+        .add_message::<OnSendToHostReq<P>>()
+        .add_message::<OnSendToAllReq<P>>()
+        .add_message::<OnSendToClientReq<P>>()
         .add_message::<OnExitLobbyReq>()
         .add_message::<OnKickReq>()
         .add_message::<OnLobbyCreated>()
         .add_message::<OnLobbyJoined>()
         .add_message::<OnLobbyEntered>()
-        .add_message::<OnInternalClientData<PlayerData, PlayerInputData, Instantiations>>()
-        .add_message::<OnInternalHostData<PlayerData, PlayerInputData, Instantiations>>()
+        .add_message::<OnInternalClientData<P>>()
+        .add_message::<OnInternalHostData<P>>()
         .add_message::<OnLobbyExit>()
+        .add_message::<OnJoinFailed>()
+        .add_message::<OnCreateFailed>()
         .add_message::<OnTransportRosterChanged>()
         .add_message::<OnTransportSendToHost>()
         .add_message::<OnTransportSendToAll>()
@@ -486,55 +661,81 @@ where
         .add_message::<OnTransportRelayToAllExcept>()
         .add_message::<OnTransportIncomingFromClient>()
         .add_message::<OnTransportIncomingFromHost>()
-        .add_message::<OnRosterUpdate<PlayerData>>()
-        .add_message::<OnRelayToAllExcept<PlayerData, PlayerInputData, Instantiations>>()
-        .add_message::<HandleInstantiation<Instantiations>>()
+        .add_message::<OnRosterUpdate<P>>()
+        .add_message::<OnRelayToAllExcept<P>>()
+        .add_message::<HandleInstantiation<P>>()
         .add_message::<PingUpdate>()
+        .add_message::<EasyP2PUpdate<P>>()
+        .add_systems(
+            PreUpdate,
+            crate::systems::release_simulated_incoming::<P>.in_set(NetworkSystemSet::Receive),
+        )
+        .add_systems(
+            PreUpdate,
+            crate::recording::replay_recording.in_set(NetworkSystemSet::Receive),
+        )
+        .add_systems(
+            PreUpdate,
+            poll_transport::<T, P>.in_set(NetworkSystemSet::Receive),
+        )
+        .add_systems(
+            Update,
+            drive_transport::<T, P>.in_set(EasyP2PSystemSet::Transport),
+        )
         .add_systems(
             Update,
             (
-                crate::systems::state_update_system::<PlayerData, PlayerInputData, Instantiations>,
+                crate::lobby::track_join_attempt::<P>,
+                crate::systems::state_update_system::<P>,
                 (
-                    crate::systems::on_external_lobby_exit::<
-                        PlayerData,
-                        PlayerInputData,
-                        Instantiations,
-                    >,
-                    crate::systems::intercept_data_messages::<
-                        PlayerData,
-                        PlayerInputData,
-                        Instantiations,
-                    >,
-                    crate::systems::send_local_data_after_enter::<
-                        PlayerData,
-                        PlayerInputData,
-                        Instantiations,
-                    >,
-                    crate::systems::handle_client_data_update_on_host::<
-                        PlayerData,
-                        PlayerInputData,
-                        Instantiations,
-                    >,
-                    crate::systems::broadcast_roster_on_host::<
-                        PlayerData,
-                        PlayerInputData,
-                        Instantiations,
-                    >,
-                    crate::systems::encode_outgoing::<PlayerData, PlayerInputData, Instantiations>,
-                    crate::systems::decode_incoming::<PlayerData, PlayerInputData, Instantiations>,
-                    crate::systems::despawn_on_leave::<PlayerData>,
-                    crate::systems::send_ping::<PlayerData, PlayerInputData, Instantiations>
-                        .run_if(on_timer(Duration::from_secs(1))),
+                    crate::systems::on_external_lobby_exit::<P>,
+                    crate::systems::intercept_data_messages::<P>,
+                    crate::instantiation::spawn_instantiations::<P>
+                        .after(crate::systems::intercept_data_messages::<P>),
+                    crate::lobby::admit_clients::<P>
+                        .before(crate::systems::handle_client_data_update_on_host::<P>),
+                    crate::systems::send_local_data_after_enter::<P>,
+                    crate::systems::handle_client_data_update_on_host::<P>,
+                    crate::systems::broadcast_roster_on_host::<P>,
+                    crate::systems::encode_outgoing::<P>,
+                    crate::systems::decode_incoming::<P>,
+                    crate::systems::despawn_on_leave::<P>,
+                    crate::heartbeat::track_peer_liveness::<P>,
+                    crate::heartbeat::send_heartbeats::<P>,
                 ),
             )
                 .chain()
                 .in_set(EasyP2PSystemSet::Core),
         )
-        .add_plugins(networked_transform::NetworkedTransformPlugin::<
-            T,
-            PlayerData,
-            PlayerInputData,
-            Instantiations,
-        >::default());
+        .add_systems(
+            OnEnter(P2PLobbyState::OutOfLobby),
+            (
+                crate::instantiation::clear_instances,
+                crate::validation::clear_strikes,
+                crate::input_buffer::clear_input_buffers::<P>,
+                crate::player_inputs::clear_player_inputs::<P>,
+            ),
+        )
+        .add_systems(
+            FixedPreUpdate,
+            (
+                crate::input_buffer::release_buffered_inputs::<P>,
+                crate::player_inputs::advance_player_inputs::<P>,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            (
+                crate::player_inputs::forget_departed_players::<P>,
+                crate::input_buffer::forget_departed_clients::<P>,
+            )
+                .in_set(EasyP2PSystemSet::Core),
+        )
+        .add_systems(
+            Update,
+            crate::updates::emit_updates::<P>.in_set(EasyP2PSystemSet::Emit),
+        )
+        .add_plugins(networked_transform::NetworkedTransformPlugin::<P>::default());
     }
 }
@@ -0,0 +1,242 @@
+use bevy::prelude::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use crate::heartbeat::PeerLiveness;
+use crate::protocol::P2PProtocol;
+use crate::state::{
+    EasyP2PState, NetworkedId, P2PLobbyState, SyncedEventRegister, SyncedStateRegister,
+};
+
+#[derive(Clone, Debug)]
+pub struct NetworkLogEntry {
+    pub time: f32,
+    pub outgoing: bool,
+    /// `None` for broadcasts to every client.
+    pub peer: Option<NetworkedId>,
+    pub kind: &'static str,
+    pub bytes: usize,
+}
+
+/// Traffic counters collected while `NetworkDebugOverlayPlugin` is installed.
+#[derive(Resource)]
+pub struct NetworkStats {
+    pub sent: BTreeMap<&'static str, u64>,
+    pub received: BTreeMap<&'static str, u64>,
+    pub recent: VecDeque<NetworkLogEntry>,
+    pub max_recent: usize,
+}
+
+impl Default for NetworkStats {
+    fn default() -> Self {
+        Self {
+            sent: BTreeMap::new(),
+            received: BTreeMap::new(),
+            recent: VecDeque::new(),
+            max_recent: 12,
+        }
+    }
+}
+
+impl NetworkStats {
+    pub(crate) fn record(
+        &mut self,
+        time: f32,
+        outgoing: bool,
+        peer: Option<NetworkedId>,
+        kind: &'static str,
+        bytes: usize,
+    ) {
+        let entry = NetworkLogEntry {
+            time,
+            outgoing,
+            peer,
+            kind,
+            bytes,
+        };
+        let counters = if entry.outgoing {
+            &mut self.sent
+        } else {
+            &mut self.received
+        };
+        *counters.entry(entry.kind).or_default() += 1;
+        self.recent.push_back(entry);
+        while self.recent.len() > self.max_recent {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sent.clear();
+        self.received.clear();
+        self.recent.clear();
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct NetworkDebugOverlay {
+    pub visible: bool,
+    pub toggle_key: KeyCode,
+}
+
+impl Default for NetworkDebugOverlay {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            toggle_key: KeyCode::F3,
+        }
+    }
+}
+
+#[derive(Component)]
+struct NetworkDebugOverlayText;
+
+pub struct NetworkDebugOverlayPlugin<P: P2PProtocol>(std::marker::PhantomData<P>);
+
+impl<P: P2PProtocol> Default for NetworkDebugOverlayPlugin<P> {
+    fn default() -> Self {
+        Self(std::marker::PhantomData)
+    }
+}
+
+impl<P: P2PProtocol> Plugin for NetworkDebugOverlayPlugin<P> {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<NetworkStats>()
+            .init_resource::<NetworkDebugOverlay>()
+            .add_systems(Startup, spawn_overlay)
+            .add_systems(Update, (toggle_overlay, update_overlay::<P>).chain())
+            .add_systems(OnEnter(P2PLobbyState::OutOfLobby), clear_stats);
+    }
+}
+
+fn spawn_overlay(mut commands: Commands) {
+    commands.spawn((
+        NetworkDebugOverlayText,
+        Text::new(""),
+        TextFont {
+            font_size: 12.0,
+            ..default()
+        },
+        TextColor(Color::srgb(0.8, 1.0, 0.8)),
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.75)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(5),
+            left: px(5),
+            padding: UiRect::all(px(5)),
+            ..default()
+        },
+        GlobalZIndex(i32::MAX),
+        Visibility::Hidden,
+    ));
+}
+
+fn toggle_overlay(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut overlay: ResMut<NetworkDebugOverlay>,
+    mut texts: Query<&mut Visibility, With<NetworkDebugOverlayText>>,
+) {
+    if keyboard.just_pressed(overlay.toggle_key) {
+        overlay.visible = !overlay.visible;
+    }
+    for mut visibility in texts.iter_mut() {
+        *visibility = if overlay.visible {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn clear_stats(mut stats: ResMut<NetworkStats>) {
+    stats.clear();
+}
+
+fn update_overlay<P: P2PProtocol>(
+    overlay: Res<NetworkDebugOverlay>,
+    state: Res<EasyP2PState<P::PlayerData>>,
+    lobby_state: Res<State<P2PLobbyState>>,
+    stats: Res<NetworkStats>,
+    liveness: Res<PeerLiveness>,
+    states_register: Res<SyncedStateRegister>,
+    events_register: Res<SyncedEventRegister>,
+    mut texts: Query<&mut Text, With<NetworkDebugOverlayText>>,
+) {
+    if !overlay.visible {
+        return;
+    }
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:?} | lobby: {} | host: {}",
+        lobby_state.get(),
+        if state.lobby_code.is_empty() {
+            "-"
+        } else {
+            state.lobby_code.as_str()
+        },
+        state.is_host
+    );
+
+    let _ = writeln!(out, "\nPlayers");
+    for player in state.get_players(state.is_host) {
+        let rtt = liveness
+            .rtt(player.id)
+            .map(|rtt| format!("{} ms", rtt.as_millis()))
+            .unwrap_or_else(|| "-".to_string());
+        let stalled = if liveness.is_stalled(player.id) {
+            "  STALLED"
+        } else {
+            ""
+        };
+        let _ = writeln!(
+            out,
+            "  {:?}  rtt {}{}  {:?}",
+            player.id, rtt, stalled, player.data
+        );
+    }
+
+    let _ = writeln!(out, "\nMessages (sent / received)");
+    let mut kinds: Vec<&&'static str> = stats.sent.keys().chain(stats.received.keys()).collect();
+    kinds.sort();
+    kinds.dedup();
+    for kind in kinds {
+        let _ = writeln!(
+            out,
+            "  {}: {} / {}",
+            kind,
+            stats.sent.get(*kind).copied().unwrap_or(0),
+            stats.received.get(*kind).copied().unwrap_or(0)
+        );
+    }
+
+    let _ = writeln!(out, "\nSynced states");
+    for (index, name) in states_register.names.iter().enumerate() {
+        let _ = writeln!(out, "  [{}] {}", index, name);
+    }
+    let _ = writeln!(out, "Synced events");
+    for (index, name) in events_register.names.iter().enumerate() {
+        let _ = writeln!(out, "  [{}] {}", index, name);
+    }
+
+    let _ = writeln!(out, "\nRecent");
+    for entry in stats.recent.iter().rev() {
+        let peer = match entry.peer {
+            Some(peer) => format!("{:?}", peer),
+            None => "All".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "  {:>8.2}s {} {} {} ({} B)",
+            entry.time,
+            if entry.outgoing { "->" } else { "<-" },
+            peer,
+            entry.kind,
+            entry.bytes
+        );
+    }
+
+    for mut text in texts.iter_mut() {
+        text.0 = out.clone();
+    }
+}
@@ -0,0 +1,290 @@
+use bevy::prelude::*;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::NetworkedId;
+
+// Encoded payloads are JSON objects or strings, so they never start with '#'.
+const FRAGMENT_PREFIX: &str = "#F";
+// Room left for the prefix and the `seq:index:count:` header.
+const MAX_HEADER_LEN: usize = 40;
+// Partially received messages kept per peer before the oldest is dropped.
+const MAX_PENDING_PER_PEER: usize = 32;
+// Largest payload put back together, well above any state sync. Anything claiming more is
+// dropped before it can make us allocate for it.
+const MAX_REASSEMBLED_LEN: usize = 4 * 1024 * 1024;
+// Fragments carry at least `MAX_HEADER_LEN` bytes each, see `FragmentationConfig::new`.
+const MAX_FRAGMENTS: usize = MAX_REASSEMBLED_LEN.div_ceil(MAX_HEADER_LEN);
+
+/// Largest payload a transport can deliver in one message. Bigger payloads are split into
+/// fragments before being handed to the transport and put back together on the receiving side.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FragmentationConfig {
+    pub max_message_size: usize,
+}
+
+impl FragmentationConfig {
+    pub fn new(max_message_size: usize) -> Self {
+        Self {
+            max_message_size: max_message_size.max(MAX_HEADER_LEN * 2),
+        }
+    }
+}
+
+impl Default for FragmentationConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: usize::MAX,
+        }
+    }
+}
+
+struct PartialMessage {
+    count: usize,
+    parts: BTreeMap<usize, String>,
+    len: usize,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct FragmentBuffers {
+    next_seq: u32,
+    pending: HashMap<(NetworkedId, u32), PartialMessage>,
+    order: VecDeque<(NetworkedId, u32)>,
+}
+
+impl FragmentBuffers {
+    pub(crate) fn split(&mut self, config: &FragmentationConfig, payload: String) -> Vec<String> {
+        if payload.len() <= config.max_message_size && !payload.starts_with(FRAGMENT_PREFIX) {
+            return vec![payload];
+        }
+        let budget = config
+            .max_message_size
+            .saturating_sub(MAX_HEADER_LEN)
+            .max(1);
+        let mut chunks = Vec::new();
+        let mut rest = payload.as_str();
+        while !rest.is_empty() {
+            let mut end = budget.min(rest.len());
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == 0 {
+                // A single character wider than the budget; send it whole rather than loop forever.
+                end = rest
+                    .chars()
+                    .next()
+                    .map(char::len_utf8)
+                    .unwrap_or(rest.len());
+            }
+            let (chunk, tail) = rest.split_at(end);
+            chunks.push(chunk);
+            rest = tail;
+        }
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let count = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| format!("{FRAGMENT_PREFIX}{seq}:{index}:{count}:{chunk}"))
+            .collect()
+    }
+
+    /// Returns the full payload once every fragment has arrived. Unfragmented payloads are
+    /// returned unchanged.
+    pub(crate) fn reassemble(&mut self, source: NetworkedId, payload: String) -> Option<String> {
+        let Some(body) = payload.strip_prefix(FRAGMENT_PREFIX) else {
+            return Some(payload);
+        };
+        let mut fields = body.splitn(4, ':');
+        let (Some(seq), Some(index), Some(count), Some(chunk)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            warn!("Dropping malformed fragment from {:?}", source);
+            return None;
+        };
+        let (Ok(seq), Ok(index), Ok(count)) = (
+            seq.parse::<u32>(),
+            index.parse::<usize>(),
+            count.parse::<usize>(),
+        ) else {
+            warn!("Dropping malformed fragment from {:?}", source);
+            return None;
+        };
+        if count == 0 || index >= count {
+            warn!("Dropping out of range fragment from {:?}", source);
+            return None;
+        }
+        if count > MAX_FRAGMENTS {
+            warn!("Dropping oversized message from {:?}", source);
+            return None;
+        }
+
+        let key = (source, seq);
+        if !self.pending.contains_key(&key) {
+            self.evict_oldest_of(source);
+            self.order.push_back(key);
+        }
+        let partial = self.pending.entry(key).or_insert_with(|| PartialMessage {
+            count,
+            parts: BTreeMap::new(),
+            len: 0,
+        });
+        if partial.count != count {
+            warn!("Fragment count mismatch from {:?}", source);
+            return None;
+        }
+        if !partial.parts.contains_key(&index) {
+            if partial.len + chunk.len() > MAX_REASSEMBLED_LEN {
+                warn!("Dropping oversized message from {:?}", source);
+                self.forget(key);
+                return None;
+            }
+            partial.len += chunk.len();
+            partial.parts.insert(index, chunk.to_string());
+        }
+        if partial.parts.len() < count {
+            return None;
+        }
+
+        let partial = self.forget(key)?;
+        Some(partial.parts.into_values().collect())
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+        self.order.clear();
+    }
+
+    fn forget(&mut self, key: (NetworkedId, u32)) -> Option<PartialMessage> {
+        self.order.retain(|k| *k != key);
+        self.pending.remove(&key)
+    }
+
+    fn evict_oldest_of(&mut self, source: NetworkedId) {
+        let pending_for_source = self.order.iter().filter(|(s, _)| *s == source).count();
+        if pending_for_source < MAX_PENDING_PER_PEER {
+            return;
+        }
+        let Some(pos) = self.order.iter().position(|(s, _)| *s == source) else {
+            return;
+        };
+        if let Some(key) = self.order.remove(pos) {
+            self.pending.remove(&key);
+            warn!("Dropping incomplete message {} from {:?}", key.1, source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: NetworkedId = NetworkedId::ClientId(1);
+
+    // Leaves 40 bytes of payload per fragment.
+    fn config() -> FragmentationConfig {
+        FragmentationConfig::new(80)
+    }
+
+    fn reassemble_all(buffers: &mut FragmentBuffers, fragments: Vec<String>) -> Vec<String> {
+        fragments
+            .into_iter()
+            .filter_map(|fragment| buffers.reassemble(PEER, fragment))
+            .collect()
+    }
+
+    #[test]
+    fn small_payloads_are_not_split() {
+        let mut buffers = FragmentBuffers::default();
+        let fragments = buffers.split(&config(), "\"short\"".to_string());
+        assert_eq!(fragments, vec!["\"short\"".to_string()]);
+        assert_eq!(
+            buffers.reassemble(PEER, fragments[0].clone()),
+            Some("\"short\"".to_string())
+        );
+    }
+
+    #[test]
+    fn split_payloads_round_trip() {
+        let mut buffers = FragmentBuffers::default();
+        let payload = "x".repeat(200);
+        let fragments = buffers.split(&config(), payload.clone());
+        assert_eq!(fragments.len(), 5);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 80));
+        assert_eq!(reassemble_all(&mut buffers, fragments), vec![payload]);
+    }
+
+    #[test]
+    fn out_of_order_fragments_are_reassembled() {
+        let mut buffers = FragmentBuffers::default();
+        let payload: String = (0..150)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let mut fragments = buffers.split(&config(), payload.clone());
+        fragments.reverse();
+        assert_eq!(reassemble_all(&mut buffers, fragments), vec![payload]);
+    }
+
+    #[test]
+    fn duplicate_fragments_are_ignored() {
+        let mut buffers = FragmentBuffers::default();
+        let payload = "y".repeat(100);
+        let fragments = buffers.split(&config(), payload.clone());
+        let mut with_duplicate = vec![fragments[0].clone()];
+        with_duplicate.extend(fragments);
+        assert_eq!(reassemble_all(&mut buffers, with_duplicate), vec![payload]);
+    }
+
+    #[test]
+    fn fragments_disagreeing_on_the_count_are_dropped() {
+        let mut buffers = FragmentBuffers::default();
+        assert_eq!(buffers.reassemble(PEER, "#F7:0:2:ab".to_string()), None);
+        assert_eq!(buffers.reassemble(PEER, "#F7:1:3:cd".to_string()), None);
+        assert_eq!(
+            buffers.reassemble(PEER, "#F7:1:2:cd".to_string()),
+            Some("abcd".to_string())
+        );
+    }
+
+    #[test]
+    fn multi_byte_characters_are_not_cut() {
+        let mut buffers = FragmentBuffers::default();
+        // The 'é' straddles the 40 byte budget of the first fragment.
+        let payload = format!("{}é{}", "a".repeat(39), "b".repeat(60));
+        let fragments = buffers.split(&config(), payload.clone());
+        assert_eq!(reassemble_all(&mut buffers, fragments), vec![payload]);
+    }
+
+    #[test]
+    fn the_oldest_incomplete_message_is_evicted() {
+        let mut buffers = FragmentBuffers::default();
+        let messages: Vec<Vec<String>> = (0..=MAX_PENDING_PER_PEER)
+            .map(|_| buffers.split(&config(), "z".repeat(100)))
+            .collect();
+        for fragments in &messages {
+            assert_eq!(buffers.reassemble(PEER, fragments[0].clone()), None);
+        }
+        assert_eq!(
+            reassemble_all(&mut buffers, messages[1][1..].to_vec()),
+            vec!["z".repeat(100)]
+        );
+        // The first one made room for the last.
+        assert!(reassemble_all(&mut buffers, messages[0][1..].to_vec()).is_empty());
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected() {
+        let mut buffers = FragmentBuffers::default();
+        assert_eq!(
+            buffers.reassemble(PEER, "#F0:0:4000000000:x".to_string()),
+            None
+        );
+        assert!(buffers.pending.is_empty());
+
+        let chunk = "x".repeat(MAX_REASSEMBLED_LEN / 2 + 1);
+        assert_eq!(buffers.reassemble(PEER, format!("#F1:0:3:{}", chunk)), None);
+        assert_eq!(buffers.reassemble(PEER, format!("#F1:1:3:{}", chunk)), None);
+        assert!(buffers.pending.is_empty());
+        assert!(buffers.order.is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::api::{
+    ExitReason, OnInternalClientData, OnInternalHostData, OnKickReq, OnLobbyExit, OnSendToAllReq,
+    OnSendToClientReq, OnSendToHostReq, PingUpdate,
+};
+use crate::protocol::P2PProtocol;
+use crate::state::{EasyP2PState, NetworkedId, P2PData, P2PLobbyState};
+use crate::updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+
+/// Both sides ping each other every `interval`. A peer that stays silent for `stall_after` is
+/// reported as stalled, and after `timeout` it is dropped whatever the transport thinks of the
+/// connection.
+#[derive(Resource, Clone, Debug)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub stall_after: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            stall_after: Duration::from_secs(3),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PeerStatus {
+    /// Real time in seconds when anything was last received from the peer.
+    pub last_heard: f64,
+    pub rtt: Option<Duration>,
+    pub stalled: bool,
+}
+
+/// Liveness of every peer we expect to hear from: each client on the host, the host on clients.
+#[derive(Resource, Default)]
+pub struct PeerLiveness {
+    peers: HashMap<NetworkedId, PeerStatus>,
+}
+
+impl PeerLiveness {
+    pub fn get(&self, peer: NetworkedId) -> Option<&PeerStatus> {
+        self.peers.get(&peer)
+    }
+
+    pub fn rtt(&self, peer: NetworkedId) -> Option<Duration> {
+        self.peers.get(&peer).and_then(|status| status.rtt)
+    }
+
+    pub fn is_stalled(&self, peer: NetworkedId) -> bool {
+        self.peers.get(&peer).is_some_and(|status| status.stalled)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NetworkedId, &PeerStatus)> {
+        self.peers.iter()
+    }
+
+    // Returns true when the peer was stalled and just came back.
+    fn heard(&mut self, peer: NetworkedId, now: f64) -> bool {
+        let status = self.peers.entry(peer).or_insert(PeerStatus {
+            last_heard: now,
+            rtt: None,
+            stalled: false,
+        });
+        status.last_heard = now;
+        std::mem::replace(&mut status.stalled, false)
+    }
+
+    fn set_rtt(&mut self, peer: NetworkedId, rtt: Duration) {
+        if let Some(status) = self.peers.get_mut(&peer) {
+            status.rtt = Some(rtt);
+        }
+    }
+}
+
+pub(crate) fn track_peer_liveness<P: P2PProtocol>(
+    time: Res<Time<Real>>,
+    state: Res<EasyP2PState<P::PlayerData>>,
+    mut liveness: ResMut<PeerLiveness>,
+    mut internal_client_r: MessageReader<OnInternalClientData<P>>,
+    mut internal_host_r: MessageReader<OnInternalHostData<P>>,
+    mut w_send_client: MessageWriter<OnSendToClientReq<P>>,
+    mut w_send_host: MessageWriter<OnSendToHostReq<P>>,
+    mut ping_w: MessageWriter<PingUpdate>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+) {
+    let now = time.elapsed_secs_f64();
+    for OnInternalClientData(cid, data) in internal_client_r.read() {
+        if !state.is_host {
+            continue;
+        }
+        let peer = NetworkedId::ClientId(*cid);
+        if liveness.heard(peer, now) {
+            updates.push(EasyP2PUpdate::PeerResumed { peer });
+        }
+        match data {
+            P2PData::PingRequest(sent_at) => {
+                w_send_client.write(OnSendToClientReq(*cid, P2PData::PingResponse(*sent_at)));
+            }
+            P2PData::PingResponse(sent_at) => {
+                liveness.set_rtt(peer, Duration::from_secs_f64((now - sent_at).max(0.)));
+            }
+            _ => {}
+        }
+    }
+    for OnInternalHostData(data) in internal_host_r.read() {
+        if state.is_host {
+            continue;
+        }
+        let peer = NetworkedId::Host;
+        if liveness.heard(peer, now) {
+            updates.push(EasyP2PUpdate::PeerResumed { peer });
+        }
+        match data {
+            P2PData::PingRequest(sent_at) => {
+                w_send_host.write(OnSendToHostReq(P2PData::PingResponse(*sent_at)));
+            }
+            P2PData::PingResponse(sent_at) => {
+                let rtt = Duration::from_secs_f64((now - sent_at).max(0.));
+                liveness.set_rtt(peer, rtt);
+                ping_w.write(PingUpdate(rtt));
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn send_heartbeats<P: P2PProtocol>(
+    time: Res<Time<Real>>,
+    config: Res<HeartbeatConfig>,
+    state: Res<EasyP2PState<P::PlayerData>>,
+    lobby_state: Res<State<P2PLobbyState>>,
+    mut liveness: ResMut<PeerLiveness>,
+    mut next_ping_at: Local<f64>,
+    mut w_send_all: MessageWriter<OnSendToAllReq<P>>,
+    mut w_send_host: MessageWriter<OnSendToHostReq<P>>,
+    mut kick_w: MessageWriter<OnKickReq>,
+    mut exit_w: MessageWriter<OnLobbyExit>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+) {
+    if *lobby_state.get() != P2PLobbyState::InLobby {
+        liveness.peers.clear();
+        return;
+    }
+    let now = time.elapsed_secs_f64();
+
+    let expected: Vec<NetworkedId> = if state.is_host {
+        state
+            .players
+            .iter()
+            .map(|player| player.id)
+            .filter(|id| *id != NetworkedId::Host)
+            .collect()
+    } else {
+        vec![NetworkedId::Host]
+    };
+    liveness.peers.retain(|id, _| expected.contains(id));
+    for id in expected {
+        liveness.peers.entry(id).or_insert(PeerStatus {
+            last_heard: now,
+            rtt: None,
+            stalled: false,
+        });
+    }
+    // A long frame means we were the ones not running (e.g. a throttled background tab), so
+    // don't hold the silence against our peers.
+    if time.delta_secs_f64() > config.stall_after.as_secs_f64() {
+        for status in liveness.peers.values_mut() {
+            status.last_heard = now;
+        }
+    }
+
+    if now >= *next_ping_at {
+        *next_ping_at = now + config.interval.as_secs_f64();
+        if state.is_host {
+            w_send_all.write(OnSendToAllReq(P2PData::PingRequest(now)));
+        } else {
+            w_send_host.write(OnSendToHostReq(P2PData::PingRequest(now)));
+        }
+    }
+
+    let mut timed_out = Vec::new();
+    for (peer, status) in liveness.peers.iter_mut() {
+        let silent_for = now - status.last_heard;
+        if silent_for > config.timeout.as_secs_f64() {
+            timed_out.push(*peer);
+        } else if !status.stalled && silent_for > config.stall_after.as_secs_f64() {
+            status.stalled = true;
+            updates.push(EasyP2PUpdate::PeerStalled { peer: *peer });
+        }
+    }
+    for peer in timed_out {
+        warn!("No heartbeat from {:?}, dropping it", peer);
+        liveness.peers.remove(&peer);
+        updates.push(EasyP2PUpdate::PeerTimedOut { peer });
+        match peer {
+            NetworkedId::ClientId(cid) => {
+                kick_w.write(OnKickReq(cid));
+            }
+            NetworkedId::Host => {
+                exit_w.write(OnLobbyExit(ExitReason::TimedOut));
+            }
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ClientId;
+use crate::api::OnRosterUpdate;
+use crate::player_inputs::PlayerInputs;
+use crate::protocol::P2PProtocol;
+use crate::state::{IsHost, NetworkedId};
+use crate::updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+
+// Weight of the newest sample in the smoothed jitter.
+const JITTER_SMOOTHING: f64 = 0.1;
+
+/// Host-side smoothing of client inputs. When enabled, inputs are queued by the tick the client
+/// stamped them with and released one per client every fixed step, once the queue holds enough
+/// of them to ride out the jitter measured on that client. Clients are expected to call
+/// `EasyP2P::send_inputs` once per fixed step.
+#[derive(Resource, Clone, Debug)]
+pub struct InputBufferConfig {
+    pub enabled: bool,
+    pub min_depth: usize,
+    /// Older inputs are dropped beyond that many queued ones.
+    pub max_depth: usize,
+}
+
+impl Default for InputBufferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_depth: 1,
+            max_depth: 8,
+        }
+    }
+}
+
+impl InputBufferConfig {
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            ..default()
+        }
+    }
+
+    pub fn with_depth(mut self, min_depth: usize, max_depth: usize) -> Self {
+        self.min_depth = min_depth;
+        self.max_depth = max_depth.max(min_depth);
+        self
+    }
+
+    fn target_depth(&self, jitter: f64, step: f64) -> usize {
+        let depth = (jitter / step).ceil() as usize + 1;
+        depth.clamp(self.min_depth, self.max_depth)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InputBufferStats {
+    /// Inputs currently queued.
+    pub depth: usize,
+    pub target_depth: usize,
+    /// Smoothed deviation of input arrivals from the fixed step, in seconds.
+    pub jitter: f64,
+    /// Inputs that arrived after a later one had already been released.
+    pub late: u32,
+    /// Inputs skipped to bring the queue back to its target depth.
+    pub dropped: u32,
+    /// Fixed steps where the queue ran dry.
+    pub starved: u32,
+}
+
+/// Jitter buffer stats per client, kept up to date on the host while the buffer is enabled.
+#[derive(Resource, Default, Debug)]
+pub struct InputBufferDiagnostics(HashMap<ClientId, InputBufferStats>);
+
+impl InputBufferDiagnostics {
+    pub fn get(&self, client_id: ClientId) -> Option<&InputBufferStats> {
+        self.0.get(&client_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&ClientId, &InputBufferStats)> {
+        self.0.iter()
+    }
+}
+
+// Tick stamped on the local player's outgoing inputs.
+#[derive(Resource, Default)]
+pub(crate) struct InputTick(pub(crate) u32);
+
+struct ClientInputBuffer<I> {
+    queue: BTreeMap<u32, I>,
+    last_released: Option<u32>,
+    last_arrival: Option<(u32, f64)>,
+    primed: bool,
+    stats: InputBufferStats,
+}
+
+impl<I> Default for ClientInputBuffer<I> {
+    fn default() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            last_released: None,
+            last_arrival: None,
+            primed: false,
+            stats: InputBufferStats::default(),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct InputBuffers<P: P2PProtocol>(
+    HashMap<ClientId, ClientInputBuffer<P::PlayerInputData>>,
+);
+
+impl<P: P2PProtocol> Default for InputBuffers<P> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<P: P2PProtocol> InputBuffers<P> {
+    pub(crate) fn push(
+        &mut self,
+        config: &InputBufferConfig,
+        client_id: ClientId,
+        tick: u32,
+        input: P::PlayerInputData,
+        now: f64,
+        step: f64,
+    ) {
+        let buffer = self.0.entry(client_id).or_default();
+        if let Some((last_tick, last_time)) = buffer.last_arrival
+            && tick > last_tick
+        {
+            let expected = (tick - last_tick) as f64 * step;
+            let deviation = ((now - last_time) - expected).abs();
+            buffer.stats.jitter += (deviation - buffer.stats.jitter) * JITTER_SMOOTHING;
+        }
+        if buffer
+            .last_arrival
+            .is_none_or(|(last_tick, _)| tick > last_tick)
+        {
+            buffer.last_arrival = Some((tick, now));
+        }
+        if buffer
+            .last_released
+            .is_some_and(|released| tick <= released)
+        {
+            buffer.stats.late += 1;
+            return;
+        }
+        buffer.queue.insert(tick, input);
+        while buffer.queue.len() > config.max_depth {
+            buffer.queue.pop_first();
+            buffer.stats.dropped += 1;
+        }
+    }
+}
+
+// Where client inputs go on the host: through the jitter buffer when enabled, straight to the
+// game otherwise.
+#[derive(SystemParam)]
+pub(crate) struct IncomingInputs<'w, P: P2PProtocol> {
+    config: Res<'w, InputBufferConfig>,
+    buffers: ResMut<'w, InputBuffers<P>>,
+    player_inputs: ResMut<'w, PlayerInputs<P>>,
+    time: Res<'w, Time<Real>>,
+    fixed_time: Res<'w, Time<Fixed>>,
+}
+
+impl<'w, P: P2PProtocol> IncomingInputs<'w, P> {
+    pub(crate) fn receive(
+        &mut self,
+        updates: &mut EasyP2PUpdateQueue<P>,
+        client_id: ClientId,
+        tick: u32,
+        input: P::PlayerInputData,
+    ) {
+        if self.config.enabled {
+            let now = self.time.elapsed_secs_f64();
+            let step = self.fixed_time.timestep().as_secs_f64();
+            self.buffers
+                .push(&self.config, client_id, tick, input, now, step);
+            return;
+        }
+        let sender = NetworkedId::ClientId(client_id);
+        self.player_inputs.receive(sender, input.clone());
+        updates.push(EasyP2PUpdate::ClientInput { sender, input });
+    }
+}
+
+pub(crate) fn release_buffered_inputs<P: P2PProtocol>(
+    host: Res<IsHost>,
+    config: Res<InputBufferConfig>,
+    time: Res<Time<Fixed>>,
+    mut buffers: ResMut<InputBuffers<P>>,
+    mut diagnostics: ResMut<InputBufferDiagnostics>,
+    mut inputs: ResMut<PlayerInputs<P>>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+) {
+    if !host.0 || !config.enabled {
+        return;
+    }
+    let step = time.timestep().as_secs_f64();
+    for (client_id, buffer) in buffers.0.iter_mut() {
+        let target = config.target_depth(buffer.stats.jitter, step);
+        buffer.stats.target_depth = target;
+        if !buffer.primed && buffer.queue.len() >= target {
+            buffer.primed = true;
+        }
+        if buffer.primed {
+            // Catch up when the jitter calmed down and the queue is deeper than needed.
+            if buffer.queue.len() > target + 1 {
+                buffer.queue.pop_first();
+                buffer.stats.dropped += 1;
+            }
+            match buffer.queue.pop_first() {
+                Some((tick, input)) => {
+                    let sender = NetworkedId::ClientId(*client_id);
+                    buffer.last_released = Some(tick);
+                    inputs.receive(sender, input.clone());
+                    updates.push(EasyP2PUpdate::ClientInput { sender, input });
+                }
+                None => {
+                    buffer.stats.starved += 1;
+                    buffer.primed = false;
+                }
+            }
+        }
+        buffer.stats.depth = buffer.queue.len();
+        diagnostics.0.insert(*client_id, buffer.stats);
+    }
+}
+
+pub(crate) fn forget_departed_clients<P: P2PProtocol>(
+    mut roster_r: MessageReader<OnRosterUpdate<P>>,
+    mut buffers: ResMut<InputBuffers<P>>,
+    mut diagnostics: ResMut<InputBufferDiagnostics>,
+) {
+    for OnRosterUpdate(players) in roster_r.read() {
+        let present = |client_id: &ClientId| {
+            players
+                .iter()
+                .any(|player| player.id == NetworkedId::ClientId(*client_id))
+        };
+        buffers.0.retain(|client_id, _| present(client_id));
+        diagnostics.0.retain(|client_id, _| present(client_id));
+    }
+}
+
+pub(crate) fn clear_input_buffers<P: P2PProtocol>(
+    mut buffers: ResMut<InputBuffers<P>>,
+    mut diagnostics: ResMut<InputBufferDiagnostics>,
+    mut tick: ResMut<InputTick>,
+) {
+    buffers.0.clear();
+    diagnostics.0.clear();
+    tick.0 = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PlayerInfo;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[derive(Clone, Debug)]
+    struct TestProtocol;
+
+    impl P2PProtocol for TestProtocol {
+        type PlayerData = ();
+        type PlayerInputData = u32;
+        type Instantiations = ();
+    }
+
+    const CLIENT: ClientId = 1;
+
+    fn world(config: InputBufferConfig) -> World {
+        let mut world = World::new();
+        world.insert_resource(IsHost(true));
+        world.insert_resource(config);
+        world.init_resource::<Time<Fixed>>();
+        world.init_resource::<InputBuffers<TestProtocol>>();
+        world.init_resource::<InputBufferDiagnostics>();
+        world.init_resource::<PlayerInputs<TestProtocol>>();
+        world.init_resource::<EasyP2PUpdateQueue<TestProtocol>>();
+        world.init_resource::<Messages<OnRosterUpdate<TestProtocol>>>();
+        world
+    }
+
+    fn step(world: &World) -> f64 {
+        world.resource::<Time<Fixed>>().timestep().as_secs_f64()
+    }
+
+    // Pushes `tick` as if it arrived at `arrival` fixed steps.
+    fn push_at(world: &mut World, tick: u32, arrival: f64) {
+        let step = step(world);
+        let config = world.resource::<InputBufferConfig>().clone();
+        world.resource_mut::<InputBuffers<TestProtocol>>().push(
+            &config,
+            CLIENT,
+            tick,
+            tick,
+            arrival * step,
+            step,
+        );
+    }
+
+    fn push(world: &mut World, tick: u32) {
+        push_at(world, tick, tick as f64);
+    }
+
+    // Runs one fixed step and returns the tick it released.
+    fn release(world: &mut World) -> Option<u32> {
+        let before = released(world);
+        world
+            .run_system_once(release_buffered_inputs::<TestProtocol>)
+            .unwrap();
+        let after = released(world);
+        if after != before { after } else { None }
+    }
+
+    fn released(world: &World) -> Option<u32> {
+        world.resource::<InputBuffers<TestProtocol>>().0[&CLIENT].last_released
+    }
+
+    fn stats(world: &World) -> InputBufferStats {
+        world.resource::<InputBuffers<TestProtocol>>().0[&CLIENT].stats
+    }
+
+    #[test]
+    fn releases_in_order_once_primed() {
+        let mut world = world(InputBufferConfig::enabled().with_depth(3, 8));
+        push(&mut world, 2);
+        push(&mut world, 1);
+        assert_eq!(release(&mut world), None);
+        push(&mut world, 3);
+        assert_eq!(release(&mut world), Some(1));
+        assert_eq!(release(&mut world), Some(2));
+        assert_eq!(release(&mut world), Some(3));
+        assert_eq!(stats(&world).late, 0);
+        assert_eq!(stats(&world).dropped, 0);
+    }
+
+    #[test]
+    fn inputs_behind_the_released_tick_are_late() {
+        let mut world = world(InputBufferConfig::enabled());
+        push(&mut world, 1);
+        push(&mut world, 2);
+        assert_eq!(release(&mut world), Some(1));
+        assert_eq!(release(&mut world), Some(2));
+        push(&mut world, 1);
+        push(&mut world, 2);
+        assert_eq!(stats(&world).late, 2);
+        assert_eq!(release(&mut world), None);
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest_inputs() {
+        let mut world = world(InputBufferConfig::enabled().with_depth(1, 4));
+        for tick in 1..=6 {
+            push(&mut world, tick);
+        }
+        assert_eq!(stats(&world).dropped, 2);
+        // Deeper than needed: one more is skipped to catch up.
+        assert_eq!(release(&mut world), Some(4));
+        assert_eq!(stats(&world).dropped, 3);
+    }
+
+    #[test]
+    fn starving_waits_for_the_queue_to_refill() {
+        let mut world = world(InputBufferConfig::enabled().with_depth(2, 8));
+        push(&mut world, 1);
+        push(&mut world, 2);
+        assert_eq!(release(&mut world), Some(1));
+        assert_eq!(release(&mut world), Some(2));
+        assert_eq!(release(&mut world), None);
+        assert_eq!(stats(&world).starved, 1);
+        push(&mut world, 3);
+        assert_eq!(release(&mut world), None);
+        assert_eq!(stats(&world).starved, 1);
+        push(&mut world, 4);
+        assert_eq!(release(&mut world), Some(3));
+        let diagnostics = world.resource::<InputBufferDiagnostics>();
+        assert_eq!(diagnostics.get(CLIENT).unwrap().starved, 1);
+        assert_eq!(diagnostics.get(CLIENT).unwrap().depth, 1);
+    }
+
+    #[test]
+    fn depth_follows_the_jitter() {
+        let mut steady = world(InputBufferConfig::enabled());
+        for tick in 1..=40 {
+            push(&mut steady, tick);
+        }
+        release(&mut steady);
+        assert_eq!(stats(&steady).jitter, 0.);
+        assert_eq!(stats(&steady).target_depth, 1);
+
+        // Inputs arriving four at a time.
+        let mut bursty = world(InputBufferConfig::enabled());
+        for tick in 1..=40 {
+            push_at(&mut bursty, tick, tick.div_ceil(4) as f64 * 4.);
+        }
+        release(&mut bursty);
+        assert!(stats(&bursty).jitter > step(&bursty));
+        assert!(stats(&bursty).target_depth >= 3);
+    }
+
+    #[test]
+    fn departed_clients_are_forgotten() {
+        let mut world = world(InputBufferConfig::enabled());
+        push(&mut world, 1);
+        world.resource_mut::<InputBuffers<TestProtocol>>().push(
+            &InputBufferConfig::enabled(),
+            2,
+            1,
+            1,
+            0.,
+            0.1,
+        );
+        release(&mut world);
+        world.write_message(OnRosterUpdate::<TestProtocol>(vec![
+            PlayerInfo {
+                id: NetworkedId::Host,
+                data: (),
+            },
+            PlayerInfo {
+                id: NetworkedId::ClientId(2),
+                data: (),
+            },
+        ]));
+        world
+            .run_system_once(forget_departed_clients::<TestProtocol>)
+            .unwrap();
+        let buffers = world.resource::<InputBuffers<TestProtocol>>();
+        assert!(!buffers.0.contains_key(&CLIENT));
+        assert!(buffers.0.contains_key(&2));
+        let diagnostics = world.resource::<InputBufferDiagnostics>();
+        assert!(diagnostics.get(CLIENT).is_none());
+        assert!(diagnostics.get(2).is_some());
+    }
+}
@@ -0,0 +1,231 @@
+use bevy::ecs::message::MessageCursor;
+use bevy::ecs::system::{BoxedSystem, SystemId};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+use crate::api::HandleInstantiation;
+use crate::protocol::P2PProtocol;
+use crate::state::{InstanceId, InstantiationData, NetworkedEntity, NetworkedId};
+use crate::updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+
+/// Per-call options for `EasyP2P::instantiate_with`.
+#[derive(Clone, Debug)]
+pub struct InstantiateOptions {
+    owner: NetworkedId,
+    parent: Option<Entity>,
+    despawn_on_leave: bool,
+}
+
+impl Default for InstantiateOptions {
+    fn default() -> Self {
+        Self {
+            owner: NetworkedId::Host,
+            parent: None,
+            despawn_on_leave: true,
+        }
+    }
+}
+
+impl InstantiateOptions {
+    /// The player the spawned `NetworkedEntity` belongs to. Defaults to the host.
+    pub fn with_owner(mut self, owner: NetworkedId) -> Self {
+        self.owner = owner;
+        self
+    }
+    /// Parents the spawned entity to another instantiated entity. Only entities instantiated by
+    /// the host can be resolved on clients.
+    pub fn with_parent(mut self, parent: Entity) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+    pub fn with_despawn_on_leave(mut self, despawn_on_leave: bool) -> Self {
+        self.despawn_on_leave = despawn_on_leave;
+        self
+    }
+
+    pub(crate) fn into_data<I>(
+        self,
+        instantiation: I,
+        transform: Transform,
+    ) -> InstantiationData<I> {
+        InstantiationData {
+            transform,
+            instantiation,
+            owner: self.owner,
+            despawn_on_leave: self.despawn_on_leave,
+            parent: self.parent,
+        }
+    }
+}
+
+/// Input of a prefab spawn system. `entity` already carries the transform, `NetworkedEntity`
+/// and parent; the system only adds what makes the prefab.
+#[derive(Clone, Debug)]
+pub struct PrefabSpawn<P: P2PProtocol> {
+    pub entity: Entity,
+    pub data: InstantiationData<P::Instantiations>,
+}
+
+type PrefabMatcher<I> = Box<dyn Fn(&I) -> bool + Send + Sync>;
+type PrefabInsert = Box<dyn Fn(&mut EntityWorldMut) + Send + Sync>;
+
+/// How to build the entities of one `Instantiations` variant, on every peer.
+///
+/// ```ignore
+/// app.register_prefab(
+///     Prefab::<MyProtocol>::new(|inst| matches!(inst, MyInstantiations::Kart(_)), spawn_kart)
+///         .despawn_on_exit(AppState::Game),
+/// );
+/// ```
+pub struct Prefab<P: P2PProtocol> {
+    matches: PrefabMatcher<P::Instantiations>,
+    spawn: BoxedSystem<In<PrefabSpawn<P>>>,
+    inserts: Vec<PrefabInsert>,
+}
+
+impl<P: P2PProtocol> Prefab<P> {
+    pub fn new<M>(
+        matches: impl Fn(&P::Instantiations) -> bool + Send + Sync + 'static,
+        spawn: impl IntoSystem<In<PrefabSpawn<P>>, (), M>,
+    ) -> Self {
+        Self {
+            matches: Box::new(matches),
+            spawn: Box::new(IntoSystem::into_system(spawn)),
+            inserts: Vec::new(),
+        }
+    }
+    pub fn despawn_on_exit<S: States>(mut self, state: S) -> Self {
+        self.inserts.push(Box::new(move |entity| {
+            entity.insert(DespawnOnExit(state.clone()));
+        }));
+        self
+    }
+    pub fn despawn_on_enter<S: States>(mut self, state: S) -> Self {
+        self.inserts.push(Box::new(move |entity| {
+            entity.insert(DespawnOnEnter(state.clone()));
+        }));
+        self
+    }
+}
+
+struct RegisteredPrefab<P: P2PProtocol> {
+    matches: PrefabMatcher<P::Instantiations>,
+    spawn: SystemId<In<PrefabSpawn<P>>>,
+    inserts: Vec<PrefabInsert>,
+}
+
+#[derive(Resource)]
+pub(crate) struct PrefabRegistry<P: P2PProtocol>(Vec<RegisteredPrefab<P>>);
+
+impl<P: P2PProtocol> Default for PrefabRegistry<P> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+// Maps host instance ids to local entities, in both directions.
+#[derive(Resource, Default)]
+pub(crate) struct NetworkedInstances {
+    next: u32,
+    entities: HashMap<InstanceId, Entity>,
+}
+
+impl NetworkedInstances {
+    pub(crate) fn allocate(&mut self, entity: Entity) -> InstanceId {
+        let instance = InstanceId(self.next);
+        self.next += 1;
+        self.entities.insert(instance, entity);
+        instance
+    }
+    pub(crate) fn insert(&mut self, instance: InstanceId, entity: Entity) {
+        self.entities.insert(instance, entity);
+    }
+    pub(crate) fn entity(&self, instance: InstanceId) -> Option<Entity> {
+        self.entities.get(&instance).copied()
+    }
+    pub(crate) fn instance_of(&self, entity: Entity) -> Option<InstanceId> {
+        self.entities
+            .iter()
+            .find(|(_, e)| **e == entity)
+            .map(|(instance, _)| *instance)
+    }
+}
+
+pub(crate) fn clear_instances(mut instances: ResMut<NetworkedInstances>) {
+    *instances = NetworkedInstances::default();
+}
+
+pub trait NetworkedPrefabsExt {
+    fn register_prefab<P: P2PProtocol>(&mut self, prefab: Prefab<P>) -> &mut Self;
+}
+
+impl NetworkedPrefabsExt for App {
+    fn register_prefab<P: P2PProtocol>(&mut self, prefab: Prefab<P>) -> &mut Self {
+        let world = self.world_mut();
+        let spawn = world.register_boxed_system(prefab.spawn);
+        world
+            .get_resource_or_init::<PrefabRegistry<P>>()
+            .0
+            .push(RegisteredPrefab {
+                matches: prefab.matches,
+                spawn,
+                inserts: prefab.inserts,
+            });
+        self
+    }
+}
+
+pub(crate) fn spawn_instantiations<P: P2PProtocol>(
+    world: &mut World,
+    mut cursor: Local<MessageCursor<HandleInstantiation<P>>>,
+) {
+    let pending: Vec<HandleInstantiation<P>> = cursor
+        .read(world.resource::<Messages<HandleInstantiation<P>>>())
+        .cloned()
+        .collect();
+    if pending.is_empty() {
+        return;
+    }
+    world.resource_scope(|world, registry: Mut<PrefabRegistry<P>>| {
+        for HandleInstantiation(entity, data) in pending {
+            let parent = data
+                .parent
+                .filter(|parent| world.get_entity(*parent).is_ok());
+            let Ok(mut entity_mut) = world.get_entity_mut(entity) else {
+                continue;
+            };
+            entity_mut.insert((
+                data.transform,
+                NetworkedEntity {
+                    id: data.owner,
+                    despawn_on_leave: data.despawn_on_leave,
+                },
+            ));
+            if let Some(parent) = parent {
+                entity_mut.insert(ChildOf(parent));
+            }
+            match registry
+                .0
+                .iter()
+                .find(|prefab| (prefab.matches)(&data.instantiation))
+            {
+                Some(prefab) => {
+                    for insert in &prefab.inserts {
+                        insert(&mut entity_mut);
+                    }
+                    let spawn = PrefabSpawn {
+                        entity,
+                        data: data.clone(),
+                    };
+                    if let Err(err) = world.run_system_with(prefab.spawn, spawn) {
+                        warn!("Prefab spawn failed for {:?}: {}", data.instantiation, err);
+                    }
+                }
+                None => warn!("No prefab registered for {:?}", data.instantiation),
+            }
+            world
+                .resource_mut::<EasyP2PUpdateQueue<P>>()
+                .push(EasyP2PUpdate::Instantiated { entity, data });
+        }
+    });
+}
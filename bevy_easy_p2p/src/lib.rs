@@ -3,19 +3,47 @@ use bevy::state::state::FreelyMutableState;
 use serde::{Deserialize, Serialize};
 
 mod api;
+mod debug_overlay;
+mod fragmentation;
+mod heartbeat;
+mod input_buffer;
+mod instantiation;
+mod lobby;
+mod player_inputs;
+mod protocol;
+mod recording;
+mod simulator;
 mod state;
 mod systems;
+mod transport;
 mod updates;
+mod validation;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod net;
 pub mod networked_transform;
 pub mod prelude;
 
 pub use api::{
-    EasyP2P, EasyP2PPlugin, EasyP2PSystemSet, EasyP2PTransportIo, ExitReason, OnApplyState,
-    PingUpdate, P2PTransport,
+    EasyP2P, EasyP2PPlugin, EasyP2PSystemSet, ExitReason, LobbyFailureReason, OnApplyState,
+    PingUpdate,
 };
+pub use debug_overlay::{
+    NetworkDebugOverlay, NetworkDebugOverlayPlugin, NetworkLogEntry, NetworkStats,
+};
+pub use fragmentation::FragmentationConfig;
+pub use heartbeat::{HeartbeatConfig, PeerLiveness, PeerStatus};
+pub use input_buffer::{InputBufferConfig, InputBufferDiagnostics, InputBufferStats};
+pub use instantiation::{InstantiateOptions, NetworkedPrefabsExt, Prefab, PrefabSpawn};
+pub use lobby::LobbyConfig;
+pub use player_inputs::PlayerInputs;
+pub use protocol::P2PProtocol;
+pub use recording::{NetworkRecorder, NetworkReplay, RecordedPayload, RecordedRoute};
+pub use simulator::{LinkConditions, NetworkConditions};
 pub use state::*;
-pub use updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+pub use transport::{P2PTransport, SendTarget, TransportError, TransportEvent};
+pub use updates::EasyP2PUpdate;
+pub use validation::{ClientValidation, Validation};
 
 pub type ClientId = u64;
 
@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::ClientId;
+use crate::api::{
+    LobbyFailureReason, OnCreateFailed, OnExitLobbyReq, OnJoinFailed, OnJoinLobbyReq, OnKickReq,
+    OnLobbyEntered, OnSendToClientReq, OnTransportRosterChanged,
+};
+use crate::protocol::P2PProtocol;
+use crate::state::{EasyP2PState, P2PData, P2PLobbyState};
+use crate::updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+
+// How long a rejected client gets to read the rejection before the host drops its connection.
+const REJECTED_CLIENT_KICK_DELAY: f64 = 1.;
+
+/// Rules the host applies to incoming players, and how long a client waits for a lobby.
+#[derive(Resource, Clone, Debug)]
+pub struct LobbyConfig {
+    pub join_timeout: Duration,
+    /// Maximum number of players including the host unless it is dedicated, unlimited when
+    /// `None`.
+    pub max_players: Option<usize>,
+    /// Clients announcing a different version are turned away by the host.
+    pub protocol_version: u32,
+    /// The host only runs the game for its clients and is not one of the players.
+    pub dedicated_host: bool,
+}
+
+impl Default for LobbyConfig {
+    fn default() -> Self {
+        Self {
+            join_timeout: Duration::from_secs(15),
+            max_players: None,
+            protocol_version: 0,
+            dedicated_host: false,
+        }
+    }
+}
+
+impl LobbyConfig {
+    pub fn with_join_timeout(mut self, join_timeout: Duration) -> Self {
+        self.join_timeout = join_timeout;
+        self
+    }
+
+    pub fn with_max_players(mut self, max_players: usize) -> Self {
+        self.max_players = Some(max_players);
+        self
+    }
+
+    pub fn with_protocol_version(mut self, protocol_version: u32) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    pub fn with_dedicated_host(mut self, dedicated_host: bool) -> Self {
+        self.dedicated_host = dedicated_host;
+        self
+    }
+
+    // Player slots taken by the host itself.
+    fn host_slots(&self) -> usize {
+        if self.dedicated_host { 0 } else { 1 }
+    }
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct JoinAttempt {
+    started_at: Option<f64>,
+}
+
+// Only accepted clients are part of the lobby, the host ignores anything else they send.
+#[derive(Resource, Default)]
+pub(crate) struct LobbyAdmission {
+    pub(crate) accepted: HashSet<ClientId>,
+    // Connected clients that did not say hello yet, and since when.
+    awaiting_hello: HashMap<ClientId, f64>,
+    rejected: Vec<(ClientId, LobbyFailureReason)>,
+    pending_kicks: Vec<(ClientId, f64)>,
+}
+
+impl LobbyAdmission {
+    pub(crate) fn admits(&self, client_id: ClientId) -> bool {
+        self.accepted.contains(&client_id)
+    }
+
+    fn is_rejected(&self, client_id: ClientId) -> bool {
+        self.rejected
+            .iter()
+            .any(|(rejected, _)| *rejected == client_id)
+            || self
+                .pending_kicks
+                .iter()
+                .any(|(rejected, _)| *rejected == client_id)
+    }
+
+    // Decided as soon as the hello is decoded, so that what the client sends right after it is
+    // let through.
+    pub(crate) fn greet(&mut self, config: &LobbyConfig, client_id: ClientId, version: u32) {
+        if self.admits(client_id) || self.is_rejected(client_id) {
+            return;
+        }
+        self.awaiting_hello.remove(&client_id);
+        let reason = if version != config.protocol_version {
+            LobbyFailureReason::VersionMismatch
+        } else if config
+            .max_players
+            .is_some_and(|max_players| self.accepted.len() + config.host_slots() >= max_players)
+        {
+            LobbyFailureReason::Full
+        } else {
+            self.accepted.insert(client_id);
+            return;
+        };
+        self.rejected.push((client_id, reason));
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+pub(crate) fn track_join_attempt<P: P2PProtocol>(
+    time: Res<Time<Real>>,
+    config: Res<LobbyConfig>,
+    mut attempt: ResMut<JoinAttempt>,
+    mut join_r: MessageReader<OnJoinLobbyReq>,
+    mut entered_r: MessageReader<OnLobbyEntered>,
+    mut join_failed_r: MessageReader<OnJoinFailed>,
+    mut create_failed_r: MessageReader<OnCreateFailed>,
+    mut exit_w: MessageWriter<OnExitLobbyReq>,
+    mut lobby_state: ResMut<NextState<P2PLobbyState>>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+) {
+    let now = time.elapsed_secs_f64();
+    for _ in join_r.read() {
+        attempt.started_at = Some(now);
+        lobby_state.set(P2PLobbyState::JoiningLobby);
+    }
+    if entered_r.read().count() > 0 {
+        attempt.started_at = None;
+    }
+
+    let mut join_failure = join_failed_r.read().last().map(|failure| failure.0.clone());
+    if join_failure.is_none()
+        && attempt
+            .started_at
+            .is_some_and(|started_at| now - started_at > config.join_timeout.as_secs_f64())
+    {
+        join_failure = Some(LobbyFailureReason::Timeout);
+    }
+    let create_failure = create_failed_r
+        .read()
+        .last()
+        .map(|failure| failure.0.clone());
+
+    if let Some(reason) = join_failure {
+        warn!("Failed to join lobby: {}", reason);
+        attempt.started_at = None;
+        updates.push(EasyP2PUpdate::JoinFailed { reason });
+        exit_w.write(OnExitLobbyReq);
+    } else if let Some(reason) = create_failure {
+        warn!("Failed to create lobby: {}", reason);
+        updates.push(EasyP2PUpdate::CreateFailed { reason });
+        exit_w.write(OnExitLobbyReq);
+    }
+}
+
+pub(crate) fn admit_clients<P: P2PProtocol>(
+    time: Res<Time<Real>>,
+    config: Res<LobbyConfig>,
+    state: Res<EasyP2PState<P::PlayerData>>,
+    mut admission: ResMut<LobbyAdmission>,
+    mut roster_r: MessageReader<OnTransportRosterChanged>,
+    mut w_send_client: MessageWriter<OnSendToClientReq<P>>,
+    mut kick_w: MessageWriter<OnKickReq>,
+) {
+    if !state.is_host {
+        admission.clear();
+        return;
+    }
+    let now = time.elapsed_secs_f64();
+    for OnTransportRosterChanged(list) in roster_r.read() {
+        admission
+            .accepted
+            .retain(|client_id| list.contains(client_id));
+        admission
+            .awaiting_hello
+            .retain(|client_id, _| list.contains(client_id));
+        for client_id in list {
+            if !admission.admits(*client_id) && !admission.is_rejected(*client_id) {
+                admission.awaiting_hello.entry(*client_id).or_insert(now);
+            }
+        }
+    }
+
+    // Clients that never say hello, such as builds from before it existed, get as long as a join
+    // may take.
+    let timeout = config.join_timeout.as_secs_f64();
+    let mut silent = Vec::new();
+    admission.awaiting_hello.retain(|client_id, since| {
+        if now - *since <= timeout {
+            return true;
+        }
+        silent.push((*client_id, LobbyFailureReason::VersionMismatch));
+        false
+    });
+    admission.rejected.extend(silent);
+
+    for (client_id, reason) in std::mem::take(&mut admission.rejected) {
+        info!("Rejecting client {}: {}", client_id, reason);
+        w_send_client.write(OnSendToClientReq(client_id, P2PData::HostReject(reason)));
+        admission
+            .pending_kicks
+            .push((client_id, now + REJECTED_CLIENT_KICK_DELAY));
+    }
+
+    admission.pending_kicks.retain(|(client_id, kick_at)| {
+        if *kick_at > now {
+            return true;
+        }
+        kick_w.write(OnKickReq(*client_id));
+        false
+    });
+}
@@ -0,0 +1,81 @@
+//! Address helpers shared by the transports that talk to peers directly over IP.
+
+use bevy::log::warn;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+/// Turns a lobby code into the address to connect to. Codes are `host:port`, the port may be
+/// left out when it is `default_port`.
+pub fn code_to_address(code: &str, default_port: u16) -> String {
+    let code = code.trim();
+    if code
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        code.to_string()
+    } else {
+        format!("{}:{}", code, default_port)
+    }
+}
+
+/// The address a host bound to `bound` can hand out to clients. When bound to every interface,
+/// that is the one its outbound traffic leaves from; without a route, only this machine can be
+/// reached.
+pub fn routable_address(bound: SocketAddr) -> SocketAddr {
+    if !bound.ip().is_unspecified() {
+        return bound;
+    }
+    // Connecting a UDP socket only picks a route, nothing is sent.
+    let ip = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket
+                .connect((Ipv4Addr::new(8, 8, 8, 8), 80))
+                .map(|_| socket)
+        })
+        .and_then(|socket| socket.local_addr())
+        .map(|address| address.ip());
+    match ip {
+        Ok(ip) => SocketAddr::new(ip, bound.port()),
+        Err(err) => {
+            let local = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), bound.port());
+            warn!(
+                "No route to other machines ({}), advertising {} which only works locally",
+                err, local
+            );
+            local
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_without_a_port_get_the_default_one() {
+        assert_eq!(code_to_address("192.168.1.5", 7878), "192.168.1.5:7878");
+        assert_eq!(code_to_address(" example.com ", 7878), "example.com:7878");
+    }
+
+    #[test]
+    fn codes_with_a_port_are_kept() {
+        assert_eq!(
+            code_to_address("192.168.1.5:9000", 7878),
+            "192.168.1.5:9000"
+        );
+        assert_eq!(code_to_address("[::1]:9000", 7878), "[::1]:9000");
+    }
+
+    #[test]
+    fn specific_bind_addresses_are_advertised_as_is() {
+        let bound: SocketAddr = "192.168.1.5:7878".parse().unwrap();
+        assert_eq!(routable_address(bound), bound);
+    }
+
+    #[test]
+    fn unspecified_bind_addresses_keep_their_port() {
+        let bound: SocketAddr = "0.0.0.0:7878".parse().unwrap();
+        let advertised = routable_address(bound);
+        assert!(!advertised.ip().is_unspecified());
+        assert_eq!(advertised.port(), 7878);
+    }
+}
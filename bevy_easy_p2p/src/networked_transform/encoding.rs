@@ -0,0 +1,575 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use super::interpolation::TransformSample;
+use super::{NetworkedPath, ReplicatedVelocity};
+use crate::ClientId;
+
+// Batches remembered on each side to decode deltas against.
+const HISTORY_LEN: usize = 32;
+// Quaternion components are quantized to that many steps per unit.
+const ROTATION_SCALE: f32 = 32767.;
+
+/// How the host sends `NetworkedTransform`s. Transforms are quantized, skipped while they stay
+/// within the thresholds of what was last sent, and delta encoded against the newest batch every
+/// client acknowledged.
+#[derive(Resource, Clone, Debug)]
+pub struct TransformReplication {
+    /// Time between two batches, `Duration::ZERO` sends every frame.
+    pub send_interval: Duration,
+    pub translation_threshold: f32,
+    /// In radians.
+    pub rotation_threshold: f32,
+    /// Unchanged transforms are still resent that often, so late joiners see parked entities.
+    pub keepalive: Duration,
+    pub quantization: TransformQuantization,
+}
+
+impl Default for TransformReplication {
+    fn default() -> Self {
+        Self {
+            send_interval: Duration::from_secs_f64(1. / 30.),
+            translation_threshold: 0.01,
+            rotation_threshold: 0.002,
+            keepalive: Duration::from_secs(1),
+            quantization: TransformQuantization::default(),
+        }
+    }
+}
+
+impl TransformReplication {
+    pub fn with_send_rate(mut self, per_second: f64) -> Self {
+        self.send_interval = Duration::from_secs_f64(1. / per_second);
+        self
+    }
+
+    pub fn with_thresholds(mut self, translation: f32, rotation: f32) -> Self {
+        self.translation_threshold = translation;
+        self.rotation_threshold = rotation;
+        self
+    }
+
+    pub fn with_keepalive(mut self, keepalive: Duration) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    pub fn with_quantization(mut self, quantization: TransformQuantization) -> Self {
+        self.quantization = quantization;
+        self
+    }
+}
+
+/// Precision transforms are sent with. `Planar` only sends x, y and the angle around z, clients
+/// keep their own z.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TransformQuantization {
+    Planar {
+        translation_step: f32,
+        angle_step: f32,
+    },
+    Full {
+        translation_step: f32,
+    },
+}
+
+impl Default for TransformQuantization {
+    fn default() -> Self {
+        Self::Planar {
+            translation_step: 0.01,
+            angle_step: 0.001,
+        }
+    }
+}
+
+fn quantize(value: f32, step: f32) -> i32 {
+    (value / step).round() as i32
+}
+
+impl TransformQuantization {
+    fn encode(&self, translation: Vec3, rotation: Quat, velocity: ReplicatedVelocity) -> Vec<i32> {
+        match *self {
+            Self::Planar {
+                translation_step,
+                angle_step,
+            } => {
+                let (angle, _, _) = rotation.to_euler(EulerRot::ZYX);
+                vec![
+                    quantize(translation.x, translation_step),
+                    quantize(translation.y, translation_step),
+                    quantize(angle, angle_step),
+                    quantize(velocity.linear.x, translation_step),
+                    quantize(velocity.linear.y, translation_step),
+                    quantize(velocity.angular, angle_step),
+                ]
+            }
+            Self::Full { translation_step } => translation
+                .to_array()
+                .into_iter()
+                .map(|value| quantize(value, translation_step))
+                .chain(
+                    rotation
+                        .to_array()
+                        .into_iter()
+                        .map(|value| quantize(value, 1. / ROTATION_SCALE)),
+                )
+                .chain(
+                    velocity
+                        .linear
+                        .to_array()
+                        .into_iter()
+                        .map(|value| quantize(value, translation_step)),
+                )
+                .chain([quantize(velocity.angular, 1. / ROTATION_SCALE)])
+                .collect(),
+        }
+    }
+
+    fn decode(&self, values: &[i32], time: f64, z: f32) -> Option<TransformSample> {
+        let value = |index: usize, step: f32| Some(*values.get(index)? as f32 * step);
+        Some(match *self {
+            Self::Planar {
+                translation_step,
+                angle_step,
+            } => {
+                let angle = value(2, angle_step)?;
+                TransformSample {
+                    time,
+                    translation: Vec3::new(
+                        value(0, translation_step)?,
+                        value(1, translation_step)?,
+                        z,
+                    ),
+                    rotation: Quat::from_rotation_z(angle.clamp(-PI, PI)),
+                    velocity: ReplicatedVelocity {
+                        linear: Vec3::new(
+                            value(3, translation_step)?,
+                            value(4, translation_step)?,
+                            0.,
+                        ),
+                        angular: value(5, angle_step)?,
+                    },
+                }
+            }
+            Self::Full { translation_step } => {
+                let rotation_step = 1. / ROTATION_SCALE;
+                TransformSample {
+                    time,
+                    translation: Vec3::new(
+                        value(0, translation_step)?,
+                        value(1, translation_step)?,
+                        value(2, translation_step)?,
+                    ),
+                    rotation: Quat::from_xyzw(
+                        value(3, rotation_step)?,
+                        value(4, rotation_step)?,
+                        value(5, rotation_step)?,
+                        value(6, rotation_step)?,
+                    )
+                    .normalize(),
+                    velocity: ReplicatedVelocity {
+                        linear: Vec3::new(
+                            value(7, translation_step)?,
+                            value(8, translation_step)?,
+                            value(9, translation_step)?,
+                        ),
+                        angular: value(10, rotation_step)?,
+                    },
+                }
+            }
+        })
+    }
+}
+
+// One entity in a batch; `delta` entries are relative to the baseline batch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TransformEntry(NetworkedPath, bool, Vec<i32>);
+
+#[derive(Message, Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct OnNetworkedTransformBatch {
+    seq: u32,
+    baseline: Option<u32>,
+    time: f64,
+    quantization: TransformQuantization,
+    entries: Vec<TransformEntry>,
+}
+
+impl OnNetworkedTransformBatch {
+    pub(crate) fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+type BatchState = HashMap<NetworkedPath, Vec<i32>>;
+
+// Batches known to both sides. A batch's state is its baseline's state with its entries applied,
+// which both the host and a client holding that baseline can rebuild.
+#[derive(Default)]
+struct BatchHistory(VecDeque<(u32, BatchState)>);
+
+impl BatchHistory {
+    fn get(&self, seq: u32) -> Option<&BatchState> {
+        self.0
+            .iter()
+            .find(|(batch, _)| *batch == seq)
+            .map(|(_, state)| state)
+    }
+
+    fn push(&mut self, seq: u32, state: BatchState) {
+        self.0.push_back((seq, state));
+        if self.0.len() > HISTORY_LEN {
+            self.0.pop_front();
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+struct SentTransform {
+    at: f64,
+    translation: Vec3,
+    rotation: Quat,
+}
+
+/// Host side bookkeeping for transform batches.
+#[derive(Resource, Default)]
+pub(crate) struct TransformSender {
+    seq: u32,
+    last_batch_at: Option<f64>,
+    history: BatchHistory,
+    sent: HashMap<NetworkedPath, SentTransform>,
+    acks: HashMap<ClientId, u32>,
+}
+
+impl TransformSender {
+    pub(crate) fn due(&self, config: &TransformReplication, now: f64) -> bool {
+        self.last_batch_at
+            .is_none_or(|last| now - last >= config.send_interval.as_secs_f64())
+    }
+
+    pub(crate) fn acknowledge(&mut self, client_id: ClientId, seq: u32) {
+        let ack = self.acks.entry(client_id).or_default();
+        *ack = (*ack).max(seq);
+    }
+
+    pub(crate) fn changed(
+        &self,
+        config: &TransformReplication,
+        path: &NetworkedPath,
+        transform: &Transform,
+        now: f64,
+    ) -> bool {
+        let Some(sent) = self.sent.get(path) else {
+            return true;
+        };
+        now - sent.at >= config.keepalive.as_secs_f64()
+            || sent.translation.distance(transform.translation) > config.translation_threshold
+            || sent.rotation.angle_between(transform.rotation) > config.rotation_threshold
+    }
+
+    /// Average velocity since `path` was last sent.
+    pub(crate) fn velocity(
+        &self,
+        path: &NetworkedPath,
+        transform: &Transform,
+        now: f64,
+    ) -> ReplicatedVelocity {
+        match self.sent.get(path) {
+            Some(sent) if now > sent.at => {
+                let elapsed = (now - sent.at) as f32;
+                let turn = transform.rotation * sent.rotation.inverse();
+                let (angle, _, _) = turn.to_euler(EulerRot::ZYX);
+                ReplicatedVelocity {
+                    linear: (transform.translation - sent.translation) / elapsed,
+                    angular: angle / elapsed,
+                }
+            }
+            _ => ReplicatedVelocity::default(),
+        }
+    }
+
+    /// Builds the next batch out of `(path, transform, velocity)`, using as baseline the newest
+    /// batch acknowledged by all of `clients`.
+    pub(crate) fn batch(
+        &mut self,
+        config: &TransformReplication,
+        clients: &[ClientId],
+        now: f64,
+        transforms: Vec<(NetworkedPath, Transform, ReplicatedVelocity)>,
+    ) -> OnNetworkedTransformBatch {
+        self.seq += 1;
+        self.last_batch_at = Some(now);
+        self.acks.retain(|client_id, _| clients.contains(client_id));
+        let baseline = clients
+            .iter()
+            .map(|client_id| self.acks.get(client_id).copied())
+            .min()
+            .flatten()
+            .filter(|seq| self.history.get(*seq).is_some());
+        let mut state = baseline
+            .and_then(|seq| self.history.get(seq))
+            .cloned()
+            .unwrap_or_default();
+        let mut entries = Vec::with_capacity(transforms.len());
+        for (path, transform, velocity) in transforms {
+            let values =
+                config
+                    .quantization
+                    .encode(transform.translation, transform.rotation, velocity);
+            let entry = match state.get(&path) {
+                Some(base) if base.len() == values.len() => {
+                    let delta = values.iter().zip(base).map(|(v, b)| v - b).collect();
+                    TransformEntry(path.clone(), true, delta)
+                }
+                _ => TransformEntry(path.clone(), false, values.clone()),
+            };
+            entries.push(entry);
+            self.sent.insert(
+                path.clone(),
+                SentTransform {
+                    at: now,
+                    translation: transform.translation,
+                    rotation: transform.rotation,
+                },
+            );
+            state.insert(path, values);
+        }
+        self.history.push(self.seq, state);
+        OnNetworkedTransformBatch {
+            seq: self.seq,
+            baseline,
+            time: now,
+            quantization: config.quantization,
+            entries,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Client side bookkeeping for transform batches.
+#[derive(Resource, Default)]
+pub(crate) struct TransformReceiver {
+    history: BatchHistory,
+    latest: Option<u32>,
+    unacked: bool,
+}
+
+impl TransformReceiver {
+    /// Decodes a batch into samples, `z_of` giving the current z of a path for planar batches.
+    /// Returns `None` when the batch refers to a baseline this client does not have.
+    pub(crate) fn receive(
+        &mut self,
+        batch: &OnNetworkedTransformBatch,
+        z_of: impl Fn(&NetworkedPath) -> f32,
+    ) -> Option<Vec<(NetworkedPath, TransformSample)>> {
+        let mut state = match batch.baseline {
+            Some(seq) => self.history.get(seq)?.clone(),
+            None => BatchState::default(),
+        };
+        let mut samples = Vec::with_capacity(batch.entries.len());
+        for TransformEntry(path, delta, values) in &batch.entries {
+            let values: Vec<i32> = if *delta {
+                let base = state.get(path)?;
+                values.iter().zip(base).map(|(d, b)| d + b).collect()
+            } else {
+                values.clone()
+            };
+            let sample = batch.quantization.decode(&values, batch.time, z_of(path))?;
+            samples.push((path.clone(), sample));
+            state.insert(path.clone(), values);
+        }
+        self.history.push(batch.seq, state);
+        if self.latest.is_none_or(|latest| batch.seq > latest) {
+            self.latest = Some(batch.seq);
+            self.unacked = true;
+        }
+        Some(samples)
+    }
+
+    /// The newest batch to acknowledge, once.
+    pub(crate) fn take_ack(&mut self) -> Option<u32> {
+        if !self.unacked {
+            return None;
+        }
+        self.unacked = false;
+        self.latest
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.history.clear();
+        self.latest = None;
+        self.unacked = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NetworkedId;
+
+    fn path(index: u16) -> NetworkedPath {
+        NetworkedPath(NetworkedId::Host, vec![index])
+    }
+
+    fn kart(x: f32, y: f32, angle: f32) -> (Transform, ReplicatedVelocity) {
+        let transform = Transform::from_xyz(x, y, 0.).with_rotation(Quat::from_rotation_z(angle));
+        let velocity = ReplicatedVelocity {
+            linear: Vec3::new(x, -y, 0.),
+            angular: angle,
+        };
+        (transform, velocity)
+    }
+
+    fn send(
+        sender: &mut TransformSender,
+        clients: &[ClientId],
+        now: f64,
+        karts: &[(Transform, ReplicatedVelocity)],
+    ) -> OnNetworkedTransformBatch {
+        let transforms = karts
+            .iter()
+            .enumerate()
+            .map(|(index, (transform, velocity))| (path(index as u16), *transform, *velocity))
+            .collect();
+        sender.batch(&TransformReplication::default(), clients, now, transforms)
+    }
+
+    fn assert_decoded(
+        samples: &[(NetworkedPath, TransformSample)],
+        karts: &[(Transform, ReplicatedVelocity)],
+    ) {
+        assert_eq!(samples.len(), karts.len());
+        for (index, (transform, velocity)) in karts.iter().enumerate() {
+            let (_, sample) = samples
+                .iter()
+                .find(|(sample_path, _)| *sample_path == path(index as u16))
+                .expect("every kart is in the batch");
+            assert!(sample.translation.distance(transform.translation) < 0.01);
+            assert!(sample.rotation.angle_between(transform.rotation) < 0.002);
+            assert!(sample.velocity.linear.distance(velocity.linear) < 0.01);
+            assert!((sample.velocity.angular - velocity.angular).abs() < 0.002);
+        }
+    }
+
+    #[test]
+    fn full_batch_round_trips() {
+        let mut sender = TransformSender::default();
+        let mut receiver = TransformReceiver::default();
+        let karts = [kart(1.5, -2.25, 0.5), kart(-40., 12.34, -2.)];
+
+        let batch = send(&mut sender, &[1], 0., &karts);
+        assert_eq!(batch.baseline, None);
+        assert!(
+            batch
+                .entries
+                .iter()
+                .all(|TransformEntry(_, delta, _)| !delta)
+        );
+
+        let samples = receiver.receive(&batch, |_| 0.).unwrap();
+        assert_decoded(&samples, &karts);
+        assert_eq!(receiver.take_ack(), Some(batch.seq));
+        assert_eq!(receiver.take_ack(), None);
+    }
+
+    #[test]
+    fn delta_batch_round_trips_against_the_acknowledged_baseline() {
+        let mut sender = TransformSender::default();
+        let mut receiver = TransformReceiver::default();
+
+        let first = send(
+            &mut sender,
+            &[1],
+            0.,
+            &[kart(1., 1., 0.1), kart(5., 5., 1.)],
+        );
+        receiver.receive(&first, |_| 0.).unwrap();
+        sender.acknowledge(1, receiver.take_ack().unwrap());
+
+        let karts = [kart(1.2, 0.9, 0.15), kart(5., 5., 1.), kart(9., 9., -1.)];
+        let second = send(&mut sender, &[1], 0.1, &karts);
+        assert_eq!(second.baseline, Some(first.seq));
+        let deltas: Vec<bool> = second
+            .entries
+            .iter()
+            .map(|TransformEntry(_, delta, _)| *delta)
+            .collect();
+        // The third kart is not in the baseline, so it is sent in full.
+        assert_eq!(deltas, [true, true, false]);
+
+        let samples = receiver.receive(&second, |_| 0.).unwrap();
+        assert_decoded(&samples, &karts);
+    }
+
+    #[test]
+    fn baseline_is_the_oldest_acknowledgement() {
+        let mut sender = TransformSender::default();
+        let first = send(&mut sender, &[1, 2], 0., &[kart(1., 1., 0.)]);
+        sender.acknowledge(1, first.seq);
+
+        // Client 2 has not acknowledged anything yet.
+        let second = send(&mut sender, &[1, 2], 0.1, &[kart(2., 2., 0.)]);
+        assert_eq!(second.baseline, None);
+
+        sender.acknowledge(2, first.seq);
+        sender.acknowledge(1, second.seq);
+        let third = send(&mut sender, &[1, 2], 0.2, &[kart(3., 3., 0.)]);
+        assert_eq!(third.baseline, Some(first.seq));
+    }
+
+    #[test]
+    fn missing_baseline_is_rejected() {
+        let mut sender = TransformSender::default();
+        let mut receiver = TransformReceiver::default();
+        let mut late_receiver = TransformReceiver::default();
+
+        let first = send(&mut sender, &[1], 0., &[kart(1., 1., 0.)]);
+        receiver.receive(&first, |_| 0.).unwrap();
+        sender.acknowledge(1, first.seq);
+        let second = send(&mut sender, &[1], 0.1, &[kart(2., 2., 0.)]);
+
+        assert!(late_receiver.receive(&second, |_| 0.).is_none());
+        assert_eq!(late_receiver.take_ack(), None);
+        assert!(receiver.receive(&second, |_| 0.).is_some());
+    }
+
+    #[test]
+    fn evicted_baselines_are_not_used() {
+        let mut sender = TransformSender::default();
+        let mut receiver = TransformReceiver::default();
+
+        let first = send(&mut sender, &[1], 0., &[kart(1., 1., 0.)]);
+        receiver.receive(&first, |_| 0.).unwrap();
+        sender.acknowledge(1, first.seq);
+
+        // The client stops acknowledging until its baseline falls out of the host's history.
+        let mut batch = first.clone();
+        for step in 1..=HISTORY_LEN {
+            batch = send(&mut sender, &[1], step as f64, &[kart(step as f32, 1., 0.)]);
+            assert_eq!(batch.baseline, Some(first.seq));
+        }
+        let full = send(&mut sender, &[1], 100., &[kart(0., 0., 0.)]);
+        assert_eq!(full.baseline, None);
+        assert!(receiver.receive(&full, |_| 0.).is_some());
+
+        // The same happens on the client.
+        for seq in 0..HISTORY_LEN as u32 {
+            let mut filler = full.clone();
+            filler.seq = full.seq + 1 + seq;
+            receiver.receive(&filler, |_| 0.).unwrap();
+        }
+        assert!(receiver.receive(&batch, |_| 0.).is_none());
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::ReplicatedVelocity;
+
+// How fast the host clock estimate follows samples that arrived slower than the fastest one.
+const CLOCK_RELAXATION: f64 = 0.01;
+// Snapshots kept per entity at most, whatever the delay.
+const MAX_SNAPSHOTS: usize = 64;
+
+/// How clients render `NetworkedTransform`s. With `enabled`, they are shown `delay` behind the
+/// host, interpolated between the two surrounding snapshots; when no newer snapshot arrived yet,
+/// they keep moving along the last replicated velocity for up to `max_extrapolation`.
+#[derive(Resource, Clone, Debug)]
+pub struct TransformInterpolation {
+    pub enabled: bool,
+    pub delay: Duration,
+    /// `Duration::ZERO` holds the last snapshot instead of extrapolating.
+    pub max_extrapolation: Duration,
+}
+
+impl Default for TransformInterpolation {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            delay: Duration::from_millis(100),
+            max_extrapolation: Duration::from_millis(200),
+        }
+    }
+}
+
+impl TransformInterpolation {
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..default()
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_max_extrapolation(mut self, max_extrapolation: Duration) -> Self {
+        self.max_extrapolation = max_extrapolation;
+        self
+    }
+}
+
+/// A replicated transform at a point of the host's clock.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub(crate) struct TransformSample {
+    pub(crate) time: f64,
+    pub(crate) translation: Vec3,
+    pub(crate) rotation: Quat,
+    pub(crate) velocity: ReplicatedVelocity,
+}
+
+// Samples received for one entity, oldest first.
+#[derive(Component, Default)]
+pub(crate) struct TransformSnapshots(VecDeque<TransformSample>);
+
+impl TransformSnapshots {
+    pub(crate) fn push(&mut self, sample: TransformSample) {
+        if self.0.back().is_some_and(|last| last.time >= sample.time) {
+            return;
+        }
+        self.0.push_back(sample);
+        if self.0.len() > MAX_SNAPSHOTS {
+            self.0.pop_front();
+        }
+    }
+
+    fn sample_at(
+        &mut self,
+        time: f64,
+        max_extrapolation: f64,
+    ) -> Option<(Vec3, Quat, ReplicatedVelocity)> {
+        // Keep a single snapshot older than `time` to interpolate from.
+        while self.0.len() > 1 && self.0[1].time <= time {
+            self.0.pop_front();
+        }
+        let from = self.0.front()?;
+        match self.0.get(1) {
+            Some(to) if from.time <= time => {
+                let t = ((time - from.time) / (to.time - from.time)) as f32;
+                Some((
+                    from.translation.lerp(to.translation, t),
+                    from.rotation.slerp(to.rotation, t),
+                    ReplicatedVelocity {
+                        linear: from.velocity.linear.lerp(to.velocity.linear, t),
+                        angular: from.velocity.angular.lerp(to.velocity.angular, t),
+                    },
+                ))
+            }
+            // Render time is before anything we have.
+            Some(_) => Some((from.translation, from.rotation, from.velocity)),
+            // Dead reckoning along the last known velocity.
+            None => {
+                let ahead = (time - from.time).clamp(0., max_extrapolation) as f32;
+                Some((
+                    from.translation + from.velocity.linear * ahead,
+                    Quat::from_rotation_z(from.velocity.angular * ahead) * from.rotation,
+                    from.velocity,
+                ))
+            }
+        }
+    }
+}
+
+// Offset between the local clock and the host's, as seen through the fastest sample so far.
+#[derive(Resource, Default)]
+pub(crate) struct HostClock {
+    offset: Option<f64>,
+}
+
+impl HostClock {
+    pub(crate) fn observe(&mut self, local_time: f64, host_time: f64) {
+        let sample = local_time - host_time;
+        self.offset = Some(match self.offset {
+            Some(offset) if sample > offset => offset + (sample - offset) * CLOCK_RELAXATION,
+            _ => sample,
+        });
+    }
+
+    fn host_time(&self, local_time: f64) -> Option<f64> {
+        Some(local_time - self.offset?)
+    }
+}
+
+pub(crate) fn interpolate_transforms(
+    config: Res<TransformInterpolation>,
+    clock: Res<HostClock>,
+    time: Res<Time<Real>>,
+    mut transforms: Query<(
+        &mut Transform,
+        &mut TransformSnapshots,
+        Option<&mut ReplicatedVelocity>,
+    )>,
+) {
+    if !config.enabled {
+        return;
+    }
+    let Some(host_now) = clock.host_time(time.elapsed_secs_f64()) else {
+        return;
+    };
+    let render_time = host_now - config.delay.as_secs_f64();
+    let max_extrapolation = config.max_extrapolation.as_secs_f64();
+    for (mut transform, mut snapshots, replicated_velocity) in transforms.iter_mut() {
+        let Some((translation, rotation, velocity)) =
+            snapshots.sample_at(render_time, max_extrapolation)
+        else {
+            continue;
+        };
+        transform.translation = translation;
+        transform.rotation = rotation;
+        if let Some(mut replicated_velocity) = replicated_velocity {
+            *replicated_velocity = velocity;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Moving right at 10 units per second.
+    fn sample(time: f64, x: f32) -> TransformSample {
+        TransformSample {
+            time,
+            translation: Vec3::new(x, 0., 0.),
+            rotation: Quat::IDENTITY,
+            velocity: ReplicatedVelocity {
+                linear: Vec3::new(10., 0., 0.),
+                angular: 0.,
+            },
+        }
+    }
+
+    fn snapshots(samples: &[TransformSample]) -> TransformSnapshots {
+        let mut snapshots = TransformSnapshots::default();
+        for sample in samples {
+            snapshots.push(*sample);
+        }
+        snapshots
+    }
+
+    #[test]
+    fn interpolates_between_surrounding_snapshots() {
+        let mut snapshots = snapshots(&[sample(1., 0.), sample(1.1, 1.), sample(1.2, 2.)]);
+        let (translation, _, _) = snapshots.sample_at(1.15, 0.2).unwrap();
+        assert!((translation.x - 1.5).abs() < 1e-4);
+        // Snapshots before the one interpolated from are no longer needed.
+        assert_eq!(snapshots.0.len(), 2);
+    }
+
+    #[test]
+    fn extrapolation_is_capped() {
+        let mut snapshots = snapshots(&[sample(1., 0.), sample(1.1, 1.)]);
+        let (translation, _, _) = snapshots.sample_at(1.15, 0.2).unwrap();
+        assert!((translation.x - 1.5).abs() < 1e-4);
+        let (translation, _, _) = snapshots.sample_at(5., 0.2).unwrap();
+        assert!((translation.x - 3.).abs() < 1e-4);
+        let (translation, _, _) = snapshots.sample_at(5., 0.).unwrap();
+        assert!((translation.x - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn holds_the_first_snapshot_before_it() {
+        let mut snapshots = snapshots(&[sample(1., 4.), sample(1.1, 5.)]);
+        let (translation, _, _) = snapshots.sample_at(0.5, 0.2).unwrap();
+        assert_eq!(translation.x, 4.);
+        assert_eq!(snapshots.0.len(), 2);
+    }
+
+    #[test]
+    fn nothing_to_sample_without_snapshots() {
+        assert!(TransformSnapshots::default().sample_at(1., 0.2).is_none());
+    }
+}
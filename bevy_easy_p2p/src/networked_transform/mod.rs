@@ -1,146 +1,246 @@
-use crate::{EasyP2P, NetworkedEventsExt, NetworkedId, P2PTransport};
+use crate::api::{OnInternalClientData, OnSendToHostReq};
+use crate::state::P2PData;
+use crate::{
+    ClientId, EasyP2P, NetworkedEntity, NetworkedEventsExt, NetworkedId, P2PLobbyState, P2PProtocol,
+};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-pub struct NetworkedTransformPlugin<
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(std::marker::PhantomData<(T, PlayerData, PlayerInputData, Instantiations)>);
-
-impl<T, PlayerData, PlayerInputData, Instantiations> Default
-    for NetworkedTransformPlugin<T, PlayerData, PlayerInputData, Instantiations>
-where
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+mod encoding;
+mod interpolation;
+
+use encoding::{OnNetworkedTransformBatch, TransformReceiver, TransformSender};
+pub use encoding::{TransformQuantization, TransformReplication};
+pub use interpolation::TransformInterpolation;
+use interpolation::{HostClock, TransformSnapshots};
+
+// Networked transforms as clients see them, interpolated or simulated locally.
+type ReplicatedTransform = (
+    Entity,
+    &'static mut Transform,
+    Option<&'static mut TransformSnapshots>,
+    Option<&'static mut ReplicatedVelocity>,
+    Has<RemoteTransform>,
+);
+
+pub struct NetworkedTransformPlugin<P: P2PProtocol>(std::marker::PhantomData<P>);
+
+impl<P: P2PProtocol> Default for NetworkedTransformPlugin<P> {
     fn default() -> Self {
         Self(std::marker::PhantomData)
     }
 }
 
-impl<T, PlayerData, PlayerInputData, Instantiations> Plugin
-    for NetworkedTransformPlugin<T, PlayerData, PlayerInputData, Instantiations>
-where
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+impl<P: P2PProtocol> Plugin for NetworkedTransformPlugin<P> {
     fn build(&self, app: &mut App) {
-        app.init_networked_event::<OnNetworkedTransformUpdate>()
+        app.init_networked_event::<OnNetworkedTransformBatch>()
+            .init_resource::<TransformReplication>()
+            .init_resource::<TransformInterpolation>()
+            .init_resource::<HostClock>()
+            .init_resource::<TransformSender>()
+            .init_resource::<TransformReceiver>()
+            .add_systems(
+                OnEnter(P2PLobbyState::OutOfLobby),
+                clear_transform_replication,
+            )
             .add_systems(
                 Update,
                 (
-                    networked_transform::<T, PlayerData, PlayerInputData, Instantiations>,
-                    apply_networked_transform::<T, PlayerData, PlayerInputData, Instantiations>,
-                ),
+                    (receive_transform_acks::<P>, networked_transform::<P>).chain(),
+                    (
+                        apply_networked_transform::<P>,
+                        interpolation::interpolate_transforms,
+                    )
+                        .chain(),
+                )
+                    .in_set(NetworkedTransformSystemSet),
             );
     }
 }
 
+/// Sending transforms on the host and applying them on clients. Write `ReplicatedVelocity`
+/// before it on the host, read what clients received after it.
+#[derive(SystemSet, Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub struct NetworkedTransformSystemSet;
+
+/// Replicates the local `Transform` from the host to clients. Works on the `NetworkedEntity`
+/// itself as well as on any of its descendants, which are told apart by their position among
+/// their siblings, so children have to be spawned in the same order on every peer.
 #[derive(Component)]
 pub struct NetworkedTransform;
 
-#[derive(Message, Clone, Debug, Serialize, Deserialize)]
-struct OnNetworkedTransformUpdate(NetworkedId, (Vec3, Quat));
-
-fn networked_transform<
-    'w,
-    's,
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(
-    easy: EasyP2P<'w, 's, T, PlayerData, PlayerInputData, Instantiations>,
-    mut transforms: Query<(Entity, &mut Transform), With<NetworkedTransform>>,
-    mut events_w: MessageWriter<OnNetworkedTransformUpdate>,
+/// Velocity sent along a `NetworkedTransform`. On the host, keep it up to date from the physics
+/// engine and it is replicated as is instead of being estimated from the last sent transforms;
+/// on clients, it is written with the interpolated velocity, for dead reckoning or to feed
+/// kinematic bodies.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplicatedVelocity {
+    pub linear: Vec3,
+    /// Around z, in radians per second.
+    pub angular: f32,
+}
+
+/// Inserted on clients on every `NetworkedTransform` driven by the host, so the game can stop
+/// simulating it locally.
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct RemoteTransform;
+
+// A networked entity and the child indices leading from it to the transform.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) struct NetworkedPath(NetworkedId, Vec<u16>);
+
+#[derive(SystemParam)]
+struct NetworkedPaths<'w, 's> {
+    parents: Query<'w, 's, &'static ChildOf>,
+    children: Query<'w, 's, &'static Children>,
+    networked: Query<'w, 's, &'static NetworkedEntity>,
+}
+
+impl NetworkedPaths<'_, '_> {
+    fn path(&self, entity: Entity) -> Option<NetworkedPath> {
+        let mut indices = Vec::new();
+        let mut current = entity;
+        loop {
+            if let Ok(networked) = self.networked.get(current) {
+                indices.reverse();
+                return Some(NetworkedPath(networked.id(), indices));
+            }
+            let parent = self.parents.get(current).ok()?.parent();
+            let index = self
+                .children
+                .get(parent)
+                .ok()?
+                .iter()
+                .position(|child| child == current)?;
+            indices.push(index as u16);
+            current = parent;
+        }
+    }
+}
+
+fn networked_transform<'w, 's, P: P2PProtocol>(
+    easy: EasyP2P<'w, 's, P>,
+    paths: NetworkedPaths,
+    time: Res<Time<Real>>,
+    config: Res<TransformReplication>,
+    mut sender: ResMut<TransformSender>,
+    transforms: Query<(Entity, &Transform, Option<&ReplicatedVelocity>), With<NetworkedTransform>>,
+    mut batch_w: MessageWriter<OnNetworkedTransformBatch>,
 ) {
-    if !easy.is_host() {
+    let now = time.elapsed_secs_f64();
+    if !easy.is_host() || !sender.due(&config, now) {
         return;
     }
-    for (entity, transform) in transforms.iter_mut() {
-        let Some(networked_id) = easy.get_closest_networked_id(entity) else {
-            continue;
-        };
-        events_w.write(OnNetworkedTransformUpdate(
-            networked_id,
-            (transform.translation, transform.rotation),
-        ));
+    let changed: Vec<_> = transforms
+        .iter()
+        .filter_map(|(entity, transform, replicated_velocity)| {
+            let path = paths.path(entity)?;
+            if !sender.changed(&config, &path, transform, now) {
+                return None;
+            }
+            let velocity = replicated_velocity
+                .copied()
+                .unwrap_or_else(|| sender.velocity(&path, transform, now));
+            Some((path, *transform, velocity))
+        })
+        .collect();
+    let clients: Vec<ClientId> = easy
+        .get_players()
+        .iter()
+        .filter_map(|player| match player.id {
+            NetworkedId::ClientId(client_id) => Some(client_id),
+            NetworkedId::Host => None,
+        })
+        .collect();
+    let batch = sender.batch(&config, &clients, now, changed);
+    if !batch.is_empty() {
+        batch_w.write(batch);
     }
 }
 
-fn apply_networked_transform<
-    'w,
-    's,
-    T: P2PTransport,
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(
-    easy: EasyP2P<'w, 's, T, PlayerData, PlayerInputData, Instantiations>,
-    mut transforms: Query<(Entity, &mut Transform), With<NetworkedTransform>>,
-    mut events_r: MessageReader<OnNetworkedTransformUpdate>,
+fn receive_transform_acks<P: P2PProtocol>(
+    mut client_data_r: MessageReader<OnInternalClientData<P>>,
+    mut sender: ResMut<TransformSender>,
 ) {
-    if easy.is_host() {
+    for OnInternalClientData(client_id, data) in client_data_r.read() {
+        if let P2PData::TransformAck(seq) = data {
+            sender.acknowledge(*client_id, *seq);
+        }
+    }
+}
+
+fn apply_networked_transform<'w, 's, P: P2PProtocol>(
+    mut commands: Commands,
+    easy: EasyP2P<'w, 's, P>,
+    paths: NetworkedPaths,
+    config: Res<TransformInterpolation>,
+    time: Res<Time<Real>>,
+    mut clock: ResMut<HostClock>,
+    mut receiver: ResMut<TransformReceiver>,
+    mut transforms: Query<ReplicatedTransform, With<NetworkedTransform>>,
+    mut batch_r: MessageReader<OnNetworkedTransformBatch>,
+    mut send_host_w: MessageWriter<OnSendToHostReq<P>>,
+) {
+    if easy.is_host() || batch_r.is_empty() {
         return;
     }
-    for OnNetworkedTransformUpdate(networked_id, (new_translation, new_rotation)) in events_r.read()
-    {
-        for (entity, mut transform) in transforms.iter_mut() {
-            let closest_networked_id = easy.get_closest_networked_id(entity);
-            if closest_networked_id == Some(networked_id.clone()) {
-                transform.translation = *new_translation;
-                transform.rotation = *new_rotation;
+    let entities: HashMap<NetworkedPath, Entity> = transforms
+        .iter()
+        .filter_map(|(entity, ..)| Some((paths.path(entity)?, entity)))
+        .collect();
+    let now = time.elapsed_secs_f64();
+    for batch in batch_r.read() {
+        let z_of = |path: &NetworkedPath| {
+            let entity = entities.get(path)?;
+            Some(transforms.get(*entity).ok()?.1.translation.z)
+        };
+        let Some(samples) = receiver.receive(batch, |path| z_of(path).unwrap_or_default()) else {
+            continue;
+        };
+        clock.observe(now, batch.time());
+        for (path, sample) in samples {
+            let Some(entity) = entities.get(&path) else {
+                continue;
+            };
+            let Ok((_, mut transform, snapshots, velocity, remote)) = transforms.get_mut(*entity)
+            else {
+                continue;
+            };
+            if !remote {
+                commands.entity(*entity).insert(RemoteTransform);
+            }
+            if !config.enabled {
+                transform.translation = sample.translation;
+                transform.rotation = sample.rotation;
+                if let Some(mut velocity) = velocity {
+                    *velocity = sample.velocity;
+                }
+                continue;
+            }
+            match snapshots {
+                Some(mut snapshots) => snapshots.push(sample),
+                None => {
+                    let mut snapshots = TransformSnapshots::default();
+                    snapshots.push(sample);
+                    commands.entity(*entity).insert(snapshots);
+                }
             }
         }
     }
+    if let Some(seq) = receiver.take_ack() {
+        send_host_w.write(OnSendToHostReq(P2PData::TransformAck(seq)));
+    }
+}
+
+fn clear_transform_replication(
+    mut sender: ResMut<TransformSender>,
+    mut receiver: ResMut<TransformReceiver>,
+    mut clock: ResMut<HostClock>,
+) {
+    sender.clear();
+    receiver.clear();
+    // The next host started its clock at another time.
+    *clock = HostClock::default();
 }
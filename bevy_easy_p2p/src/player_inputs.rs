@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+use crate::api::OnRosterUpdate;
+use crate::protocol::P2PProtocol;
+use crate::state::NetworkedId;
+
+/// Latest input of every player, for fixed-step systems on the host. Inputs received between two
+/// fixed steps are applied at the start of the next one and stay held until a newer input
+/// arrives, so each step sees exactly one input per player. The previous step's input is kept
+/// for edge detection.
+#[derive(Resource)]
+pub struct PlayerInputs<P: P2PProtocol> {
+    received: HashMap<NetworkedId, P::PlayerInputData>,
+    current: HashMap<NetworkedId, P::PlayerInputData>,
+    previous: HashMap<NetworkedId, P::PlayerInputData>,
+}
+
+impl<P: P2PProtocol> Default for PlayerInputs<P> {
+    fn default() -> Self {
+        Self {
+            received: HashMap::new(),
+            current: HashMap::new(),
+            previous: HashMap::new(),
+        }
+    }
+}
+
+impl<P: P2PProtocol> PlayerInputs<P> {
+    pub fn get(&self, player: NetworkedId) -> Option<&P::PlayerInputData> {
+        self.current.get(&player)
+    }
+
+    pub fn previous(&self, player: NetworkedId) -> Option<&P::PlayerInputData> {
+        self.previous.get(&player)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&NetworkedId, &P::PlayerInputData)> {
+        self.current.iter()
+    }
+
+    /// Whether `pressed` holds for this step's input but did not for the previous one.
+    pub fn just_pressed(
+        &self,
+        player: NetworkedId,
+        pressed: impl Fn(&P::PlayerInputData) -> bool,
+    ) -> bool {
+        self.get(player).is_some_and(&pressed) && !self.previous(player).is_some_and(&pressed)
+    }
+
+    /// Whether `pressed` held for the previous step's input but no longer does.
+    pub fn just_released(
+        &self,
+        player: NetworkedId,
+        pressed: impl Fn(&P::PlayerInputData) -> bool,
+    ) -> bool {
+        self.previous(player).is_some_and(&pressed) && !self.get(player).is_some_and(&pressed)
+    }
+
+    pub(crate) fn receive(&mut self, player: NetworkedId, input: P::PlayerInputData) {
+        self.received.insert(player, input);
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current.clone();
+        self.current.extend(self.received.drain());
+    }
+
+    fn retain(&mut self, keep: impl Fn(&NetworkedId) -> bool) {
+        self.received.retain(|player, _| keep(player));
+        self.current.retain(|player, _| keep(player));
+        self.previous.retain(|player, _| keep(player));
+    }
+
+    fn clear(&mut self) {
+        self.received.clear();
+        self.current.clear();
+        self.previous.clear();
+    }
+}
+
+pub(crate) fn advance_player_inputs<P: P2PProtocol>(mut inputs: ResMut<PlayerInputs<P>>) {
+    inputs.advance();
+}
+
+pub(crate) fn forget_departed_players<P: P2PProtocol>(
+    mut roster_r: MessageReader<OnRosterUpdate<P>>,
+    mut inputs: ResMut<PlayerInputs<P>>,
+) {
+    for OnRosterUpdate(players) in roster_r.read() {
+        inputs.retain(|id| players.iter().any(|player| player.id == *id));
+    }
+}
+
+pub(crate) fn clear_player_inputs<P: P2PProtocol>(mut inputs: ResMut<PlayerInputs<P>>) {
+    inputs.clear();
+}
@@ -1,5 +1,9 @@
 pub use crate::{
-    EasyP2P, EasyP2PPlugin, EasyP2PState, EasyP2PSystemSet, EasyP2PTransportIo, EasyP2PUpdate,
-    ExitReason, NetworkedEntity, NetworkedEventsExt, NetworkedId, P2PLobbyState, PingUpdate,
-    networked_transform::NetworkedTransform,
+    ClientValidation, EasyP2P, EasyP2PPlugin, EasyP2PState, EasyP2PSystemSet, EasyP2PUpdate,
+    ExitReason, HeartbeatConfig, InputBufferConfig, InstantiateOptions, LinkConditions,
+    LobbyConfig, LobbyFailureReason, NetworkConditions, NetworkDebugOverlayPlugin, NetworkedEntity,
+    NetworkedEventsExt, NetworkedId, NetworkedPrefabsExt, P2PLobbyState, P2PProtocol, P2PTransport,
+    PingUpdate, PlayerInputs, Prefab, PrefabSpawn, SendTarget, TransportError, TransportEvent,
+    Validation,
+    networked_transform::{NetworkedTransform, RemoteTransform, ReplicatedVelocity},
 };
@@ -0,0 +1,54 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// The payload types a game exchanges through EasyP2P, bundled so that plugins, system params
+/// and updates take a single type parameter. Usually implemented on an empty marker type:
+///
+/// ```ignore
+/// #[derive(Clone, Debug)]
+/// struct MyProtocol;
+///
+/// impl P2PProtocol for MyProtocol {
+///     type PlayerData = MyPlayerData;
+///     type PlayerInputData = MyInput;
+///     type Instantiations = MySpawns;
+/// }
+/// ```
+pub trait P2PProtocol: Clone + core::fmt::Debug + Send + Sync + 'static {
+    /// Per-player lobby data (name, color...), owned by each player and shared by the host.
+    type PlayerData: Serialize
+        + DeserializeOwned
+        + Clone
+        + Send
+        + Sync
+        + core::fmt::Debug
+        + Default
+        + PartialEq
+        + 'static;
+    /// Inputs clients send to the host every frame.
+    type PlayerInputData: Serialize
+        + DeserializeOwned
+        + Clone
+        + Send
+        + Sync
+        + core::fmt::Debug
+        + 'static;
+    /// Entities the host can spawn on every peer.
+    type Instantiations: Serialize
+        + DeserializeOwned
+        + Clone
+        + Send
+        + Sync
+        + core::fmt::Debug
+        + 'static;
+}
+
+// Stand-in used to encode the variants that carry none of the game's types.
+#[derive(Clone, Debug)]
+pub(crate) struct UnitProtocol;
+
+impl P2PProtocol for UnitProtocol {
+    type PlayerData = ();
+    type PlayerInputData = ();
+    type Instantiations = ();
+}
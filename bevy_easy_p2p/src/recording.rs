@@ -0,0 +1,280 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::ClientId;
+use crate::api::{
+    ExitReason, OnLobbyCreated, OnLobbyEntered, OnLobbyExit, OnLobbyJoined,
+    OnTransportIncomingFromClient, OnTransportIncomingFromHost,
+};
+
+/// Where a recorded payload was going, or where it came from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RecordedRoute {
+    ToHost,
+    ToAll,
+    ToClient(ClientId),
+    RelayToAllExcept(ClientId),
+    FromHost,
+    FromClient(ClientId),
+    LobbyCreated,
+    LobbyJoined,
+    LobbyEntered,
+    LobbyExited(ExitReason),
+}
+
+impl RecordedRoute {
+    pub fn is_incoming(&self) -> bool {
+        !matches!(
+            self,
+            RecordedRoute::ToHost
+                | RecordedRoute::ToAll
+                | RecordedRoute::ToClient(_)
+                | RecordedRoute::RelayToAllExcept(_)
+        )
+    }
+}
+
+/// One line of a recording. `time` is in seconds since the recording started; for lobby
+/// lifecycle routes `payload` holds the lobby code.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecordedPayload {
+    pub time: f64,
+    pub route: RecordedRoute,
+    pub payload: String,
+}
+
+/// Keeps every payload that crosses the transport in memory while recording, which works in
+/// browsers too. Captures are exported as JSON lines, the format `NetworkReplay` reads.
+#[derive(Resource, Default)]
+pub struct NetworkRecorder {
+    recording: bool,
+    entries: Vec<RecordedPayload>,
+    started_at: Option<f64>,
+}
+
+impl NetworkRecorder {
+    /// Starts a new capture, dropping the previous one.
+    pub fn start(&mut self) {
+        self.recording = true;
+        self.entries.clear();
+        self.started_at = None;
+        info!("Recording network traffic");
+    }
+
+    /// Stops recording. The capture stays available until the next `start`.
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn entries(&self) -> &[RecordedPayload] {
+        &self.entries
+    }
+
+    pub fn take_entries(&mut self) -> Vec<RecordedPayload> {
+        std::mem::take(&mut self.entries)
+    }
+
+    pub fn to_json_lines(&self) -> String {
+        self.entries
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    /// Writes the capture to a file, where there is a file system.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json_lines())
+    }
+
+    pub(crate) fn record(&mut self, now: f64, route: RecordedRoute, payload: &str) {
+        if !self.recording {
+            return;
+        }
+        let started_at = *self.started_at.get_or_insert(now);
+        self.entries.push(RecordedPayload {
+            time: now - started_at,
+            route,
+            payload: payload.to_string(),
+        });
+    }
+}
+
+/// Feeds a recording back into the plugin as if it came from the transport. Outgoing entries
+/// are skipped; incoming payloads and lobby events are replayed at their recorded times.
+#[derive(Resource)]
+pub struct NetworkReplay {
+    entries: VecDeque<RecordedPayload>,
+    started_at: Option<f64>,
+    pub speed: f64,
+}
+
+impl NetworkReplay {
+    pub fn new(entries: impl IntoIterator<Item = RecordedPayload>) -> Self {
+        Self {
+            entries: entries
+                .into_iter()
+                .filter(|entry| entry.route.is_incoming())
+                .collect(),
+            started_at: None,
+            speed: 1.,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::from_json_lines(&std::fs::read_to_string(path)?)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn from_json_lines(lines: &str) -> serde_json::Result<Self> {
+        let entries = lines
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<RecordedPayload>)
+            .collect::<serde_json::Result<Vec<_>>>()?;
+        Ok(Self::new(entries))
+    }
+
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn take_due(&mut self, now: f64) -> Vec<RecordedPayload> {
+        let started_at = *self.started_at.get_or_insert(now);
+        let elapsed = (now - started_at) * self.speed;
+        let mut due = Vec::new();
+        while self
+            .entries
+            .front()
+            .is_some_and(|entry| entry.time <= elapsed)
+        {
+            due.extend(self.entries.pop_front());
+        }
+        due
+    }
+}
+
+pub(crate) fn replay_recording(
+    time: Res<Time<Real>>,
+    replay: Option<ResMut<NetworkReplay>>,
+    mut created_w: MessageWriter<OnLobbyCreated>,
+    mut joined_w: MessageWriter<OnLobbyJoined>,
+    mut entered_w: MessageWriter<OnLobbyEntered>,
+    mut exit_w: MessageWriter<OnLobbyExit>,
+    mut from_host_w: MessageWriter<OnTransportIncomingFromHost>,
+    mut from_client_w: MessageWriter<OnTransportIncomingFromClient>,
+) {
+    let Some(mut replay) = replay else {
+        return;
+    };
+    for entry in replay.take_due(time.elapsed_secs_f64()) {
+        match entry.route {
+            RecordedRoute::FromHost => {
+                from_host_w.write(OnTransportIncomingFromHost(entry.payload));
+            }
+            RecordedRoute::FromClient(client_id) => {
+                from_client_w.write(OnTransportIncomingFromClient(client_id, entry.payload));
+            }
+            RecordedRoute::LobbyCreated => {
+                created_w.write(OnLobbyCreated(entry.payload));
+            }
+            RecordedRoute::LobbyJoined => {
+                joined_w.write(OnLobbyJoined(entry.payload));
+            }
+            RecordedRoute::LobbyEntered => {
+                entered_w.write(OnLobbyEntered(entry.payload));
+            }
+            RecordedRoute::LobbyExited(reason) => {
+                exit_w.write(OnLobbyExit(reason));
+            }
+            RecordedRoute::ToHost
+            | RecordedRoute::ToAll
+            | RecordedRoute::ToClient(_)
+            | RecordedRoute::RelayToAllExcept(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture() -> NetworkRecorder {
+        let mut recorder = NetworkRecorder::default();
+        recorder.record(1., RecordedRoute::ToHost, "dropped");
+        recorder.start();
+        recorder.record(10., RecordedRoute::LobbyJoined, "CODE");
+        recorder.record(10.5, RecordedRoute::ToHost, "\"hello\"");
+        recorder.record(11., RecordedRoute::FromHost, "\"welcome\"");
+        recorder.record(11.5, RecordedRoute::ToClient(3), "\"hi\"");
+        recorder.record(12., RecordedRoute::FromClient(3), "{\"a\":1}");
+        recorder.stop();
+        recorder.record(13., RecordedRoute::FromHost, "dropped");
+        recorder
+    }
+
+    #[test]
+    fn only_records_while_recording() {
+        let recorder = capture();
+        assert_eq!(recorder.entries().len(), 5);
+        assert_eq!(recorder.entries()[0].time, 0.);
+        assert_eq!(recorder.entries()[4].time, 2.);
+    }
+
+    #[test]
+    fn replay_reads_back_incoming_routes() {
+        let mut replay = NetworkReplay::from_json_lines(&capture().to_json_lines()).unwrap();
+        assert_eq!(replay.take_due(0.).len(), 1);
+        let due = replay.take_due(100.);
+        let routes: Vec<_> = due.iter().map(|entry| entry.route.clone()).collect();
+        assert_eq!(
+            routes,
+            vec![RecordedRoute::FromHost, RecordedRoute::FromClient(3)]
+        );
+        assert_eq!(due[1].payload, "{\"a\":1}");
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn replay_follows_recorded_times() {
+        let mut replay = NetworkReplay::from_json_lines(&capture().to_json_lines()).unwrap();
+        // The replay clock starts on the first call.
+        assert_eq!(replay.take_due(50.).len(), 1);
+        assert!(replay.take_due(50.9).is_empty());
+        assert_eq!(replay.take_due(51.).len(), 1);
+        assert_eq!(replay.take_due(52.).len(), 1);
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn replay_speed_scales_time() {
+        let mut replay = NetworkReplay::from_json_lines(&capture().to_json_lines())
+            .unwrap()
+            .with_speed(2.);
+        assert_eq!(replay.take_due(0.).len(), 1);
+        assert_eq!(replay.take_due(0.5).len(), 1);
+        assert!(replay.take_due(0.9).is_empty());
+        assert_eq!(replay.take_due(1.).len(), 1);
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(NetworkReplay::from_json_lines("not json\n").is_err());
+        assert!(
+            NetworkReplay::from_json_lines("\n\n")
+                .unwrap()
+                .is_finished()
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+use crate::ClientId;
+
+/// Artificial network conditions applied to one direction of traffic.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkConditions {
+    pub latency: Duration,
+    pub jitter: Duration,
+    /// Chance between 0 and 1 that a packet is dropped.
+    pub loss: f32,
+    /// Chance between 0 and 1 that a packet is delivered twice.
+    pub duplication: f32,
+    /// Chance between 0 and 1 that a packet is held back and overtaken by later ones.
+    pub reordering: f32,
+}
+
+impl LinkConditions {
+    pub fn new(latency: Duration, jitter: Duration) -> Self {
+        Self {
+            latency,
+            jitter,
+            ..default()
+        }
+    }
+
+    pub fn with_loss(mut self, loss: f32) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn with_duplication(mut self, duplication: f32) -> Self {
+        self.duplication = duplication;
+        self
+    }
+
+    pub fn with_reordering(mut self, reordering: f32) -> Self {
+        self.reordering = reordering;
+        self
+    }
+}
+
+/// Runtime switch for the network condition simulator. Every payload going through
+/// the transport is delayed, dropped, duplicated or reordered according to these settings
+/// while `enabled` is set, whatever transport is in use.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct NetworkConditions {
+    pub enabled: bool,
+    pub outgoing: LinkConditions,
+    pub incoming: LinkConditions,
+}
+
+impl NetworkConditions {
+    pub fn new(outgoing: LinkConditions, incoming: LinkConditions) -> Self {
+        Self {
+            enabled: true,
+            outgoing,
+            incoming,
+        }
+    }
+
+    pub fn symmetric(conditions: LinkConditions) -> Self {
+        Self::new(conditions, conditions)
+    }
+}
+
+struct DelayedPacket<T> {
+    release_at: f64,
+    order: u64,
+    item: T,
+}
+
+pub(crate) struct DelayQueue<T> {
+    packets: Vec<DelayedPacket<T>>,
+    last_in_order_release: f64,
+}
+
+impl<T> Default for DelayQueue<T> {
+    fn default() -> Self {
+        Self {
+            packets: Vec::new(),
+            last_in_order_release: 0.,
+        }
+    }
+}
+
+impl<T: Clone> DelayQueue<T> {
+    fn push(&mut self, rng: &mut SimulatorRng, conditions: &LinkConditions, now: f64, item: T) {
+        if rng.chance(conditions.loss) {
+            return;
+        }
+        let copies = if rng.chance(conditions.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let jitter = conditions.jitter.as_secs_f64() * rng.next_f64();
+            let mut release_at = now + conditions.latency.as_secs_f64() + jitter;
+            if rng.chance(conditions.reordering) {
+                // Held back past the in-order stream so that later packets overtake it.
+                release_at += conditions.latency.as_secs_f64().max(0.05) * (1. + rng.next_f64());
+            } else {
+                release_at = release_at.max(self.last_in_order_release);
+                self.last_in_order_release = release_at;
+            }
+            let order = rng.next_u64();
+            self.packets.push(DelayedPacket {
+                release_at,
+                order,
+                item: item.clone(),
+            });
+        }
+    }
+
+    fn release(&mut self, now: f64) -> Vec<T> {
+        let (mut due, pending): (Vec<_>, Vec<_>) = self
+            .packets
+            .drain(..)
+            .partition(|packet| packet.release_at <= now);
+        self.packets = pending;
+        due.sort_by(|a, b| {
+            a.release_at
+                .total_cmp(&b.release_at)
+                .then(a.order.cmp(&b.order))
+        });
+        due.into_iter().map(|packet| packet.item).collect()
+    }
+
+    fn drain(&mut self) -> Vec<T> {
+        self.release(f64::INFINITY)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+}
+
+// xorshift64*, good enough for dice rolls and deterministic between runs.
+struct SimulatorRng(u64);
+
+impl SimulatorRng {
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0. && self.next_f64() < probability as f64
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SimulatorQueues {
+    pub(crate) to_host: DelayQueue<String>,
+    pub(crate) to_all: DelayQueue<String>,
+    pub(crate) to_client: DelayQueue<(ClientId, String)>,
+    pub(crate) relay_except: DelayQueue<(ClientId, String)>,
+    pub(crate) from_host: DelayQueue<String>,
+    pub(crate) from_client: DelayQueue<(ClientId, String)>,
+}
+
+#[derive(Resource)]
+pub(crate) struct NetworkSimulator {
+    rng: SimulatorRng,
+    queues: SimulatorQueues,
+}
+
+impl Default for NetworkSimulator {
+    fn default() -> Self {
+        Self {
+            rng: SimulatorRng(0x9E37_79B9_7F4A_7C15),
+            queues: default(),
+        }
+    }
+}
+
+impl NetworkSimulator {
+    /// Queues `items` on the selected simulated link and returns whatever is due for delivery at
+    /// `now`. While the simulator is disabled anything still queued is flushed along with `items`.
+    pub(crate) fn route<T: Clone>(
+        &mut self,
+        select: impl FnOnce(&mut SimulatorQueues) -> &mut DelayQueue<T>,
+        enabled: bool,
+        link: &LinkConditions,
+        now: f64,
+        items: Vec<T>,
+    ) -> Vec<T> {
+        let queue = select(&mut self.queues);
+        if !enabled {
+            if queue.is_empty() {
+                return items;
+            }
+            let mut flushed = queue.drain();
+            flushed.extend(items);
+            return flushed;
+        }
+        for item in items {
+            queue.push(&mut self.rng, link, now, item);
+        }
+        queue.release(now)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.queues = default();
+    }
+}
@@ -3,6 +3,9 @@ use core::any::TypeId;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::api::LobbyFailureReason;
+use crate::protocol::P2PProtocol;
+
 #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
 pub enum P2PLobbyState {
     #[default]
@@ -51,15 +54,40 @@ pub struct PlayerInfo<PlayerData> {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub enum P2PData<PlayerData, PlayerInputData, Instantiations> {
+#[serde(bound = "")]
+pub enum P2PData<P: P2PProtocol> {
     ClientLobbyChatMessage(String, NetworkedId),
-    ClientInput(PlayerInputData),
-    ClientDataUpdate(PlayerData),
-    HostLobbyInfoUpdate(Vec<PlayerInfo<PlayerData>>),
+    /// Input of the client's fixed step with that tick.
+    ClientInput(u32, P::PlayerInputData),
+    ClientDataUpdate(P::PlayerData),
+    HostLobbyInfoUpdate(Vec<PlayerInfo<P::PlayerData>>),
     StateSync(u8, String),
     EventSync(u8, String),
-    HostInstantiation(InstantiationDataNet<Instantiations>),
-    PingRequest(f32),
+    HostInstantiation(InstantiationDataNet<P::Instantiations>),
+    PingRequest(f64),
+    PingResponse(f64),
+    ClientHello(u32),
+    HostReject(LobbyFailureReason),
+    TransformAck(u32),
+}
+
+impl<P: P2PProtocol> P2PData<P> {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            P2PData::ClientLobbyChatMessage(..) => "ClientLobbyChatMessage",
+            P2PData::ClientInput(..) => "ClientInput",
+            P2PData::ClientDataUpdate(_) => "ClientDataUpdate",
+            P2PData::HostLobbyInfoUpdate(_) => "HostLobbyInfoUpdate",
+            P2PData::StateSync(..) => "StateSync",
+            P2PData::EventSync(..) => "EventSync",
+            P2PData::HostInstantiation(_) => "HostInstantiation",
+            P2PData::PingRequest(_) => "PingRequest",
+            P2PData::PingResponse(_) => "PingResponse",
+            P2PData::TransformAck(_) => "TransformAck",
+            P2PData::ClientHello(_) => "ClientHello",
+            P2PData::HostReject(_) => "HostReject",
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -92,38 +120,27 @@ impl From<&NetTransform> for Transform {
     }
 }
 
+/// Identifies a host instantiation on every peer, so later instantiations can be parented to it.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceId(pub u32);
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct InstantiationDataNet<Instantiations> {
     pub transform: NetTransform,
     pub instantiation: Instantiations,
+    pub owner: NetworkedId,
+    pub despawn_on_leave: bool,
+    pub instance: InstanceId,
+    pub parent: Option<InstanceId>,
 }
 
 #[derive(Clone, Debug)]
 pub struct InstantiationData<Instantiations> {
     pub transform: Transform,
     pub instantiation: Instantiations,
-}
-
-impl<Instantiations: Clone> From<&InstantiationData<Instantiations>>
-    for InstantiationDataNet<Instantiations>
-{
-    fn from(value: &InstantiationData<Instantiations>) -> Self {
-        Self {
-            transform: NetTransform::from(&value.transform),
-            instantiation: value.instantiation.clone(),
-        }
-    }
-}
-
-impl<Instantiations: Clone> From<&InstantiationDataNet<Instantiations>>
-    for InstantiationData<Instantiations>
-{
-    fn from(value: &InstantiationDataNet<Instantiations>) -> Self {
-        Self {
-            transform: Transform::from(&value.transform),
-            instantiation: value.instantiation.clone(),
-        }
-    }
+    pub owner: NetworkedId,
+    pub despawn_on_leave: bool,
+    pub parent: Option<Entity>,
 }
 
 #[derive(Resource, Default, Clone, PartialEq, Debug)]
@@ -140,6 +157,9 @@ pub struct EasyP2PState<
 > {
     pub local_player_data: PlayerData,
     pub is_host: bool,
+    /// Set on a host created with `LobbyConfig::dedicated_host`, which is then left out of the
+    /// players.
+    pub dedicated_host: bool,
     pub lobby_code: String,
     pub players: Vec<PlayerInfo<PlayerData>>,
 }
@@ -157,7 +177,7 @@ impl<
 > EasyP2PState<PlayerData>
 {
     pub fn get_players(&self, add_host: bool) -> Vec<PlayerInfo<PlayerData>> {
-        let mut players = if add_host {
+        let mut players = if add_host && !self.dedicated_host {
             vec![PlayerInfo {
                 id: NetworkedId::Host,
                 data: self.local_player_data.clone(),
@@ -177,6 +197,7 @@ pub struct IsHost(pub bool);
 pub struct SyncedStateRegister {
     pub readers: Vec<fn(&str, &mut Commands) -> ()>,
     pub indexes: HashMap<TypeId, u8>,
+    pub names: Vec<&'static str>,
     pub counter: u8,
 }
 
@@ -184,6 +205,7 @@ pub struct SyncedStateRegister {
 pub struct SyncedEventRegister {
     pub readers: Vec<fn(&str, &mut World) -> ()>,
     pub indexes: HashMap<TypeId, u8>,
+    pub names: Vec<&'static str>,
     pub counter: u8,
 }
 
@@ -205,6 +227,7 @@ impl SyncedStateRegister {
         }
         let idx = self.counter;
         self.indexes.insert(TypeId::of::<S>(), idx);
+        self.names.push(std::any::type_name::<S>());
         self.counter = self.counter.wrapping_add(1);
         self.readers.push(|payload: &str, commands: &mut Commands| {
             if let Ok(value) = serde_json::from_str::<S>(payload) {
@@ -231,6 +254,7 @@ impl SyncedEventRegister {
         }
         let idx = self.counter;
         self.indexes.insert(TypeId::of::<E>(), idx);
+        self.names.push(std::any::type_name::<E>());
         self.counter = self.counter.wrapping_add(1);
         self.readers.push(|payload: &str, world: &mut World| {
             if let Ok(value) = serde_json::from_str::<E>(payload) {
@@ -1,43 +1,39 @@
 use core::any::TypeId;
-use std::time::Duration;
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::api::EasyP2PTransportIo;
 use crate::api::{
-    HandleInstantiation, OnExitLobbyReq, OnInternalClientData, OnInternalHostData, OnLobbyCreated,
-    OnLobbyEntered, OnLobbyExit, OnLobbyJoined, OnRelayToAllExcept, OnRosterUpdate, OnSendToAllReq,
-    OnSendToClientReq, OnSendToHostReq, OnTransportIncomingFromClient, OnTransportIncomingFromHost,
-    OnTransportRelayToAllExcept, OnTransportRosterChanged, OnTransportSendToAll,
-    OnTransportSendToClient, OnTransportSendToHost, PingUpdate,
+    HandleInstantiation, OnExitLobbyReq, OnInternalClientData, OnInternalHostData, OnJoinFailed,
+    OnKickReq, OnLobbyCreated, OnLobbyEntered, OnLobbyExit, OnLobbyJoined, OnRelayToAllExcept,
+    OnRosterUpdate, OnSendToAllReq, OnSendToClientReq, OnSendToHostReq,
+    OnTransportIncomingFromClient, OnTransportIncomingFromHost, OnTransportRelayToAllExcept,
+    OnTransportRosterChanged, OnTransportSendToAll, OnTransportSendToClient, OnTransportSendToHost,
 };
+use crate::debug_overlay::NetworkStats;
+use crate::fragmentation::FragmentBuffers;
+use crate::input_buffer::IncomingInputs;
+use crate::instantiation::NetworkedInstances;
+use crate::lobby::{LobbyAdmission, LobbyConfig};
+use crate::protocol::{P2PProtocol, UnitProtocol};
+use crate::simulator::NetworkSimulator;
 use crate::state::{
     EasyP2PState, InstantiationData, IsHost, NetworkedEntity, NetworkedId, P2PData, P2PLobbyState,
     PlayerInfo, SyncedEventRegister, SyncedStateRegister,
 };
 use crate::updates::{EasyP2PUpdate, EasyP2PUpdateQueue};
+use crate::validation::{ClientStrikes, ClientValidation, Validation};
 
-pub(crate) fn on_external_lobby_exit<PlayerData, PlayerInputData, Instantiations>(
-    mut state: ResMut<EasyP2PState<PlayerData>>,
+pub(crate) fn on_external_lobby_exit<P: P2PProtocol>(
+    mut state: ResMut<EasyP2PState<P::PlayerData>>,
     mut r: MessageReader<OnLobbyExit>,
     mut lobby_state: ResMut<NextState<P2PLobbyState>>,
     mut host_flag: ResMut<IsHost>,
-    mut updates: ResMut<EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>,
-) where
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+    mut fragments: ResMut<FragmentBuffers>,
+    mut simulator: ResMut<NetworkSimulator>,
+) {
     let mut exit_reason = None;
     for OnLobbyExit(reason) in r.read() {
         exit_reason = Some(*reason);
@@ -47,40 +43,29 @@ pub(crate) fn on_external_lobby_exit<PlayerData, PlayerInputData, Instantiations
     }
     let reason = exit_reason.unwrap();
     state.is_host = false;
+    state.dedicated_host = false;
     host_flag.0 = false;
     state.lobby_code.clear();
     state.players.clear();
+    fragments.clear();
+    simulator.clear();
     lobby_state.set(P2PLobbyState::OutOfLobby);
     updates.push(EasyP2PUpdate::LobbyExited { reason });
 }
 
-pub(crate) fn broadcast_roster_on_host<PlayerData, PlayerInputData, Instantiations>(
+pub(crate) fn broadcast_roster_on_host<P: P2PProtocol>(
     mut info_r: MessageReader<OnTransportRosterChanged>,
-    mut roster_w: MessageWriter<OnRosterUpdate<PlayerData>>,
-    mut w_send_all: MessageWriter<OnSendToAllReq<PlayerData, PlayerInputData, Instantiations>>,
-    mut state: ResMut<EasyP2PState<PlayerData>>,
-    mut updates: ResMut<EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>,
-) where
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+    mut roster_w: MessageWriter<OnRosterUpdate<P>>,
+    mut w_send_all: MessageWriter<OnSendToAllReq<P>>,
+    mut state: ResMut<EasyP2PState<P::PlayerData>>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+) {
     if !state.is_host {
         return;
     }
     for OnTransportRosterChanged(list) in info_r.read() {
         state.players.retain(|p| match p.id {
-            NetworkedId::ClientId(cid) => list.contains(&cid.to_string()),
+            NetworkedId::ClientId(cid) => list.contains(&cid),
             NetworkedId::Host => true,
         });
 
@@ -93,19 +78,9 @@ pub(crate) fn broadcast_roster_on_host<PlayerData, PlayerInputData, Instantiatio
     }
 }
 
-pub(crate) fn despawn_on_leave<
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
->(
+pub(crate) fn despawn_on_leave<P: P2PProtocol>(
     mut commands: Commands,
-    mut on_roster_update: MessageReader<OnRosterUpdate<PlayerData>>,
+    mut on_roster_update: MessageReader<OnRosterUpdate<P>>,
     network_entities_q: Query<(Entity, &NetworkedEntity)>,
 ) {
     for OnRosterUpdate(list) in on_roster_update.read() {
@@ -123,118 +98,137 @@ pub(crate) fn despawn_on_leave<
     }
 }
 
-pub(crate) fn encode_outgoing<
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(
-    mut to_host_r: MessageReader<OnSendToHostReq<PlayerData, PlayerInputData, Instantiations>>,
-    mut to_all_r: MessageReader<OnSendToAllReq<PlayerData, PlayerInputData, Instantiations>>,
-    mut to_client_r: MessageReader<OnSendToClientReq<PlayerData, PlayerInputData, Instantiations>>,
-    mut relay_except_r: MessageReader<
-        OnRelayToAllExcept<PlayerData, PlayerInputData, Instantiations>,
-    >,
+pub(crate) fn encode_outgoing<P: P2PProtocol>(
+    mut to_host_r: MessageReader<OnSendToHostReq<P>>,
+    mut to_all_r: MessageReader<OnSendToAllReq<P>>,
+    mut to_client_r: MessageReader<OnSendToClientReq<P>>,
+    mut relay_except_r: MessageReader<OnRelayToAllExcept<P>>,
     mut w_send_host: MessageWriter<OnTransportSendToHost>,
     mut w_send_all: MessageWriter<OnTransportSendToAll>,
     mut w_send_client: MessageWriter<OnTransportSendToClient>,
     mut w_relay_except: MessageWriter<OnTransportRelayToAllExcept>,
+    time: Res<Time<Real>>,
+    mut stats: Option<ResMut<NetworkStats>>,
 ) {
+    let now = time.elapsed_secs();
     for OnSendToHostReq(data) in to_host_r.read() {
         if let Ok(text) = serde_json::to_string(&data) {
+            if let Some(stats) = stats.as_mut() {
+                stats.record(now, true, Some(NetworkedId::Host), data.kind(), text.len());
+            }
             w_send_host.write(OnTransportSendToHost(text));
         }
     }
     for OnSendToAllReq(data) in to_all_r.read() {
         if let Ok(text) = serde_json::to_string(&data) {
+            if let Some(stats) = stats.as_mut() {
+                stats.record(now, true, None, data.kind(), text.len());
+            }
             w_send_all.write(OnTransportSendToAll(text));
         }
     }
     for OnSendToClientReq(cid, data) in to_client_r.read() {
         if let Ok(text) = serde_json::to_string(&data) {
+            if let Some(stats) = stats.as_mut() {
+                let peer = Some(NetworkedId::ClientId(*cid));
+                stats.record(now, true, peer, data.kind(), text.len());
+            }
             w_send_client.write(OnTransportSendToClient(*cid, text));
         }
     }
     for OnRelayToAllExcept(sender, data) in relay_except_r.read() {
         if let Ok(text) = serde_json::to_string(&data) {
+            if let Some(stats) = stats.as_mut() {
+                stats.record(now, true, None, data.kind(), text.len());
+            }
             w_relay_except.write(OnTransportRelayToAllExcept(*sender, text));
         }
     }
 }
 
-pub(crate) fn decode_incoming<
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(
+pub(crate) fn decode_incoming<P: P2PProtocol>(
     mut from_client_r: MessageReader<OnTransportIncomingFromClient>,
     mut from_host_r: MessageReader<OnTransportIncomingFromHost>,
-    mut ev_client: MessageWriter<OnInternalClientData<PlayerData, PlayerInputData, Instantiations>>,
-    mut ev_host: MessageWriter<OnInternalHostData<PlayerData, PlayerInputData, Instantiations>>,
+    mut ev_client: MessageWriter<OnInternalClientData<P>>,
+    mut ev_host: MessageWriter<OnInternalHostData<P>>,
+    validation: Res<ClientValidation<P>>,
+    mut strikes: ResMut<ClientStrikes>,
+    mut kick_w: MessageWriter<OnKickReq>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+    time: Res<Time<Real>>,
+    mut stats: Option<ResMut<NetworkStats>>,
+    lobby_config: Res<LobbyConfig>,
+    mut admission: ResMut<LobbyAdmission>,
 ) {
+    let now = time.elapsed_secs();
     for OnTransportIncomingFromClient(cid, text) in from_client_r.read() {
-        if let Ok(data) =
-            serde_json::from_str::<P2PData<PlayerData, PlayerInputData, Instantiations>>(text)
-        {
-            ev_client.write(OnInternalClientData(*cid, data));
+        let validated = match serde_json::from_str::<P2PData<P>>(text) {
+            Ok(mut data) => {
+                if let Some(stats) = stats.as_mut() {
+                    let peer = Some(NetworkedId::ClientId(*cid));
+                    stats.record(now, false, peer, data.kind(), text.len());
+                }
+                match validation.validate(*cid, &mut data) {
+                    Validation::Accept => Some(data),
+                    Validation::Reject => None,
+                }
+            }
+            Err(_) => None,
+        };
+        let Some(data) = validated else {
+            let count = strikes.strike(*cid);
+            updates.push(EasyP2PUpdate::ClientRejected {
+                client_id: *cid,
+                strikes: count,
+            });
+            if validation.max_strikes.is_some_and(|max| count >= max) {
+                warn!("Kicking client {} after {} rejected messages", cid, count);
+                strikes.forget(*cid);
+                kick_w.write(OnKickReq(*cid));
+            }
+            continue;
+        };
+        match &data {
+            P2PData::ClientHello(version) => admission.greet(&lobby_config, *cid, *version),
+            _ if !admission.admits(*cid) => continue,
+            _ => {}
         }
+        ev_client.write(OnInternalClientData(*cid, data));
     }
     for OnTransportIncomingFromHost(text) in from_host_r.read() {
-        if let Ok(data) =
-            serde_json::from_str::<P2PData<PlayerData, PlayerInputData, Instantiations>>(text)
-        {
+        if let Ok(data) = serde_json::from_str::<P2PData<P>>(text) {
+            if let Some(stats) = stats.as_mut() {
+                let peer = Some(NetworkedId::Host);
+                stats.record(now, false, peer, data.kind(), text.len());
+            }
             ev_host.write(OnInternalHostData(data));
         }
     }
 }
 
-pub(crate) fn state_update_system<PlayerData, PlayerInputData, Instantiations>(
-    mut state: ResMut<EasyP2PState<PlayerData>>,
+pub(crate) fn state_update_system<P: P2PProtocol>(
+    mut state: ResMut<EasyP2PState<P::PlayerData>>,
     mut created_r: MessageReader<OnLobbyCreated>,
     mut joined_r: MessageReader<OnLobbyJoined>,
     mut entered_r: MessageReader<OnLobbyEntered>,
     mut exit_r: MessageReader<OnExitLobbyReq>,
     mut lobby_state: ResMut<NextState<P2PLobbyState>>,
     mut host_flag: ResMut<IsHost>,
-    mut updates: ResMut<EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>,
-) where
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+    mut fragments: ResMut<FragmentBuffers>,
+    mut simulator: ResMut<NetworkSimulator>,
+    config: Res<LobbyConfig>,
+) {
     for OnLobbyCreated(code) in created_r.read() {
         state.is_host = true;
+        state.dedicated_host = config.dedicated_host;
         state.lobby_code = code.clone();
         host_flag.0 = true;
         updates.push(EasyP2PUpdate::LobbyCreated { code: code.clone() });
     }
     for OnLobbyJoined(code) in joined_r.read() {
         state.is_host = false;
+        state.dedicated_host = false;
         state.lobby_code = code.clone();
         host_flag.0 = false;
         updates.push(EasyP2PUpdate::LobbyJoined { code: code.clone() });
@@ -246,45 +240,31 @@ pub(crate) fn state_update_system<PlayerData, PlayerInputData, Instantiations>(
     }
     for _ in exit_r.read() {
         state.is_host = false;
+        state.dedicated_host = false;
         state.lobby_code.clear();
         state.players.clear();
+        fragments.clear();
+        simulator.clear();
         lobby_state.set(P2PLobbyState::OutOfLobby);
         host_flag.0 = false;
     }
 }
 
-pub(crate) fn intercept_data_messages<
-    PlayerData: Default + PartialEq,
-    PlayerInputData,
-    Instantiations,
->(
-    time: Res<Time>,
+pub(crate) fn intercept_data_messages<P: P2PProtocol>(
     mut commands: Commands,
-    mut internal_client_r: MessageReader<
-        OnInternalClientData<PlayerData, PlayerInputData, Instantiations>,
-    >,
-    mut internal_host_r: MessageReader<
-        OnInternalHostData<PlayerData, PlayerInputData, Instantiations>,
-    >,
-    mut roster_w: MessageWriter<OnRosterUpdate<PlayerData>>,
-    mut relay_w: MessageWriter<OnRelayToAllExcept<PlayerData, PlayerInputData, Instantiations>>,
-    mut inst_w: MessageWriter<HandleInstantiation<Instantiations>>,
-    mut w_send_client: MessageWriter<
-        OnSendToClientReq<PlayerData, PlayerInputData, Instantiations>,
-    >,
-    mut ping_w: MessageWriter<PingUpdate>,
-    mut state: ResMut<EasyP2PState<PlayerData>>,
+    mut internal_client_r: MessageReader<OnInternalClientData<P>>,
+    mut internal_host_r: MessageReader<OnInternalHostData<P>>,
+    mut roster_w: MessageWriter<OnRosterUpdate<P>>,
+    mut relay_w: MessageWriter<OnRelayToAllExcept<P>>,
+    mut inst_w: MessageWriter<HandleInstantiation<P>>,
+    mut instances: ResMut<NetworkedInstances>,
+    mut join_failed_w: MessageWriter<OnJoinFailed>,
+    mut state: ResMut<EasyP2PState<P::PlayerData>>,
     register: Res<SyncedStateRegister>,
     event_register: Res<SyncedEventRegister>,
-    mut updates: ResMut<EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>,
-) where
-    PlayerData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+    mut incoming_inputs: IncomingInputs<P>,
+) {
     for OnInternalClientData(cid, data) in internal_client_r.read() {
         match data {
             P2PData::ClientLobbyChatMessage(text, _sender) => {
@@ -300,14 +280,10 @@ pub(crate) fn intercept_data_messages<
                 }
             }
             P2PData::HostLobbyInfoUpdate(_) => {}
-            P2PData::ClientInput(input) => {
-                if state.is_host {
-                    updates.push(EasyP2PUpdate::ClientInput {
-                        sender: NetworkedId::ClientId(*cid),
-                        input: input.clone(),
-                    });
-                }
+            P2PData::ClientInput(tick, input) if state.is_host => {
+                incoming_inputs.receive(&mut updates, *cid, *tick, input.clone());
             }
+            P2PData::ClientInput(..) => {}
             P2PData::ClientDataUpdate(data) => {
                 if let Some(entry) = state
                     .players
@@ -320,12 +296,13 @@ pub(crate) fn intercept_data_messages<
             P2PData::StateSync(_, _) => {}
             P2PData::EventSync(_, _) => {}
             P2PData::HostInstantiation(_) => {}
-            P2PData::PingRequest(timestamp) => {
-                // Host receives ping from client, echo it back
-                if state.is_host {
-                    w_send_client.write(OnSendToClientReq(*cid, P2PData::PingRequest(*timestamp)));
-                }
-            }
+            P2PData::ClientHello(_) => {}
+            P2PData::HostReject(_) => {}
+            // Heartbeats are answered in the heartbeat module
+            P2PData::PingRequest(_) => {}
+            P2PData::PingResponse(_) => {}
+            // Handled by the networked transform module
+            P2PData::TransformAck(_) => {}
         }
     }
     for OnInternalHostData(data) in internal_host_r.read() {
@@ -364,75 +341,68 @@ pub(crate) fn intercept_data_messages<
                     });
                 }
             }
-            P2PData::ClientInput(_) => {}
+            P2PData::ClientInput(..) => {}
             P2PData::ClientDataUpdate(_) => {}
             P2PData::HostInstantiation(inst) => {
-                let local: InstantiationData<Instantiations> = InstantiationData::from(&*inst);
-                inst_w.write(HandleInstantiation(local.clone()));
-                updates.push(EasyP2PUpdate::Instantiated { data: local });
+                let entity = commands.spawn_empty().id();
+                instances.insert(inst.instance, entity);
+                inst_w.write(HandleInstantiation(
+                    entity,
+                    InstantiationData {
+                        transform: Transform::from(&inst.transform),
+                        instantiation: inst.instantiation.clone(),
+                        owner: inst.owner,
+                        despawn_on_leave: inst.despawn_on_leave,
+                        parent: inst.parent.and_then(|parent| instances.entity(parent)),
+                    },
+                ));
             }
-            P2PData::PingRequest(timestamp) => {
-                let elapsed_secs = time.elapsed_secs() - timestamp;
-                ping_w.write(PingUpdate(Duration::from_secs_f32(elapsed_secs)));
+            P2PData::ClientHello(_) => {}
+            P2PData::HostReject(reason) => {
+                join_failed_w.write(OnJoinFailed(reason.clone()));
             }
+            P2PData::PingRequest(_) => {}
+            P2PData::PingResponse(_) => {}
+            P2PData::TransformAck(_) => {}
         }
     }
 }
 
-pub(crate) fn send_local_data_after_enter<
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(
+pub(crate) fn send_local_data_after_enter<P: P2PProtocol>(
     mut entered_r: MessageReader<OnLobbyEntered>,
-    state: Res<EasyP2PState<PlayerData>>,
-    mut w_send_host: MessageWriter<OnSendToHostReq<PlayerData, PlayerInputData, Instantiations>>,
+    state: Res<EasyP2PState<P::PlayerData>>,
+    config: Res<LobbyConfig>,
+    mut w_send_host: MessageWriter<OnSendToHostReq<P>>,
 ) {
     for OnLobbyEntered(_code) in entered_r.read() {
         if state.is_host {
             continue;
         }
+        w_send_host.write(OnSendToHostReq(P2PData::ClientHello(
+            config.protocol_version,
+        )));
         w_send_host.write(OnSendToHostReq(P2PData::ClientDataUpdate(
             state.local_player_data.clone(),
         )));
     }
 }
 
-pub(crate) fn handle_client_data_update_on_host<PlayerData, PlayerInputData, Instantiations>(
-    mut internal_client_r: MessageReader<
-        OnInternalClientData<PlayerData, PlayerInputData, Instantiations>,
-    >,
-    mut state: ResMut<EasyP2PState<PlayerData>>,
-    mut w_send_all: MessageWriter<OnSendToAllReq<PlayerData, PlayerInputData, Instantiations>>,
-    mut roster_w: MessageWriter<OnRosterUpdate<PlayerData>>,
-    mut updates: ResMut<EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>>,
-) where
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations:
-        Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+pub(crate) fn handle_client_data_update_on_host<P: P2PProtocol>(
+    mut internal_client_r: MessageReader<OnInternalClientData<P>>,
+    mut state: ResMut<EasyP2PState<P::PlayerData>>,
+    mut w_send_all: MessageWriter<OnSendToAllReq<P>>,
+    mut roster_w: MessageWriter<OnRosterUpdate<P>>,
+    mut updates: ResMut<EasyP2PUpdateQueue<P>>,
+    admission: Res<LobbyAdmission>,
+) {
     if !state.is_host {
         return;
     }
     for OnInternalClientData(cid, data) in internal_client_r.read() {
+        // Clients only show up in the roster once the host accepted their hello.
+        if !admission.accepted.contains(cid) {
+            continue;
+        }
         if let P2PData::ClientDataUpdate(client_info) = data {
             let client_id = *cid;
             let mut found = false;
@@ -444,7 +414,7 @@ pub(crate) fn handle_client_data_update_on_host<PlayerData, PlayerInputData, Ins
                 }
             }
             if !found {
-                state.players.push(PlayerInfo::<PlayerData> {
+                state.players.push(PlayerInfo::<P::PlayerData> {
                     id: NetworkedId::ClientId(client_id),
                     data: client_info.clone(),
                 });
@@ -466,6 +436,8 @@ pub(crate) fn host_broadcast_event<E>(
     mut events: MessageReader<E>,
     register: Res<SyncedEventRegister>,
     mut w_send_all: MessageWriter<OnTransportSendToAll>,
+    time: Res<Time<Real>>,
+    mut stats: Option<ResMut<NetworkStats>>,
 ) where
     E: Serialize
         + for<'de> Deserialize<'de>
@@ -483,9 +455,12 @@ pub(crate) fn host_broadcast_event<E>(
         if let Some(index) = register.indexes.get(&TypeId::of::<E>()) {
             match serde_json::to_string(e) {
                 Ok(text) => {
-                    if let Ok(payload) =
-                        serde_json::to_string(&P2PData::<(), (), ()>::EventSync(*index, text))
-                    {
+                    let data = P2PData::<UnitProtocol>::EventSync(*index, text);
+                    if let Ok(payload) = serde_json::to_string(&data) {
+                        if let Some(stats) = stats.as_mut() {
+                            let now = time.elapsed_secs();
+                            stats.record(now, true, None, data.kind(), payload.len());
+                        }
                         w_send_all.write(OnTransportSendToAll(payload));
                     }
                 }
@@ -503,6 +478,8 @@ pub(crate) fn host_broadcast_state_change<S>(
     mut last: Local<Option<S>>,
     register: Res<SyncedStateRegister>,
     mut w_send_all: MessageWriter<OnTransportSendToAll>,
+    time: Res<Time<Real>>,
+    mut stats: Option<ResMut<NetworkStats>>,
 ) where
     S: States
         + Serialize
@@ -522,13 +499,16 @@ pub(crate) fn host_broadcast_state_change<S>(
         return;
     }
     *last = Some(current_value.clone());
-    if let Some(index) = register.indexes.get(&TypeId::of::<S>()) {
-        if let Ok(text) = serde_json::to_string(&current_value) {
-            if let Ok(payload) =
-                serde_json::to_string(&P2PData::<(), (), ()>::StateSync(*index, text))
-            {
-                w_send_all.write(OnTransportSendToAll(payload));
+    if let Some(index) = register.indexes.get(&TypeId::of::<S>())
+        && let Ok(text) = serde_json::to_string(&current_value)
+    {
+        let data = P2PData::<UnitProtocol>::StateSync(*index, text);
+        if let Ok(payload) = serde_json::to_string(&data) {
+            if let Some(stats) = stats.as_mut() {
+                let now = time.elapsed_secs();
+                stats.record(now, true, None, data.kind(), payload.len());
             }
+            w_send_all.write(OnTransportSendToAll(payload));
         }
     }
 }
@@ -551,26 +531,6 @@ impl bevy::ecs::system::Command for EmitSyncedEvent {
     }
 }
 
-pub(crate) fn send_ping<
-    PlayerData: Serialize
-        + for<'de> Deserialize<'de>
-        + Clone
-        + Send
-        + Sync
-        + core::fmt::Debug
-        + 'static
-        + Default
-        + PartialEq,
-    PlayerInputData: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + core::fmt::Debug + 'static,
->(
-    time: Res<Time>,
-    state: Res<EasyP2PState<PlayerData>>,
-    mut w_send_host: MessageWriter<OnSendToHostReq<PlayerData, PlayerInputData, Instantiations>>,
-) {
-    if state.is_host {
-        return;
-    }
-
-    w_send_host.write(OnSendToHostReq(P2PData::PingRequest(time.elapsed_secs())));
+pub(crate) fn release_simulated_incoming<P: P2PProtocol>(mut io: EasyP2PTransportIo<P>) {
+    io.release_simulated_incoming();
 }
@@ -0,0 +1,225 @@
+use bevy::ecs::system::SystemState;
+use bevy::prelude::*;
+use std::collections::BTreeSet;
+
+use crate::api::{EasyP2PTransportIo, ExitReason, LobbyFailureReason};
+use crate::protocol::P2PProtocol;
+use crate::{ClientId, NetworkedId};
+
+/// Who an outgoing payload is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendTarget {
+    Host,
+    All,
+    Client(ClientId),
+    AllExcept(ClientId),
+}
+
+/// Something that happened on the transport since the last `P2PTransport::poll`.
+#[derive(Clone, Debug)]
+pub enum TransportEvent {
+    LobbyCreated(String),
+    LobbyJoined(String),
+    LobbyEntered(String),
+    LobbyExited(ExitReason),
+    CreateFailed(LobbyFailureReason),
+    JoinFailed(LobbyFailureReason),
+    /// Host only: a client finished connecting.
+    PeerConnected(ClientId),
+    /// Host only: a client went away or was kicked.
+    PeerDisconnected(ClientId),
+    Received {
+        from: NetworkedId,
+        payload: Vec<u8>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransportError {
+    NotConnected,
+    UnknownPeer(ClientId),
+    InvalidPayload,
+    Other(String),
+}
+
+impl core::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TransportError::NotConnected => write!(f, "not connected"),
+            TransportError::UnknownPeer(client_id) => write!(f, "unknown peer {}", client_id),
+            TransportError::InvalidPayload => write!(f, "invalid payload"),
+            TransportError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// A connection backend driven by `EasyP2PPlugin`. The plugin owns the transport (as a non-send
+/// resource, so browser handles are fine), forwards lobby requests and encoded payloads to it
+/// every frame and polls it for events. Payloads handed to `send` never exceed
+/// `max_message_size`; bigger ones are fragmented beforehand.
+pub trait P2PTransport: 'static {
+    /// Registers whatever the transport relies on besides itself: plugins, resources, messages.
+    fn setup(_app: &mut App)
+    where
+        Self: Sized,
+    {
+    }
+
+    fn max_message_size(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Starts hosting. The lobby code is reported later through `TransportEvent::LobbyCreated`.
+    fn create_lobby(&mut self, world: &mut World) -> Result<(), TransportError>;
+    fn join_lobby(&mut self, world: &mut World, code: &str) -> Result<(), TransportError>;
+    /// Drops every connection. Also called after the transport reported `LobbyExited`.
+    fn exit_lobby(&mut self, world: &mut World);
+    fn kick(&mut self, world: &mut World, client_id: ClientId);
+    fn send(
+        &mut self,
+        world: &mut World,
+        target: SendTarget,
+        payload: &[u8],
+    ) -> Result<(), TransportError>;
+    fn poll(&mut self, world: &mut World, events: &mut Vec<TransportEvent>);
+}
+
+// Clients currently connected to the host, as reported by the transport.
+#[derive(Resource, Default)]
+pub(crate) struct ConnectedPeers(BTreeSet<ClientId>);
+
+type TransportIoState<P> = SystemState<(
+    EasyP2PTransportIo<'static, 'static, P>,
+    ResMut<'static, ConnectedPeers>,
+)>;
+
+pub(crate) fn poll_transport<T: P2PTransport, P: P2PProtocol>(
+    world: &mut World,
+    io_state: &mut TransportIoState<P>,
+) {
+    let Some(mut transport) = world.remove_non_send_resource::<T>() else {
+        return;
+    };
+    let mut events = Vec::new();
+    transport.poll(world, &mut events);
+    world.insert_non_send_resource(transport);
+    deliver_events(world, io_state, events);
+}
+
+pub(crate) fn drive_transport<T: P2PTransport, P: P2PProtocol>(
+    world: &mut World,
+    io_state: &mut TransportIoState<P>,
+) {
+    let Some(mut transport) = world.remove_non_send_resource::<T>() else {
+        return;
+    };
+    let (mut io, mut peers) = io_state.get_mut(world);
+    let exit = io.take_exit_requests() > 0 || !io.take_lobby_exit_events().is_empty();
+    if exit {
+        peers.0.clear();
+    }
+    let creates = io.take_create_requests();
+    let joins = io.take_join_requests();
+    let kicks = io.take_kick_requests();
+    let mut outgoing: Vec<(SendTarget, String)> = Vec::new();
+    outgoing.extend(
+        io.take_send_to_host()
+            .into_iter()
+            .map(|payload| (SendTarget::Host, payload)),
+    );
+    outgoing.extend(
+        io.take_send_to_all()
+            .into_iter()
+            .map(|payload| (SendTarget::All, payload)),
+    );
+    outgoing.extend(
+        io.take_send_to_client()
+            .into_iter()
+            .map(|(client_id, payload)| (SendTarget::Client(client_id), payload)),
+    );
+    outgoing.extend(
+        io.take_relay_to_all_except()
+            .into_iter()
+            .map(|(client_id, payload)| (SendTarget::AllExcept(client_id), payload)),
+    );
+    io_state.apply(world);
+
+    // Payloads go out before kicks and exits so that a last message still reaches the peer.
+    for (target, payload) in outgoing {
+        if let Err(err) = transport.send(world, target, payload.as_bytes()) {
+            debug!("Dropping payload for {:?}: {}", target, err);
+        }
+    }
+    for client_id in kicks {
+        transport.kick(world, client_id);
+    }
+    if exit {
+        transport.exit_lobby(world);
+    }
+    let mut events = Vec::new();
+    for _ in 0..creates {
+        if let Err(err) = transport.create_lobby(world) {
+            events.push(TransportEvent::CreateFailed(
+                LobbyFailureReason::TransportError(err.to_string()),
+            ));
+        }
+    }
+    for code in joins {
+        if let Err(err) = transport.join_lobby(world, &code) {
+            events.push(TransportEvent::JoinFailed(
+                LobbyFailureReason::TransportError(err.to_string()),
+            ));
+        }
+    }
+    world.insert_non_send_resource(transport);
+    deliver_events(world, io_state, events);
+}
+
+fn deliver_events<P: P2PProtocol>(
+    world: &mut World,
+    io_state: &mut TransportIoState<P>,
+    events: Vec<TransportEvent>,
+) {
+    if events.is_empty() {
+        return;
+    }
+    let (mut io, mut peers) = io_state.get_mut(world);
+    for event in events {
+        match event {
+            TransportEvent::LobbyCreated(code) => io.emit_lobby_created(code),
+            TransportEvent::LobbyJoined(code) => io.emit_lobby_joined(code),
+            TransportEvent::LobbyEntered(code) => io.emit_lobby_entered(code),
+            TransportEvent::LobbyExited(reason) => {
+                peers.0.clear();
+                io.emit_lobby_exit(reason);
+            }
+            TransportEvent::CreateFailed(reason) => io.emit_create_failed(reason),
+            TransportEvent::JoinFailed(reason) => io.emit_join_failed(reason),
+            TransportEvent::PeerConnected(client_id) => {
+                if peers.0.insert(client_id) {
+                    io.emit_roster_changed(peers.0.iter().copied().collect());
+                }
+            }
+            TransportEvent::PeerDisconnected(client_id) => {
+                if peers.0.remove(&client_id) {
+                    io.emit_roster_changed(peers.0.iter().copied().collect());
+                }
+            }
+            TransportEvent::Received { from, payload } => {
+                let Ok(payload) = String::from_utf8(payload) else {
+                    warn!("Dropping non UTF-8 payload from {:?}", from);
+                    continue;
+                };
+                match from {
+                    NetworkedId::Host => io.emit_incoming_from_host(payload),
+                    NetworkedId::ClientId(client_id) => {
+                        io.emit_incoming_from_client(client_id, payload)
+                    }
+                }
+            }
+        }
+    }
+    io_state.apply(world);
+}
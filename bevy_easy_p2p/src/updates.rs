@@ -1,15 +1,14 @@
-use bevy::prelude::{FromWorld, Resource, World};
+use bevy::prelude::*;
 
+use crate::protocol::P2PProtocol;
 use crate::state::{InstantiationData, PlayerInfo};
-use crate::{ClientId, ExitReason, NetworkedId};
+use crate::{ClientId, ExitReason, LobbyFailureReason, NetworkedId};
 
-#[derive(Clone, Debug)]
-pub enum EasyP2PUpdate<PlayerData, PlayerInputData, Instantiations>
-where
-    PlayerData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    PlayerInputData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+/// Everything that happened in the lobby, written as messages during `EasyP2PSystemSet::Emit`.
+/// Any number of systems can read them with a `MessageReader<EasyP2PUpdate<P>>` ordered after
+/// that set.
+#[derive(Message, Clone, Debug)]
+pub enum EasyP2PUpdate<P: P2PProtocol> {
     LobbyCreated {
         code: String,
     },
@@ -22,6 +21,12 @@ where
     LobbyExited {
         reason: ExitReason,
     },
+    JoinFailed {
+        reason: LobbyFailureReason,
+    },
+    CreateFailed {
+        reason: LobbyFailureReason,
+    },
     HostChat {
         text: String,
     },
@@ -30,53 +35,56 @@ where
         text: String,
     },
     RosterUpdated {
-        players: Vec<PlayerInfo<PlayerData>>,
+        players: Vec<PlayerInfo<P::PlayerData>>,
     },
     ClientInput {
         sender: NetworkedId,
-        input: PlayerInputData,
+        input: P::PlayerInputData,
     },
     Instantiated {
-        data: InstantiationData<Instantiations>,
+        entity: Entity,
+        data: InstantiationData<P::Instantiations>,
+    },
+    /// Host only: a client message failed `ClientValidation` or could not be decoded.
+    ClientRejected {
+        client_id: ClientId,
+        strikes: u32,
+    },
+    PeerStalled {
+        peer: NetworkedId,
+    },
+    PeerResumed {
+        peer: NetworkedId,
+    },
+    PeerTimedOut {
+        peer: NetworkedId,
     },
 }
 
+// Updates gathered during the frame, written out together so that readers ordered after
+// `EasyP2PSystemSet::Emit` see all of them.
 #[derive(Resource)]
-pub struct EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>
-where
-    PlayerData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    PlayerInputData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Clone + Send + Sync + core::fmt::Debug + 'static,
-{
-    queue: Vec<EasyP2PUpdate<PlayerData, PlayerInputData, Instantiations>>,
+pub(crate) struct EasyP2PUpdateQueue<P: P2PProtocol> {
+    queue: Vec<EasyP2PUpdate<P>>,
 }
 
-impl<PlayerData, PlayerInputData, Instantiations>
-    EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>
-where
-    PlayerData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    PlayerInputData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Clone + Send + Sync + core::fmt::Debug + 'static,
-{
-    pub fn push(&mut self, update: EasyP2PUpdate<PlayerData, PlayerInputData, Instantiations>) {
+impl<P: P2PProtocol> EasyP2PUpdateQueue<P> {
+    pub(crate) fn push(&mut self, update: EasyP2PUpdate<P>) {
         self.queue.push(update);
     }
-
-    pub fn drain(
-        &mut self,
-    ) -> impl Iterator<Item = EasyP2PUpdate<PlayerData, PlayerInputData, Instantiations>> {
-        self.queue.drain(..)
-    }
 }
 
-impl<PlayerData, PlayerInputData, Instantiations> FromWorld
-    for EasyP2PUpdateQueue<PlayerData, PlayerInputData, Instantiations>
-where
-    PlayerData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    PlayerInputData: Clone + Send + Sync + core::fmt::Debug + 'static,
-    Instantiations: Clone + Send + Sync + core::fmt::Debug + 'static,
-{
+impl<P: P2PProtocol> FromWorld for EasyP2PUpdateQueue<P> {
     fn from_world(_: &mut World) -> Self {
         Self { queue: Vec::new() }
     }
 }
+
+pub(crate) fn emit_updates<P: P2PProtocol>(
+    mut queue: ResMut<EasyP2PUpdateQueue<P>>,
+    mut updates_w: MessageWriter<EasyP2PUpdate<P>>,
+) {
+    if !queue.queue.is_empty() {
+        updates_w.write_batch(queue.queue.drain(..));
+    }
+}
@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+use crate::ClientId;
+use crate::protocol::P2PProtocol;
+use crate::state::P2PData;
+
+/// What a validator decided about a client message. Validators get the message mutably, so
+/// sanitizing it in place and returning `Accept` is fine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validation {
+    Accept,
+    Reject,
+}
+
+type Validator<T> = Box<dyn Fn(ClientId, &mut T) -> Validation + Send + Sync>;
+
+/// Host-side checks on messages coming from clients. Rejected messages are dropped and count as
+/// a strike against the sender; undecodable payloads count too. A client reaching `max_strikes`
+/// is kicked.
+#[derive(Resource)]
+pub struct ClientValidation<P: P2PProtocol> {
+    player_data: Option<Validator<P::PlayerData>>,
+    input: Option<Validator<P::PlayerInputData>>,
+    chat: Option<Validator<String>>,
+    /// Kicks a client after that many rejected messages, never when `None`.
+    pub max_strikes: Option<u32>,
+}
+
+impl<P: P2PProtocol> Default for ClientValidation<P> {
+    fn default() -> Self {
+        Self {
+            player_data: None,
+            input: None,
+            chat: None,
+            max_strikes: Some(10),
+        }
+    }
+}
+
+impl<P: P2PProtocol> ClientValidation<P> {
+    pub fn with_player_data(
+        mut self,
+        validator: impl Fn(ClientId, &mut P::PlayerData) -> Validation + Send + Sync + 'static,
+    ) -> Self {
+        self.player_data = Some(Box::new(validator));
+        self
+    }
+
+    pub fn with_input(
+        mut self,
+        validator: impl Fn(ClientId, &mut P::PlayerInputData) -> Validation + Send + Sync + 'static,
+    ) -> Self {
+        self.input = Some(Box::new(validator));
+        self
+    }
+
+    pub fn with_chat(
+        mut self,
+        validator: impl Fn(ClientId, &mut String) -> Validation + Send + Sync + 'static,
+    ) -> Self {
+        self.chat = Some(Box::new(validator));
+        self
+    }
+
+    pub fn with_max_strikes(mut self, max_strikes: Option<u32>) -> Self {
+        self.max_strikes = max_strikes;
+        self
+    }
+
+    pub(crate) fn validate(&self, client_id: ClientId, data: &mut P2PData<P>) -> Validation {
+        match data {
+            P2PData::ClientDataUpdate(player_data) => {
+                run(&self.player_data, client_id, player_data)
+            }
+            P2PData::ClientInput(_, input) => run(&self.input, client_id, input),
+            P2PData::ClientLobbyChatMessage(text, _) => run(&self.chat, client_id, text),
+            // Only the host sends these; a client doing so is misbehaving.
+            P2PData::HostLobbyInfoUpdate(_)
+            | P2PData::StateSync(_, _)
+            | P2PData::EventSync(_, _)
+            | P2PData::HostInstantiation(_)
+            | P2PData::HostReject(_) => Validation::Reject,
+            P2PData::ClientHello(_)
+            | P2PData::PingRequest(_)
+            | P2PData::PingResponse(_)
+            | P2PData::TransformAck(_) => Validation::Accept,
+        }
+    }
+}
+
+fn run<T>(validator: &Option<Validator<T>>, client_id: ClientId, value: &mut T) -> Validation {
+    match validator {
+        Some(validator) => validator(client_id, value),
+        None => Validation::Accept,
+    }
+}
+
+// Rejected messages per client since it joined.
+#[derive(Resource, Default)]
+pub(crate) struct ClientStrikes(HashMap<ClientId, u32>);
+
+impl ClientStrikes {
+    pub(crate) fn strike(&mut self, client_id: ClientId) -> u32 {
+        let strikes = self.0.entry(client_id).or_default();
+        *strikes += 1;
+        *strikes
+    }
+
+    pub(crate) fn forget(&mut self, client_id: ClientId) {
+        self.0.remove(&client_id);
+    }
+}
+
+pub(crate) fn clear_strikes(mut strikes: ResMut<ClientStrikes>) {
+    strikes.0.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        OnInternalClientData, OnInternalHostData, OnKickReq, OnTransportIncomingFromClient,
+        OnTransportIncomingFromHost,
+    };
+    use crate::lobby::{LobbyAdmission, LobbyConfig};
+    use crate::state::{NetworkedId, PlayerInfo};
+    use crate::systems::decode_incoming;
+    use crate::updates::EasyP2PUpdateQueue;
+    use bevy::ecs::system::RunSystemOnce;
+
+    #[derive(Clone, Debug)]
+    struct TestProtocol;
+
+    impl P2PProtocol for TestProtocol {
+        type PlayerData = String;
+        type PlayerInputData = u32;
+        type Instantiations = ();
+    }
+
+    const CLIENT: ClientId = 5;
+
+    fn short_names() -> ClientValidation<TestProtocol> {
+        ClientValidation::<TestProtocol>::default().with_player_data(|_, name| {
+            if name.is_empty() {
+                return Validation::Reject;
+            }
+            name.truncate(4);
+            Validation::Accept
+        })
+    }
+
+    #[test]
+    fn validators_can_sanitize() {
+        let validation = short_names();
+        let mut data = P2PData::<TestProtocol>::ClientDataUpdate("Bartholomew".to_string());
+        assert_eq!(validation.validate(CLIENT, &mut data), Validation::Accept);
+        assert!(matches!(data, P2PData::ClientDataUpdate(name) if name == "Bart"));
+
+        let mut data = P2PData::<TestProtocol>::ClientDataUpdate(String::new());
+        assert_eq!(validation.validate(CLIENT, &mut data), Validation::Reject);
+    }
+
+    #[test]
+    fn unvalidated_kinds_are_accepted() {
+        let validation = short_names();
+        let mut data = P2PData::<TestProtocol>::ClientInput(1, 7);
+        assert_eq!(validation.validate(CLIENT, &mut data), Validation::Accept);
+        let mut data = P2PData::<TestProtocol>::ClientLobbyChatMessage(
+            "hi".to_string(),
+            NetworkedId::ClientId(CLIENT),
+        );
+        assert_eq!(validation.validate(CLIENT, &mut data), Validation::Accept);
+    }
+
+    #[test]
+    fn host_only_messages_from_clients_are_rejected() {
+        let validation = ClientValidation::<TestProtocol>::default();
+        let mut data = P2PData::<TestProtocol>::HostLobbyInfoUpdate(vec![PlayerInfo {
+            id: NetworkedId::Host,
+            data: String::new(),
+        }]);
+        assert_eq!(validation.validate(CLIENT, &mut data), Validation::Reject);
+        let mut data = P2PData::<TestProtocol>::StateSync(0, String::new());
+        assert_eq!(validation.validate(CLIENT, &mut data), Validation::Reject);
+    }
+
+    #[test]
+    fn strikes_accumulate_per_client() {
+        let mut strikes = ClientStrikes::default();
+        assert_eq!(strikes.strike(CLIENT), 1);
+        assert_eq!(strikes.strike(CLIENT), 2);
+        assert_eq!(strikes.strike(CLIENT + 1), 1);
+        strikes.forget(CLIENT);
+        assert_eq!(strikes.strike(CLIENT), 1);
+    }
+
+    fn world(validation: ClientValidation<TestProtocol>) -> World {
+        let mut world = World::new();
+        world.insert_resource(validation);
+        world.init_resource::<ClientStrikes>();
+        world.init_resource::<EasyP2PUpdateQueue<TestProtocol>>();
+        world.init_resource::<Time<Real>>();
+        world.init_resource::<LobbyConfig>();
+        world.init_resource::<LobbyAdmission>();
+        world.init_resource::<Messages<OnTransportIncomingFromClient>>();
+        world.init_resource::<Messages<OnTransportIncomingFromHost>>();
+        world.init_resource::<Messages<OnInternalClientData<TestProtocol>>>();
+        world.init_resource::<Messages<OnInternalHostData<TestProtocol>>>();
+        world.init_resource::<Messages<OnKickReq>>();
+        world
+            .resource_mut::<LobbyAdmission>()
+            .accepted
+            .insert(CLIENT);
+        world
+    }
+
+    // Decodes what the client sent and returns the kicks requested so far.
+    fn receive(world: &mut World, payloads: &[&str]) -> usize {
+        for payload in payloads {
+            world.write_message(OnTransportIncomingFromClient(CLIENT, payload.to_string()));
+        }
+        world
+            .run_system_once(decode_incoming::<TestProtocol>)
+            .unwrap();
+        // Each run reads from scratch, don't decode these again.
+        world
+            .resource_mut::<Messages<OnTransportIncomingFromClient>>()
+            .clear();
+        world.resource::<Messages<OnKickReq>>().len()
+    }
+
+    #[test]
+    fn clients_are_kicked_at_max_strikes() {
+        let mut world = world(short_names().with_max_strikes(Some(3)));
+        let empty_name =
+            serde_json::to_string(&P2PData::<TestProtocol>::ClientDataUpdate(String::new()))
+                .unwrap();
+        assert_eq!(receive(&mut world, &["garbage", &empty_name]), 0);
+        assert_eq!(world.resource::<ClientStrikes>().0[&CLIENT], 2);
+        assert_eq!(receive(&mut world, &["{}"]), 1);
+        // Strikes start over should the client come back.
+        assert!(!world.resource::<ClientStrikes>().0.contains_key(&CLIENT));
+    }
+
+    #[test]
+    fn accepted_messages_are_not_strikes() {
+        let mut world = world(short_names().with_max_strikes(Some(1)));
+        let name = serde_json::to_string(&P2PData::<TestProtocol>::ClientDataUpdate(
+            "Bartholomew".to_string(),
+        ))
+        .unwrap();
+        assert_eq!(receive(&mut world, &[&name]), 0);
+        let decoded = world.resource::<Messages<OnInternalClientData<TestProtocol>>>();
+        assert_eq!(decoded.len(), 1);
+    }
+
+    #[test]
+    fn no_max_strikes_never_kicks() {
+        let mut world = world(short_names().with_max_strikes(None));
+        assert_eq!(receive(&mut world, &["garbage"; 50]), 0);
+        assert_eq!(world.resource::<ClientStrikes>().0[&CLIENT], 50);
+    }
+}
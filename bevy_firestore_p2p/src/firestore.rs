@@ -0,0 +1,282 @@
+use bevy_easy_p2p::ClientId;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::signaling::{CreateError, RoomSnapshot};
+use crate::{FetchAuth, FetchError, FirestoreConfig, http_fetch_json, now_ms};
+
+fn database_name(cfg: &FirestoreConfig) -> String {
+    format!("projects/{}/databases/(default)", cfg.project_id)
+}
+
+fn room_doc_name(cfg: &FirestoreConfig, room: &str) -> String {
+    format!(
+        "{}/documents/{}rooms/{}",
+        database_name(cfg),
+        cfg.collection_prefix,
+        room
+    )
+}
+
+fn room_doc_url(cfg: &FirestoreConfig, room: &str) -> String {
+    format!(
+        "{}/{}",
+        cfg.base_url.trim_end_matches('/'),
+        room_doc_name(cfg, room)
+    )
+}
+
+fn auth(cfg: &FirestoreConfig) -> FetchAuth<'_> {
+    FetchAuth {
+        api_key: cfg.api_key.as_deref(),
+        bearer_token: cfg.bearer_token.as_deref(),
+    }
+}
+
+fn patch_url(cfg: &FirestoreConfig, room: &str, mask: &str) -> String {
+    format!("{}?updateMask.fieldPaths={}", room_doc_url(cfg, room), mask)
+}
+
+fn timestamp(ms: f64) -> String {
+    js_sys::Date::new(&ms.into()).to_iso_string().into()
+}
+
+// A fresh room. `expiresAt` may also back a Firestore TTL policy so that abandoned rooms get
+// cleaned up.
+fn room_body(ttl: Duration) -> serde_json::Value {
+    json!({
+        "fields": {
+            "offers": {"mapValue": {"fields": {}}},
+            "answers": {"mapValue": {"fields": {}}},
+            "expiresAt": {"timestampValue": timestamp(now_ms() + ttl.as_secs_f64() * 1000.0)}
+        }
+    })
+}
+
+pub(crate) async fn create_room(
+    cfg: &FirestoreConfig,
+    room: &str,
+    ttl: Duration,
+) -> Result<(), CreateError> {
+    let url = format!("{}?currentDocument.exists=false", room_doc_url(cfg, room));
+    match http_fetch_json("PATCH", &url, Some(room_body(ttl)), auth(cfg)).await {
+        Ok(_) => return Ok(()),
+        // The document exists, which only matters if someone still keeps it alive.
+        Err(FetchError::Status(400 | 409)) => {}
+        Err(err) => return Err(CreateError::Fetch(err)),
+    }
+    // A stale room is taken over only if nobody else did since we read it: its `updateTime`
+    // would have changed and Firestore refuses the write.
+    let existing = http_fetch_json("GET", &room_doc_url(cfg, room), None, auth(cfg)).await;
+    let precondition = match existing {
+        Ok(doc) if !room_snapshot(&doc).is_stale(now_ms()) => return Err(CreateError::Taken),
+        Ok(doc) => {
+            let Some(update_time) = doc.get("updateTime").and_then(|value| value.as_str()) else {
+                return Err(CreateError::Taken);
+            };
+            format!(
+                "currentDocument.updateTime={}",
+                String::from(js_sys::encode_uri_component(update_time))
+            )
+        }
+        Err(FetchError::NotFound) => "currentDocument.exists=false".to_string(),
+        Err(err) => return Err(CreateError::Fetch(err)),
+    };
+    let url = format!("{}?{}", room_doc_url(cfg, room), precondition);
+    match http_fetch_json("PATCH", &url, Some(room_body(ttl)), auth(cfg)).await {
+        Ok(_) => Ok(()),
+        Err(FetchError::Status(400 | 409)) => Err(CreateError::Taken),
+        Err(err) => Err(CreateError::Fetch(err)),
+    }
+}
+
+pub(crate) async fn refresh_room(
+    cfg: &FirestoreConfig,
+    room: &str,
+    ttl: Duration,
+) -> Result<(), FetchError> {
+    let url = format!(
+        "{}&currentDocument.exists=true",
+        patch_url(cfg, room, "expiresAt")
+    );
+    let body = json!({
+        "fields": {
+            "expiresAt": {"timestampValue": timestamp(now_ms() + ttl.as_secs_f64() * 1000.0)}
+        }
+    });
+    http_fetch_json("PATCH", &url, Some(body), auth(cfg))
+        .await
+        .map(|_| ())
+}
+
+pub(crate) async fn delete_room(cfg: &FirestoreConfig, room: &str) -> Result<(), FetchError> {
+    http_fetch_json("DELETE", &room_doc_url(cfg, room), None, auth(cfg))
+        .await
+        .map(|_| ())
+}
+
+// `field` is either "offers" or "answers", maps of client ids to SDP.
+async fn write_sdp(
+    cfg: &FirestoreConfig,
+    room: &str,
+    field: &str,
+    client_id: ClientId,
+    sdp: &str,
+) -> Result<(), FetchError> {
+    // Never brings back a deleted room.
+    let url = format!(
+        "{}&currentDocument.exists=true",
+        patch_url(cfg, room, field)
+    );
+    let body = json!({
+        "fields": {
+            field: {"mapValue": {"fields": {
+                client_id.to_string(): {"stringValue": sdp}
+            }}}
+        }
+    });
+    http_fetch_json("PATCH", &url, Some(body), auth(cfg))
+        .await
+        .map(|_| ())
+}
+
+pub(crate) async fn write_offer(
+    cfg: &FirestoreConfig,
+    room: &str,
+    client_id: ClientId,
+    sdp: &str,
+) -> Result<(), FetchError> {
+    write_sdp(cfg, room, "offers", client_id, sdp).await
+}
+
+pub(crate) async fn write_answer(
+    cfg: &FirestoreConfig,
+    room: &str,
+    client_id: ClientId,
+    sdp: &str,
+) -> Result<(), FetchError> {
+    write_sdp(cfg, room, "answers", client_id, sdp).await
+}
+
+// `field` is either "offerCandidates" or "answerCandidates", maps of client ids to arrays of
+// candidates. Appending through a transform keeps concurrent writers from overwriting each other.
+async fn write_candidates(
+    cfg: &FirestoreConfig,
+    room: &str,
+    field: &str,
+    client_id: ClientId,
+    candidates: &[String],
+) -> Result<(), FetchError> {
+    let url = format!(
+        "{}/{}/documents:commit",
+        cfg.base_url.trim_end_matches('/'),
+        database_name(cfg)
+    );
+    let values: Vec<serde_json::Value> = candidates
+        .iter()
+        .map(|candidate| json!({ "stringValue": candidate }))
+        .collect();
+    let body = json!({
+        "writes": [{
+            "transform": {
+                "document": room_doc_name(cfg, room),
+                "fieldTransforms": [{
+                    // Client ids are numbers, which field paths only accept quoted.
+                    "fieldPath": format!("{}.`{}`", field, client_id),
+                    "appendMissingElements": { "values": values }
+                }]
+            },
+            "currentDocument": { "exists": true }
+        }]
+    });
+    http_fetch_json("POST", &url, Some(body), auth(cfg))
+        .await
+        .map(|_| ())
+}
+
+pub(crate) async fn write_offer_candidates(
+    cfg: &FirestoreConfig,
+    room: &str,
+    client_id: ClientId,
+    candidates: &[String],
+) -> Result<(), FetchError> {
+    write_candidates(cfg, room, "offerCandidates", client_id, candidates).await
+}
+
+pub(crate) async fn write_answer_candidates(
+    cfg: &FirestoreConfig,
+    room: &str,
+    client_id: ClientId,
+    candidates: &[String],
+) -> Result<(), FetchError> {
+    write_candidates(cfg, room, "answerCandidates", client_id, candidates).await
+}
+
+fn sdp_map(fields: Option<&serde_json::Value>, field: &str) -> HashMap<ClientId, String> {
+    let Some(entries) = fields
+        .and_then(|fields| fields.get(field))
+        .and_then(|m| m.get("mapValue"))
+        .and_then(|m| m.get("fields"))
+        .and_then(|m| m.as_object())
+    else {
+        return HashMap::new();
+    };
+    entries
+        .iter()
+        .filter_map(|(client_id, value)| {
+            let sdp = value.get("stringValue")?.as_str()?;
+            Some((client_id.parse().ok()?, sdp.to_string()))
+        })
+        .collect()
+}
+
+fn candidate_map(
+    fields: Option<&serde_json::Value>,
+    field: &str,
+) -> HashMap<ClientId, Vec<String>> {
+    let Some(entries) = fields
+        .and_then(|fields| fields.get(field))
+        .and_then(|m| m.get("mapValue"))
+        .and_then(|m| m.get("fields"))
+        .and_then(|m| m.as_object())
+    else {
+        return HashMap::new();
+    };
+    entries
+        .iter()
+        .filter_map(|(client_id, value)| {
+            let values = value.get("arrayValue")?.get("values")?.as_array()?;
+            let candidates = values
+                .iter()
+                .filter_map(|value| Some(value.get("stringValue")?.as_str()?.to_string()))
+                .collect();
+            Some((client_id.parse().ok()?, candidates))
+        })
+        .collect()
+}
+
+pub(crate) async fn read_room(
+    cfg: &FirestoreConfig,
+    room: &str,
+) -> Result<RoomSnapshot, FetchError> {
+    let doc = http_fetch_json("GET", &room_doc_url(cfg, room), None, auth(cfg)).await?;
+    Ok(room_snapshot(&doc))
+}
+
+fn room_snapshot(doc: &serde_json::Value) -> RoomSnapshot {
+    let fields = doc.get("fields");
+    let expires_at_ms = fields
+        .and_then(|fields| fields.get("expiresAt"))
+        .and_then(|value| value.get("timestampValue"))
+        .and_then(|value| value.as_str())
+        .map(js_sys::Date::parse)
+        .filter(|ms| !ms.is_nan());
+    RoomSnapshot {
+        offers: sdp_map(fields, "offers"),
+        answers: sdp_map(fields, "answers"),
+        offer_candidates: candidate_map(fields, "offerCandidates"),
+        answer_candidates: candidate_map(fields, "answerCandidates"),
+        expires_at_ms,
+    }
+}
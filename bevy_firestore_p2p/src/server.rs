@@ -0,0 +1,179 @@
+use bevy_easy_p2p::ClientId;
+use serde_json::json;
+use std::time::Duration;
+
+use crate::signaling::{CreateError, HostedRoom, RoomSnapshot};
+use crate::{FetchAuth, FetchError, http_fetch_json};
+
+/// Where a `p2p_signaling` server is reachable.
+#[derive(Clone, Debug)]
+pub struct SignalingServerConfig {
+    pub base_url: String,
+}
+
+impl Default for SignalingServerConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "http://127.0.0.1:9002".to_string(),
+        }
+    }
+}
+
+impl SignalingServerConfig {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+        }
+    }
+}
+
+fn room_url(cfg: &SignalingServerConfig, room: &str) -> String {
+    format!("{}/rooms/{}", cfg.base_url.trim_end_matches('/'), room)
+}
+
+// Refreshing and deleting the room take the token the server replied with.
+fn host_auth(room: &HostedRoom) -> FetchAuth<'_> {
+    FetchAuth {
+        bearer_token: room.token.as_deref(),
+        ..FetchAuth::default()
+    }
+}
+
+/// Returns the host token of the new room.
+pub(crate) async fn create_room(
+    cfg: &SignalingServerConfig,
+    room: &str,
+    ttl: Duration,
+) -> Result<String, CreateError> {
+    let body = json!({ "ttl_secs": ttl.as_secs() });
+    match http_fetch_json(
+        "POST",
+        &room_url(cfg, room),
+        Some(body),
+        FetchAuth::default(),
+    )
+    .await
+    {
+        Ok(reply) => reply["host_token"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                CreateError::Fetch(FetchError::Network("no host token in the reply".into()))
+            }),
+        Err(FetchError::Status(409)) => Err(CreateError::Taken),
+        Err(err) => Err(CreateError::Fetch(err)),
+    }
+}
+
+pub(crate) async fn refresh_room(
+    cfg: &SignalingServerConfig,
+    room: &HostedRoom,
+    ttl: Duration,
+) -> Result<(), FetchError> {
+    let body = json!({ "ttl_secs": ttl.as_secs() });
+    http_fetch_json(
+        "PUT",
+        &room_url(cfg, &room.code),
+        Some(body),
+        host_auth(room),
+    )
+    .await
+    .map(|_| ())
+}
+
+pub(crate) async fn delete_room(
+    cfg: &SignalingServerConfig,
+    room: &HostedRoom,
+) -> Result<(), FetchError> {
+    http_fetch_json("DELETE", &room_url(cfg, &room.code), None, host_auth(room))
+        .await
+        .map(|_| ())
+}
+
+pub(crate) async fn write_offer(
+    cfg: &SignalingServerConfig,
+    room: &str,
+    client_id: ClientId,
+    sdp: &str,
+) -> Result<(), FetchError> {
+    let url = format!("{}/offers/{}", room_url(cfg, room), client_id);
+    http_fetch_json(
+        "PUT",
+        &url,
+        Some(json!({ "sdp": sdp })),
+        FetchAuth::default(),
+    )
+    .await
+    .map(|_| ())
+}
+
+pub(crate) async fn write_answer(
+    cfg: &SignalingServerConfig,
+    room: &str,
+    client_id: ClientId,
+    sdp: &str,
+) -> Result<(), FetchError> {
+    let url = format!("{}/answers/{}", room_url(cfg, room), client_id);
+    http_fetch_json(
+        "PUT",
+        &url,
+        Some(json!({ "sdp": sdp })),
+        FetchAuth::default(),
+    )
+    .await
+    .map(|_| ())
+}
+
+// `kind` is either "offers" or "answers".
+async fn write_candidates(
+    cfg: &SignalingServerConfig,
+    room: &str,
+    kind: &str,
+    client_id: ClientId,
+    candidates: &[String],
+) -> Result<(), FetchError> {
+    let url = format!("{}/{}/{}/candidates", room_url(cfg, room), kind, client_id);
+    http_fetch_json(
+        "POST",
+        &url,
+        Some(json!({ "candidates": candidates })),
+        FetchAuth::default(),
+    )
+    .await
+    .map(|_| ())
+}
+
+pub(crate) async fn write_offer_candidates(
+    cfg: &SignalingServerConfig,
+    room: &str,
+    client_id: ClientId,
+    candidates: &[String],
+) -> Result<(), FetchError> {
+    write_candidates(cfg, room, "offers", client_id, candidates).await
+}
+
+pub(crate) async fn write_answer_candidates(
+    cfg: &SignalingServerConfig,
+    room: &str,
+    client_id: ClientId,
+    candidates: &[String],
+) -> Result<(), FetchError> {
+    write_candidates(cfg, room, "answers", client_id, candidates).await
+}
+
+pub(crate) async fn read_room(
+    cfg: &SignalingServerConfig,
+    room: &str,
+) -> Result<RoomSnapshot, FetchError> {
+    let room = http_fetch_json("GET", &room_url(cfg, room), None, FetchAuth::default()).await?;
+    Ok(RoomSnapshot {
+        offers: serde_json::from_value(room["offers"].clone()).unwrap_or_default(),
+        answers: serde_json::from_value(room["answers"].clone()).unwrap_or_default(),
+        offer_candidates: serde_json::from_value(room["offer_candidates"].clone())
+            .unwrap_or_default(),
+        answer_candidates: serde_json::from_value(room["answer_candidates"].clone())
+            .unwrap_or_default(),
+        // The server forgets expired rooms itself.
+        expires_at_ms: None,
+    })
+}
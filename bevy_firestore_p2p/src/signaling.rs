@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+use bevy_easy_p2p::ClientId;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::{FetchError, FirestoreConfig, SignalingServerConfig, firestore, server};
+
+/// Where rooms and the offers and answers exchanged to join them are stored.
+#[derive(Clone, Debug)]
+pub enum SignalingBackend {
+    Firestore(FirestoreConfig),
+    /// A `p2p_signaling` server.
+    Server(SignalingServerConfig),
+}
+
+impl Default for SignalingBackend {
+    fn default() -> Self {
+        SignalingBackend::Firestore(FirestoreConfig::default())
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct SignalingConfig {
+    pub backend: SignalingBackend,
+    // How long a client keeps polling for a room that does not exist before giving up.
+    pub room_not_found_timeout: Duration,
+    /// Rooms their host stopped keeping alive for this long are considered abandoned. Hosts
+    /// refresh theirs several times per period.
+    pub room_ttl: Duration,
+}
+
+impl Default for SignalingConfig {
+    fn default() -> Self {
+        Self {
+            backend: SignalingBackend::default(),
+            room_not_found_timeout: Duration::from_secs(3),
+            room_ttl: Duration::from_secs(120),
+        }
+    }
+}
+
+impl SignalingConfig {
+    pub fn with_backend(mut self, backend: SignalingBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn with_room_not_found_timeout(mut self, room_not_found_timeout: Duration) -> Self {
+        self.room_not_found_timeout = room_not_found_timeout;
+        self
+    }
+
+    pub fn with_room_ttl(mut self, room_ttl: Duration) -> Self {
+        self.room_ttl = room_ttl;
+        self
+    }
+}
+
+// What a room holds, whichever backend stores it.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RoomSnapshot {
+    pub offers: HashMap<ClientId, String>,
+    pub answers: HashMap<ClientId, String>,
+    // ICE candidates of each client's offer and of the host's answer to it, in the order they
+    // were written.
+    pub offer_candidates: HashMap<ClientId, Vec<String>>,
+    pub answer_candidates: HashMap<ClientId, Vec<String>>,
+    // Unknown when the backend drops expired rooms by itself.
+    pub expires_at_ms: Option<f64>,
+}
+
+impl RoomSnapshot {
+    pub(crate) fn is_stale(&self, now_ms: f64) -> bool {
+        self.expires_at_ms
+            .is_some_and(|expires_at_ms| expires_at_ms <= now_ms)
+    }
+}
+
+// A room this peer created, with the token its backend wants back to refresh or delete it.
+#[derive(Clone, Debug)]
+pub(crate) struct HostedRoom {
+    pub code: String,
+    pub token: Option<String>,
+}
+
+pub(crate) enum CreateError {
+    // A live room already uses the code.
+    Taken,
+    Fetch(FetchError),
+}
+
+// Results of signaling requests, handed back to the transport through `SIGNALING_INBOX`.
+pub(crate) enum SignalingReply {
+    Created(HostedRoom),
+    CreateFailed(String),
+    NotFound,
+    Room(RoomSnapshot),
+    ReadFailed,
+}
+
+impl SignalingBackend {
+    // Creates the room unless a live one uses the code, replacing abandoned ones.
+    pub(crate) async fn create_room(
+        &self,
+        room: &str,
+        ttl: Duration,
+    ) -> Result<HostedRoom, CreateError> {
+        let token = match self {
+            SignalingBackend::Firestore(cfg) => {
+                firestore::create_room(cfg, room, ttl).await.map(|_| None)
+            }
+            SignalingBackend::Server(cfg) => server::create_room(cfg, room, ttl).await.map(Some),
+        }?;
+        Ok(HostedRoom {
+            code: room.to_string(),
+            token,
+        })
+    }
+
+    // Keeps the room from being considered abandoned for another `ttl`.
+    pub(crate) async fn refresh_room(&self, room: &HostedRoom, ttl: Duration) {
+        let result = match self {
+            SignalingBackend::Firestore(cfg) => firestore::refresh_room(cfg, &room.code, ttl).await,
+            SignalingBackend::Server(cfg) => server::refresh_room(cfg, room, ttl).await,
+        };
+        if let Err(err) = result {
+            warn!("Failed to refresh room '{}': {}", room.code, err);
+        }
+    }
+
+    pub(crate) async fn delete_room(&self, room: &HostedRoom) {
+        let result = match self {
+            SignalingBackend::Firestore(cfg) => firestore::delete_room(cfg, &room.code).await,
+            SignalingBackend::Server(cfg) => server::delete_room(cfg, room).await,
+        };
+        if let Err(err) = result {
+            warn!("Failed to delete room '{}': {}", room.code, err);
+        }
+    }
+
+    pub(crate) async fn write_offer(&self, room: &str, client_id: ClientId, sdp: &str) {
+        let result = match self {
+            SignalingBackend::Firestore(cfg) => {
+                firestore::write_offer(cfg, room, client_id, sdp).await
+            }
+            SignalingBackend::Server(cfg) => server::write_offer(cfg, room, client_id, sdp).await,
+        };
+        if let Err(err) = result {
+            warn!("Failed to write signaling data for {}: {}", client_id, err);
+        }
+    }
+
+    pub(crate) async fn write_answer(&self, room: &str, client_id: ClientId, sdp: &str) {
+        let result = match self {
+            SignalingBackend::Firestore(cfg) => {
+                firestore::write_answer(cfg, room, client_id, sdp).await
+            }
+            SignalingBackend::Server(cfg) => server::write_answer(cfg, room, client_id, sdp).await,
+        };
+        if let Err(err) = result {
+            warn!("Failed to write signaling data for {}: {}", client_id, err);
+        }
+    }
+
+    pub(crate) async fn write_offer_candidates(
+        &self,
+        room: &str,
+        client_id: ClientId,
+        candidates: &[String],
+    ) {
+        let result = match self {
+            SignalingBackend::Firestore(cfg) => {
+                firestore::write_offer_candidates(cfg, room, client_id, candidates).await
+            }
+            SignalingBackend::Server(cfg) => {
+                server::write_offer_candidates(cfg, room, client_id, candidates).await
+            }
+        };
+        if let Err(err) = result {
+            warn!("Failed to write ICE candidates for {}: {}", client_id, err);
+        }
+    }
+
+    pub(crate) async fn write_answer_candidates(
+        &self,
+        room: &str,
+        client_id: ClientId,
+        candidates: &[String],
+    ) {
+        let result = match self {
+            SignalingBackend::Firestore(cfg) => {
+                firestore::write_answer_candidates(cfg, room, client_id, candidates).await
+            }
+            SignalingBackend::Server(cfg) => {
+                server::write_answer_candidates(cfg, room, client_id, candidates).await
+            }
+        };
+        if let Err(err) = result {
+            warn!("Failed to write ICE candidates for {}: {}", client_id, err);
+        }
+    }
+
+    pub(crate) async fn read_room(&self, room: &str) -> Result<RoomSnapshot, FetchError> {
+        match self {
+            SignalingBackend::Firestore(cfg) => firestore::read_room(cfg, room).await,
+            SignalingBackend::Server(cfg) => server::read_room(cfg, room).await,
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use bevy::ecs::message::MessageCursor;
+use bevy::prelude::*;
+use bevy_easy_p2p::{
+    ClientId, ExitReason, LobbyFailureReason, NetworkedId, P2PTransport, SendTarget,
+    TransportError, TransportEvent,
+};
+use bevy_webrtc::{
+    AddIceCandidate, CloseAllConnections, CloseConnection, ConnectionClosed, ConnectionId,
+    ConnectionOpen, CreateAnswer, CreateOffer, IncomingData, LocalIceCandidate, LocalSdpReady,
+    SendData, SetRemote, WebRtcPlugin,
+};
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen_futures::spawn_local;
+
+use crate::signaling::{CreateError, HostedRoom, RoomSnapshot, SignalingReply};
+use crate::{
+    FetchError, SIGNALING_INBOX, SignalingConfig, gen_client_id_num, generate_room_code, now_ms,
+};
+
+// Largest message that every browser's data channel implementation accepts.
+const MAX_DATA_CHANNEL_MESSAGE_SIZE: usize = 16 * 1024;
+// Room codes drawn before giving up on finding a free one.
+const CREATE_ATTEMPTS: usize = 5;
+// Room polling, quicker while offers, answers and candidates are still being exchanged.
+const POLL_INTERVAL_MS: f64 = 500.0;
+const NEGOTIATING_POLL_INTERVAL_MS: f64 = 150.0;
+
+// Everything tied to the current room, dropped as a whole when leaving it.
+#[derive(Default)]
+struct Session {
+    room_code: String,
+    is_host: bool,
+    answered_clients: HashSet<ClientId>,
+    joined_clients: HashSet<ClientId>,
+    client_id: Option<ClientId>,
+    client_answer_applied: bool,
+    offer_conn: Option<ConnectionId>,
+    host_connection_to_client_id: HashMap<ConnectionId, ClientId>,
+    // How many of each peer's ICE candidates were handed to its connection. Clients only track
+    // the host's, under their own id.
+    applied_candidates: HashMap<ClientId, usize>,
+    open_connections: HashSet<ConnectionId>,
+    client_join_pending: bool,
+    // Track if client has emitted LobbyJoined/LobbyEntered
+    client_emitted_join: bool,
+    in_flight: bool,
+    next_allowed_fetch_at_ms: f64,
+    not_found_logged: bool,
+    room_exists: bool,
+    join_started_at_ms: f64,
+    // The host is still looking for a free room code.
+    creating: bool,
+    // Handed out by the backend when the room was created, if it uses them.
+    host_token: Option<String>,
+    next_refresh_at_ms: f64,
+}
+
+impl Session {
+    fn hosted_room(&self) -> HostedRoom {
+        HostedRoom {
+            code: self.room_code.clone(),
+            token: self.host_token.clone(),
+        }
+    }
+}
+
+/// WebRTC data channels, signaled through rooms stored by the configured `SignalingBackend`.
+#[derive(Default)]
+pub struct FirestoreWebRtcTransport {
+    session: Session,
+    next_connection_id: u64,
+    pending_events: Vec<TransportEvent>,
+    local_sdp_cursor: MessageCursor<LocalSdpReady>,
+    local_candidate_cursor: MessageCursor<LocalIceCandidate>,
+    open_cursor: MessageCursor<ConnectionOpen>,
+    closed_cursor: MessageCursor<ConnectionClosed>,
+    incoming_cursor: MessageCursor<IncomingData>,
+}
+
+impl FirestoreWebRtcTransport {
+    fn allocate_connection(&mut self) -> ConnectionId {
+        self.next_connection_id += 1;
+        ConnectionId(self.next_connection_id)
+    }
+
+    fn connection_of(&self, client_id: ClientId) -> Option<ConnectionId> {
+        self.session
+            .host_connection_to_client_id
+            .iter()
+            .find(|(_, cid)| **cid == client_id)
+            .map(|(conn, _)| *conn)
+    }
+
+    fn forget_client(&mut self, conn: ConnectionId) {
+        let Some(client_id) = self.session.host_connection_to_client_id.remove(&conn) else {
+            return;
+        };
+        self.session.answered_clients.remove(&client_id);
+        self.session.applied_candidates.remove(&client_id);
+        if self.session.joined_clients.remove(&client_id) {
+            self.pending_events
+                .push(TransportEvent::PeerDisconnected(client_id));
+        }
+    }
+
+    fn handle_local_sdp(&mut self, world: &mut World) {
+        let ready: Vec<(ConnectionId, String)> = self
+            .local_sdp_cursor
+            .read(world.resource::<Messages<LocalSdpReady>>())
+            .map(|LocalSdpReady { id, sdp }| (*id, sdp.clone()))
+            .collect();
+        let backend = world.resource::<SignalingConfig>().backend.clone();
+        let sig = &mut self.session;
+        for (id, sdp) in ready {
+            if sig.room_code.is_empty() {
+                continue;
+            }
+            let room = sig.room_code.clone();
+            let backend = backend.clone();
+            if !sig.is_host {
+                if sig.offer_conn.is_none() {
+                    sig.offer_conn = Some(id);
+                }
+                if let Some(cid) = sig.client_id {
+                    spawn_local(async move {
+                        backend.write_offer(&room, cid, &sdp).await;
+                    });
+                }
+            } else if let Some(client_id) = sig.host_connection_to_client_id.get(&id).copied() {
+                spawn_local(async move {
+                    backend.write_answer(&room, client_id, &sdp).await;
+                });
+            }
+        }
+    }
+
+    // Candidates gathered during a frame go out together, one write per connection.
+    fn handle_local_candidates(&mut self, world: &mut World) {
+        let mut gathered: HashMap<ConnectionId, Vec<String>> = HashMap::new();
+        for LocalIceCandidate { id, candidate } in self
+            .local_candidate_cursor
+            .read(world.resource::<Messages<LocalIceCandidate>>())
+        {
+            gathered.entry(*id).or_default().push(candidate.clone());
+        }
+        let sig = &self.session;
+        if gathered.is_empty() || sig.room_code.is_empty() {
+            return;
+        }
+        let backend = world.resource::<SignalingConfig>().backend.clone();
+        for (id, candidates) in gathered {
+            let room = sig.room_code.clone();
+            let backend = backend.clone();
+            if !sig.is_host {
+                if let Some(cid) = sig.client_id
+                    && sig.offer_conn == Some(id)
+                {
+                    spawn_local(async move {
+                        backend
+                            .write_offer_candidates(&room, cid, &candidates)
+                            .await;
+                    });
+                }
+            } else if let Some(client_id) = sig.host_connection_to_client_id.get(&id).copied() {
+                spawn_local(async move {
+                    backend
+                        .write_answer_candidates(&room, client_id, &candidates)
+                        .await;
+                });
+            }
+        }
+    }
+
+    fn handle_connection_events(&mut self, world: &mut World) {
+        let opened: Vec<ConnectionId> = self
+            .open_cursor
+            .read(world.resource::<Messages<ConnectionOpen>>())
+            .map(|ConnectionOpen(id)| *id)
+            .collect();
+        for id in opened {
+            let sig = &mut self.session;
+            if sig.is_host {
+                if let Some(client_id) = sig.host_connection_to_client_id.get(&id).copied() {
+                    sig.open_connections.insert(id);
+                    if sig.joined_clients.insert(client_id) {
+                        self.pending_events
+                            .push(TransportEvent::PeerConnected(client_id));
+                    }
+                }
+            } else if sig.offer_conn == Some(id) && !sig.client_emitted_join {
+                sig.open_connections.insert(id);
+                sig.client_emitted_join = true;
+                let room = sig.room_code.clone();
+                self.pending_events
+                    .push(TransportEvent::LobbyJoined(room.clone()));
+                self.pending_events.push(TransportEvent::LobbyEntered(room));
+            }
+        }
+
+        let closed: Vec<ConnectionId> = self
+            .closed_cursor
+            .read(world.resource::<Messages<ConnectionClosed>>())
+            .map(|ConnectionClosed(id)| *id)
+            .collect();
+        for id in closed {
+            // Connections we already dropped ourselves (kick, exit) are not news.
+            if !self.session.open_connections.remove(&id) {
+                continue;
+            }
+            if self.session.is_host {
+                self.forget_client(id);
+            } else {
+                self.pending_events
+                    .push(TransportEvent::LobbyExited(ExitReason::Disconnected));
+            }
+        }
+
+        let incoming: Vec<(ConnectionId, String)> = self
+            .incoming_cursor
+            .read(world.resource::<Messages<IncomingData>>())
+            .map(|IncomingData { id, text }| (*id, text.clone()))
+            .collect();
+        for (id, text) in incoming {
+            let from = if self.session.is_host {
+                let Some(client_id) = self.session.host_connection_to_client_id.get(&id) else {
+                    continue;
+                };
+                NetworkedId::ClientId(*client_id)
+            } else {
+                NetworkedId::Host
+            };
+            self.pending_events.push(TransportEvent::Received {
+                from,
+                payload: text.into_bytes(),
+            });
+        }
+    }
+
+    fn signaling_pump(&mut self, world: &mut World) {
+        let cfg = world.resource::<SignalingConfig>().clone();
+
+        let mut replies: Vec<SignalingReply> = Vec::new();
+        SIGNALING_INBOX.with(|inbox| {
+            let mut buf = inbox.borrow_mut();
+            replies.extend(buf.drain(..));
+        });
+        if !replies.is_empty() {
+            self.session.in_flight = false;
+        }
+        for reply in replies {
+            match reply {
+                SignalingReply::Created(room) if !self.session.creating => {
+                    // The host left before the room was ready.
+                    let backend = cfg.backend.clone();
+                    spawn_local(async move { backend.delete_room(&room).await });
+                }
+                SignalingReply::Created(room) => {
+                    let sig = &mut self.session;
+                    sig.creating = false;
+                    sig.room_code = room.code.clone();
+                    sig.host_token = room.token;
+                    sig.room_exists = true;
+                    sig.next_refresh_at_ms = now_ms() + refresh_interval_ms(&cfg);
+                    self.pending_events
+                        .push(TransportEvent::LobbyCreated(room.code.clone()));
+                    self.pending_events
+                        .push(TransportEvent::LobbyEntered(room.code));
+                }
+                SignalingReply::CreateFailed(err) => {
+                    if !self.session.creating {
+                        continue;
+                    }
+                    self.session.creating = false;
+                    self.pending_events.push(TransportEvent::CreateFailed(
+                        LobbyFailureReason::TransportError(err),
+                    ));
+                    return;
+                }
+                // Left over from a room we already left.
+                _ if self.session.room_code.is_empty() => {}
+                SignalingReply::NotFound => {
+                    let sig = &mut self.session;
+                    let now = now_ms();
+                    if !sig.is_host
+                        && now - sig.join_started_at_ms
+                            > cfg.room_not_found_timeout.as_secs_f64() * 1000.0
+                    {
+                        self.pending_events
+                            .push(TransportEvent::JoinFailed(LobbyFailureReason::RoomNotFound));
+                        sig.room_code.clear();
+                        return;
+                    }
+                    if sig.next_allowed_fetch_at_ms < now + 1500.0 {
+                        sig.next_allowed_fetch_at_ms = now + 1500.0;
+                    }
+                    if !sig.not_found_logged {
+                        info!(
+                            "Room '{}' not found yet. Waiting for host...",
+                            sig.room_code
+                        );
+                        sig.not_found_logged = true;
+                    }
+                }
+                SignalingReply::Room(room) => {
+                    self.apply_room(world, &room);
+                    // If we are a client waiting to join and the room exists, create offer only.
+                    // Delay emitting LobbyJoined/LobbyEntered until data channel opens.
+                    if self.session.client_join_pending && !self.session.is_host {
+                        let id = self.allocate_connection();
+                        self.session.client_join_pending = false;
+                        self.session.client_emitted_join = false;
+                        self.session.offer_conn = Some(id);
+                        world.write_message(CreateOffer { id });
+                    }
+                }
+                SignalingReply::ReadFailed => {}
+            }
+        }
+
+        let now = now_ms();
+        let sig = &mut self.session;
+        if sig.room_code.is_empty() {
+            return;
+        }
+        if sig.is_host && sig.room_exists && now >= sig.next_refresh_at_ms {
+            sig.next_refresh_at_ms = now + refresh_interval_ms(&cfg);
+            let backend = cfg.backend.clone();
+            let room = sig.hosted_room();
+            let ttl = cfg.room_ttl;
+            spawn_local(async move { backend.refresh_room(&room, ttl).await });
+        }
+        if sig.in_flight || now < sig.next_allowed_fetch_at_ms {
+            return;
+        }
+        if sig.is_host && !sig.room_exists {
+            return;
+        }
+        let negotiating = if sig.is_host {
+            sig.answered_clients.len() > sig.joined_clients.len()
+        } else {
+            !sig.client_emitted_join
+        };
+        sig.in_flight = true;
+        sig.next_allowed_fetch_at_ms = now
+            + if negotiating {
+                NEGOTIATING_POLL_INTERVAL_MS
+            } else {
+                POLL_INTERVAL_MS
+            };
+        let room = sig.room_code.clone();
+        spawn_local(async move {
+            let reply = match cfg.backend.read_room(&room).await {
+                // Its host is gone without cleaning up.
+                Ok(snapshot) if snapshot.is_stale(now_ms()) => SignalingReply::NotFound,
+                Ok(snapshot) => SignalingReply::Room(snapshot),
+                Err(FetchError::NotFound) => SignalingReply::NotFound,
+                Err(err) => {
+                    warn!("Failed to read room '{}': {}", room, err);
+                    SignalingReply::ReadFailed
+                }
+            };
+            SIGNALING_INBOX.with(|inbox| inbox.borrow_mut().push(reply));
+        });
+    }
+
+    fn apply_room(&mut self, world: &mut World, room: &RoomSnapshot) {
+        if self.session.is_host {
+            for (client_id, sdp) in room.offers.iter() {
+                if self.session.answered_clients.contains(client_id) {
+                    continue;
+                }
+                let id = self.allocate_connection();
+                world.write_message(CreateAnswer {
+                    id,
+                    remote_sdp: sdp.clone(),
+                });
+                self.session.answered_clients.insert(*client_id);
+                self.session
+                    .host_connection_to_client_id
+                    .insert(id, *client_id);
+            }
+            let connections: Vec<(ConnectionId, ClientId)> = self
+                .session
+                .host_connection_to_client_id
+                .iter()
+                .map(|(conn, client_id)| (*conn, *client_id))
+                .collect();
+            for (conn, client_id) in connections {
+                if let Some(candidates) = room.offer_candidates.get(&client_id) {
+                    self.apply_candidates(world, conn, client_id, candidates);
+                }
+            }
+        } else if let Some(cid) = self.session.client_id {
+            if !self.session.client_answer_applied
+                && let Some(sdp) = room.answers.get(&cid)
+            {
+                let target = match self.session.offer_conn {
+                    Some(id) => id,
+                    None => self.allocate_connection(),
+                };
+                world.write_message(SetRemote {
+                    id: target,
+                    sdp: sdp.clone(),
+                });
+                self.session.client_answer_applied = true;
+            }
+            if let Some(conn) = self.session.offer_conn
+                && let Some(candidates) = room.answer_candidates.get(&cid)
+            {
+                self.apply_candidates(world, conn, cid, candidates);
+            }
+        }
+    }
+
+    // Hands `peer`'s candidates that its connection has not seen yet over to it.
+    fn apply_candidates(
+        &mut self,
+        world: &mut World,
+        conn: ConnectionId,
+        peer: ClientId,
+        candidates: &[String],
+    ) {
+        let applied = self.session.applied_candidates.entry(peer).or_default();
+        for candidate in candidates.iter().skip(*applied) {
+            world.write_message(AddIceCandidate {
+                id: conn,
+                candidate: candidate.clone(),
+            });
+        }
+        *applied = (*applied).max(candidates.len());
+    }
+}
+
+// Hosts refresh their room well before it could be taken for abandoned.
+fn refresh_interval_ms(cfg: &SignalingConfig) -> f64 {
+    cfg.room_ttl.as_secs_f64() * 1000.0 / 4.0
+}
+
+impl P2PTransport for FirestoreWebRtcTransport {
+    fn setup(app: &mut App) {
+        app.init_resource::<SignalingConfig>()
+            .add_plugins(WebRtcPlugin);
+    }
+
+    fn max_message_size(&self) -> usize {
+        MAX_DATA_CHANNEL_MESSAGE_SIZE
+    }
+
+    fn create_lobby(&mut self, world: &mut World) -> Result<(), TransportError> {
+        self.session = Session {
+            is_host: true,
+            creating: true,
+            ..default()
+        };
+        let cfg = world.resource::<SignalingConfig>().clone();
+        spawn_local(async move {
+            let mut reply = SignalingReply::CreateFailed("no free room code".to_string());
+            for _ in 0..CREATE_ATTEMPTS {
+                let room = generate_room_code();
+                match cfg.backend.create_room(&room, cfg.room_ttl).await {
+                    Ok(hosted) => {
+                        reply = SignalingReply::Created(hosted);
+                        break;
+                    }
+                    Err(CreateError::Taken) => continue,
+                    Err(CreateError::Fetch(err)) => {
+                        reply = SignalingReply::CreateFailed(err.to_string());
+                        break;
+                    }
+                }
+            }
+            SIGNALING_INBOX.with(|inbox| inbox.borrow_mut().push(reply));
+        });
+        Ok(())
+    }
+
+    fn join_lobby(&mut self, _world: &mut World, code: &str) -> Result<(), TransportError> {
+        self.session = Session {
+            room_code: code.to_string(),
+            client_id: Some(gen_client_id_num()),
+            client_join_pending: true,
+            join_started_at_ms: now_ms(),
+            ..default()
+        };
+        Ok(())
+    }
+
+    fn exit_lobby(&mut self, world: &mut World) {
+        world.write_message(CloseAllConnections);
+        let backend = world.resource::<SignalingConfig>().backend.clone();
+        let mut abandoned: Vec<HostedRoom> = SIGNALING_INBOX.with(|inbox| {
+            inbox
+                .borrow_mut()
+                .drain(..)
+                .filter_map(|reply| match reply {
+                    SignalingReply::Created(room) => Some(room),
+                    _ => None,
+                })
+                .collect()
+        });
+        if self.session.is_host && self.session.room_exists {
+            abandoned.push(self.session.hosted_room());
+        }
+        for room in abandoned {
+            let backend = backend.clone();
+            spawn_local(async move { backend.delete_room(&room).await });
+        }
+        self.session = Session::default();
+        self.pending_events.clear();
+    }
+
+    fn kick(&mut self, world: &mut World, client_id: ClientId) {
+        if !self.session.is_host {
+            return;
+        }
+        let Some(conn) = self.connection_of(client_id) else {
+            return;
+        };
+        world.write_message(CloseConnection { id: conn });
+        self.session.open_connections.remove(&conn);
+        self.forget_client(conn);
+    }
+
+    fn send(
+        &mut self,
+        world: &mut World,
+        target: SendTarget,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        let text = std::str::from_utf8(payload).map_err(|_| TransportError::InvalidPayload)?;
+        let sig = &self.session;
+        let targets: Vec<ConnectionId> = match target {
+            SendTarget::Host => {
+                if sig.is_host {
+                    return Err(TransportError::NotConnected);
+                }
+                sig.offer_conn
+                    .filter(|id| sig.open_connections.contains(id))
+                    .into_iter()
+                    .collect()
+            }
+            SendTarget::All => sig.open_connections.iter().copied().collect(),
+            SendTarget::Client(client_id) => vec![
+                self.connection_of(client_id)
+                    .ok_or(TransportError::UnknownPeer(client_id))?,
+            ],
+            SendTarget::AllExcept(client_id) => sig
+                .host_connection_to_client_id
+                .iter()
+                .filter(|(id, cid)| **cid != client_id && sig.open_connections.contains(id))
+                .map(|(id, _)| *id)
+                .collect(),
+        };
+        if targets.is_empty() && target == SendTarget::Host {
+            return Err(TransportError::NotConnected);
+        }
+        for id in targets {
+            world.write_message(SendData {
+                id,
+                text: text.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self, world: &mut World, events: &mut Vec<TransportEvent>) {
+        self.handle_local_sdp(world);
+        self.handle_local_candidates(world);
+        self.handle_connection_events(world);
+        self.signaling_pump(world);
+        events.append(&mut self.pending_events);
+    }
+}
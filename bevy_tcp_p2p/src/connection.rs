@@ -0,0 +1,101 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+
+// Frames are a little-endian u32 length followed by that many bytes of payload.
+const HEADER_SIZE: usize = 4;
+// Largest payload a peer may announce before the connection is dropped.
+pub(crate) const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// What a peer sent since the last read.
+pub(crate) enum Frame {
+    Payload(Vec<u8>),
+    /// Sent by the host right before closing a kicked client's connection.
+    Kick,
+}
+
+// A non-blocking stream and the bytes waiting to go in and out of it.
+pub(crate) struct Connection {
+    stream: TcpStream,
+    inbox: Vec<u8>,
+    outbox: Vec<u8>,
+}
+
+impl Connection {
+    pub(crate) fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            inbox: Vec::new(),
+            outbox: Vec::new(),
+        })
+    }
+
+    pub(crate) fn queue(&mut self, payload: &[u8]) {
+        self.outbox
+            .extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        self.outbox.extend_from_slice(payload);
+    }
+
+    pub(crate) fn queue_kick(&mut self) {
+        self.outbox.extend_from_slice(&0u32.to_le_bytes());
+    }
+
+    /// Writes as much of the queued bytes as the socket takes without blocking.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        while !self.outbox.is_empty() {
+            match self.stream.write(&self.outbox) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outbox.drain(..written);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads everything available and appends the complete frames to `frames`. Fails once the
+    /// peer closed the connection or sent something that is not a frame.
+    pub(crate) fn read(&mut self, frames: &mut Vec<Frame>) -> io::Result<()> {
+        let mut buffer = [0; 16 * 1024];
+        let result = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.inbox.extend_from_slice(&buffer[..read]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            }
+        };
+        // Frames that arrived before the connection closed still count.
+        let mut start = 0;
+        while self.inbox.len() - start >= HEADER_SIZE {
+            let header: [u8; HEADER_SIZE] =
+                self.inbox[start..start + HEADER_SIZE].try_into().unwrap();
+            let size = u32::from_le_bytes(header) as usize;
+            if size > MAX_FRAME_SIZE {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            if self.inbox.len() - start < HEADER_SIZE + size {
+                break;
+            }
+            let payload = &self.inbox[start + HEADER_SIZE..start + HEADER_SIZE + size];
+            frames.push(if size == 0 {
+                Frame::Kick
+            } else {
+                Frame::Payload(payload.to_vec())
+            });
+            start += HEADER_SIZE + size;
+        }
+        self.inbox.drain(..start);
+        result
+    }
+
+    pub(crate) fn close(mut self) {
+        let _ = self.flush();
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+mod connection;
+mod transport;
+
+pub use transport::TcpTransport;
+
+pub const DEFAULT_PORT: u16 = 7878;
+
+#[derive(Resource, Clone, Debug)]
+pub struct TcpConfig {
+    /// Where a host listens for clients.
+    pub bind_address: String,
+    /// Lobby code handed out by a host, which is the address clients connect to. Defaults to
+    /// the address of the interface the host reaches the internet through.
+    pub advertised_address: Option<String>,
+    pub connect_timeout: Duration,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: format!("0.0.0.0:{}", DEFAULT_PORT),
+            advertised_address: None,
+            connect_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl TcpConfig {
+    pub fn with_bind_address(mut self, bind_address: impl Into<String>) -> Self {
+        self.bind_address = bind_address.into();
+        self
+    }
+
+    pub fn with_advertised_address(mut self, advertised_address: impl Into<String>) -> Self {
+        self.advertised_address = Some(advertised_address.into());
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+}
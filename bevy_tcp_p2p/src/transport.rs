@@ -0,0 +1,289 @@
+use bevy::prelude::*;
+use bevy_easy_p2p::net::{code_to_address, routable_address};
+use bevy_easy_p2p::{
+    ClientId, ExitReason, LobbyFailureReason, NetworkedId, P2PTransport, SendTarget,
+    TransportError, TransportEvent,
+};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use crate::connection::{Connection, Frame, MAX_FRAME_SIZE};
+use crate::{DEFAULT_PORT, TcpConfig};
+
+#[derive(Default)]
+enum Role {
+    #[default]
+    Idle,
+    Host {
+        listener: TcpListener,
+        clients: HashMap<ClientId, Connection>,
+        next_client_id: ClientId,
+    },
+    // The connection is opened on a helper thread so a slow host does not stall the frame.
+    Connecting {
+        code: String,
+        attempt: Receiver<Result<TcpStream, LobbyFailureReason>>,
+    },
+    Client(Connection),
+}
+
+/// Plain TCP connections to a host reachable at the lobby code, for native builds.
+#[derive(Default)]
+pub struct TcpTransport {
+    role: Role,
+    pending_events: Vec<TransportEvent>,
+}
+
+impl TcpTransport {
+    fn close(&mut self) {
+        match std::mem::take(&mut self.role) {
+            Role::Idle | Role::Connecting { .. } => {}
+            Role::Host { clients, .. } => clients.into_values().for_each(Connection::close),
+            Role::Client(host) => host.close(),
+        }
+    }
+
+    fn connect(config: &TcpConfig, code: &str) -> Result<TcpStream, LobbyFailureReason> {
+        let address = code_to_address(code, DEFAULT_PORT);
+        let not_found = |err: std::io::Error| {
+            debug!("Could not reach {}: {}", address, err);
+            LobbyFailureReason::RoomNotFound
+        };
+        let socket_address = address
+            .to_socket_addrs()
+            .map_err(not_found)?
+            .next()
+            .ok_or(LobbyFailureReason::RoomNotFound)?;
+        TcpStream::connect_timeout(&socket_address, config.connect_timeout).map_err(|err| match err
+            .kind()
+        {
+            ErrorKind::ConnectionRefused | ErrorKind::TimedOut => not_found(err),
+            _ => LobbyFailureReason::TransportError(err.to_string()),
+        })
+    }
+
+    fn poll_host(&mut self) {
+        let Role::Host {
+            listener,
+            clients,
+            next_client_id,
+        } = &mut self.role
+        else {
+            return;
+        };
+        loop {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    let connection = match Connection::new(stream) {
+                        Ok(connection) => connection,
+                        Err(err) => {
+                            warn!("Dropping connection from {}: {}", address, err);
+                            continue;
+                        }
+                    };
+                    *next_client_id += 1;
+                    debug!("Client {} connected from {}", next_client_id, address);
+                    clients.insert(*next_client_id, connection);
+                    self.pending_events
+                        .push(TransportEvent::PeerConnected(*next_client_id));
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("Could not accept a client: {}", err);
+                    break;
+                }
+            }
+        }
+        let mut departed = Vec::new();
+        for (client_id, connection) in clients.iter_mut() {
+            let mut frames = Vec::new();
+            let result = connection
+                .flush()
+                .and_then(|_| connection.read(&mut frames));
+            for frame in frames {
+                if let Frame::Payload(payload) = frame {
+                    self.pending_events.push(TransportEvent::Received {
+                        from: NetworkedId::ClientId(*client_id),
+                        payload,
+                    });
+                }
+            }
+            if result.is_err() {
+                departed.push(*client_id);
+            }
+        }
+        for client_id in departed {
+            if let Some(connection) = clients.remove(&client_id) {
+                connection.close();
+            }
+            self.pending_events
+                .push(TransportEvent::PeerDisconnected(client_id));
+        }
+    }
+
+    fn poll_connecting(&mut self) {
+        let Role::Connecting { code, attempt } = &self.role else {
+            return;
+        };
+        let connection = match attempt.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(LobbyFailureReason::TransportError(
+                "the connection attempt was abandoned".to_string(),
+            )),
+            Ok(stream) => stream.and_then(|stream| {
+                Connection::new(stream)
+                    .map_err(|err| LobbyFailureReason::TransportError(err.to_string()))
+            }),
+        };
+        let code = code.clone();
+        match connection {
+            Ok(connection) => {
+                self.role = Role::Client(connection);
+                self.pending_events
+                    .push(TransportEvent::LobbyJoined(code.clone()));
+                self.pending_events.push(TransportEvent::LobbyEntered(code));
+            }
+            Err(reason) => {
+                self.role = Role::Idle;
+                self.pending_events.push(TransportEvent::JoinFailed(reason));
+            }
+        }
+    }
+
+    fn poll_client(&mut self) {
+        let Role::Client(host) = &mut self.role else {
+            return;
+        };
+        let mut frames = Vec::new();
+        let result = host.flush().and_then(|_| host.read(&mut frames));
+        let mut exit = result.is_err().then_some(ExitReason::Disconnected);
+        for frame in frames {
+            match frame {
+                Frame::Payload(payload) => self.pending_events.push(TransportEvent::Received {
+                    from: NetworkedId::Host,
+                    payload,
+                }),
+                Frame::Kick => exit = Some(ExitReason::Kicked),
+            }
+        }
+        if let Some(reason) = exit {
+            self.close();
+            self.pending_events
+                .push(TransportEvent::LobbyExited(reason));
+        }
+    }
+}
+
+impl P2PTransport for TcpTransport {
+    fn setup(app: &mut App) {
+        app.init_resource::<TcpConfig>();
+    }
+
+    fn max_message_size(&self) -> usize {
+        MAX_FRAME_SIZE
+    }
+
+    fn create_lobby(&mut self, world: &mut World) -> Result<(), TransportError> {
+        self.close();
+        let config = world.resource::<TcpConfig>().clone();
+        let listener = TcpListener::bind(&config.bind_address)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|err| TransportError::Other(err.to_string()))?;
+        let code = match config.advertised_address {
+            Some(address) => address,
+            None => routable_address(
+                listener
+                    .local_addr()
+                    .map_err(|err| TransportError::Other(err.to_string()))?,
+            )
+            .to_string(),
+        };
+        self.role = Role::Host {
+            listener,
+            clients: HashMap::new(),
+            next_client_id: 0,
+        };
+        self.pending_events
+            .push(TransportEvent::LobbyCreated(code.clone()));
+        self.pending_events.push(TransportEvent::LobbyEntered(code));
+        Ok(())
+    }
+
+    fn join_lobby(&mut self, world: &mut World, code: &str) -> Result<(), TransportError> {
+        self.close();
+        let config = world.resource::<TcpConfig>().clone();
+        let (sender, attempt) = std::sync::mpsc::channel();
+        let target = code.to_string();
+        std::thread::Builder::new()
+            .name("tcp-connect".to_string())
+            .spawn(move || {
+                let _ = sender.send(Self::connect(&config, &target));
+            })
+            .map_err(|err| TransportError::Other(err.to_string()))?;
+        self.role = Role::Connecting {
+            code: code.to_string(),
+            attempt,
+        };
+        Ok(())
+    }
+
+    fn exit_lobby(&mut self, _world: &mut World) {
+        self.close();
+        self.pending_events.clear();
+    }
+
+    fn kick(&mut self, _world: &mut World, client_id: ClientId) {
+        let Role::Host { clients, .. } = &mut self.role else {
+            return;
+        };
+        let Some(mut connection) = clients.remove(&client_id) else {
+            return;
+        };
+        connection.queue_kick();
+        connection.close();
+        self.pending_events
+            .push(TransportEvent::PeerDisconnected(client_id));
+    }
+
+    fn send(
+        &mut self,
+        _world: &mut World,
+        target: SendTarget,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        // Write errors surface as disconnections on the next poll.
+        match (&mut self.role, target) {
+            (Role::Client(host), SendTarget::Host | SendTarget::All) => {
+                host.queue(payload);
+                let _ = host.flush();
+            }
+            (Role::Host { clients, .. }, SendTarget::Client(client_id)) => {
+                let connection = clients
+                    .get_mut(&client_id)
+                    .ok_or(TransportError::UnknownPeer(client_id))?;
+                connection.queue(payload);
+                let _ = connection.flush();
+            }
+            (Role::Host { clients, .. }, SendTarget::All | SendTarget::AllExcept(_)) => {
+                for (client_id, connection) in clients.iter_mut() {
+                    if target == SendTarget::AllExcept(*client_id) {
+                        continue;
+                    }
+                    connection.queue(payload);
+                    let _ = connection.flush();
+                }
+            }
+            _ => return Err(TransportError::NotConnected),
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self, _world: &mut World, events: &mut Vec<TransportEvent>) {
+        self.poll_host();
+        self.poll_connecting();
+        self.poll_client();
+        events.append(&mut self.pending_events);
+    }
+}
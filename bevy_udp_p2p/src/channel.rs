@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::packet::Packet;
+
+// Payloads in flight at once; later ones wait for acks.
+const WINDOW: usize = 256;
+
+struct Unacked {
+    sequence: u32,
+    payload: Vec<u8>,
+    sent_at: Option<Instant>,
+}
+
+// Makes datagrams to and from one peer reliable and ordered: payloads are resent until acked and
+// delivered in sequence order.
+#[derive(Default)]
+pub(crate) struct ReliableChannel {
+    next_sequence: u32,
+    unacked: VecDeque<Unacked>,
+    next_expected: u32,
+    early: BTreeMap<u32, Vec<u8>>,
+    ack_due: bool,
+}
+
+impl ReliableChannel {
+    pub(crate) fn queue(&mut self, payload: Vec<u8>) {
+        self.unacked.push_back(Unacked {
+            sequence: self.next_sequence,
+            payload,
+            sent_at: None,
+        });
+        self.next_sequence += 1;
+    }
+
+    pub(crate) fn acknowledge(&mut self, next: u32) {
+        while self
+            .unacked
+            .front()
+            .is_some_and(|unacked| unacked.sequence < next)
+        {
+            self.unacked.pop_front();
+        }
+    }
+
+    pub(crate) fn receive(
+        &mut self,
+        sequence: u32,
+        payload: Vec<u8>,
+        delivered: &mut Vec<Vec<u8>>,
+    ) {
+        self.ack_due = true;
+        // Duplicates of delivered payloads, or too far ahead to be legitimate.
+        if sequence < self.next_expected || sequence - self.next_expected >= WINDOW as u32 {
+            return;
+        }
+        self.early.insert(sequence, payload);
+        while let Some(payload) = self.early.remove(&self.next_expected) {
+            delivered.push(payload);
+            self.next_expected += 1;
+        }
+    }
+
+    /// Payloads never sent or not acked in time, then the ack owed to the peer if any.
+    pub(crate) fn due(&mut self, now: Instant, resend_after: Duration, packets: &mut Vec<Packet>) {
+        for unacked in self.unacked.iter_mut().take(WINDOW) {
+            if unacked
+                .sent_at
+                .is_none_or(|sent_at| now.duration_since(sent_at) >= resend_after)
+            {
+                packets.push(Packet::Data {
+                    sequence: unacked.sequence,
+                    payload: unacked.payload.clone(),
+                });
+                unacked.sent_at = Some(now);
+            }
+        }
+        if std::mem::take(&mut self.ack_due) {
+            packets.push(Packet::Ack {
+                next: self.next_expected,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESEND_AFTER: Duration = Duration::from_millis(100);
+
+    fn sent_sequences(channel: &mut ReliableChannel, now: Instant) -> Vec<u32> {
+        let mut packets = Vec::new();
+        channel.due(now, RESEND_AFTER, &mut packets);
+        packets
+            .into_iter()
+            .filter_map(|packet| match packet {
+                Packet::Data { sequence, .. } => Some(sequence),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reordered_payloads_are_delivered_in_sequence() {
+        let mut channel = ReliableChannel::default();
+        let mut delivered = Vec::new();
+        channel.receive(2, vec![2], &mut delivered);
+        channel.receive(1, vec![1], &mut delivered);
+        assert!(delivered.is_empty());
+        channel.receive(0, vec![0], &mut delivered);
+        assert_eq!(delivered, vec![vec![0], vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn duplicates_are_delivered_once_and_still_acked() {
+        let mut channel = ReliableChannel::default();
+        let mut delivered = Vec::new();
+        channel.receive(1, vec![1], &mut delivered);
+        channel.receive(1, vec![1], &mut delivered);
+        channel.receive(0, vec![0], &mut delivered);
+        channel.receive(0, vec![0], &mut delivered);
+        assert_eq!(delivered, vec![vec![0], vec![1]]);
+
+        // The peer resends until it hears about it, so a late duplicate still earns an ack.
+        let mut packets = Vec::new();
+        channel.due(Instant::now(), RESEND_AFTER, &mut packets);
+        channel.receive(0, vec![0], &mut delivered);
+        channel.due(Instant::now(), RESEND_AFTER, &mut packets);
+        assert_eq!(
+            packets,
+            vec![Packet::Ack { next: 2 }, Packet::Ack { next: 2 }]
+        );
+        assert_eq!(delivered.len(), 2);
+    }
+
+    #[test]
+    fn payloads_beyond_the_window_are_dropped() {
+        let mut channel = ReliableChannel::default();
+        let mut delivered = Vec::new();
+        channel.receive(WINDOW as u32, vec![0], &mut delivered);
+        channel.receive(WINDOW as u32 - 1, vec![1], &mut delivered);
+        assert!(channel.early.contains_key(&(WINDOW as u32 - 1)));
+        assert!(!channel.early.contains_key(&(WINDOW as u32)));
+    }
+
+    #[test]
+    fn only_a_window_of_payloads_is_in_flight() {
+        let mut channel = ReliableChannel::default();
+        for index in 0..WINDOW + 10 {
+            channel.queue(vec![index as u8]);
+        }
+        let now = Instant::now();
+        let sent = sent_sequences(&mut channel, now);
+        assert_eq!(sent, (0..WINDOW as u32).collect::<Vec<_>>());
+
+        channel.acknowledge(10);
+        let sent = sent_sequences(&mut channel, now);
+        assert_eq!(
+            sent,
+            (WINDOW as u32..WINDOW as u32 + 10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn acks_trim_what_is_resent() {
+        let mut channel = ReliableChannel::default();
+        for index in 0..4 {
+            channel.queue(vec![index]);
+        }
+        let now = Instant::now();
+        assert_eq!(sent_sequences(&mut channel, now), vec![0, 1, 2, 3]);
+        assert!(sent_sequences(&mut channel, now).is_empty());
+
+        channel.acknowledge(2);
+        // Stale acks do not bring anything back.
+        channel.acknowledge(1);
+        assert_eq!(sent_sequences(&mut channel, now + RESEND_AFTER), vec![2, 3]);
+
+        channel.acknowledge(4);
+        assert!(sent_sequences(&mut channel, now + RESEND_AFTER * 2).is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Instant;
+
+use crate::UdpConfig;
+use crate::packet::{Advertisement, MAX_DATAGRAM_SIZE, Packet};
+
+// Lobbies that missed this many queries in a row are forgotten.
+const MISSED_QUERIES: u32 = 3;
+
+/// A lobby a host on the local network answered with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanLobby {
+    /// What to join it with.
+    pub code: String,
+    pub name: String,
+    pub players: usize,
+    pub max_players: Option<usize>,
+}
+
+/// Lobbies hosted with `UdpTransport` on the local network, kept up to date while out of a
+/// lobby.
+#[derive(Resource, Clone, Debug, Default, PartialEq)]
+pub struct LanLobbies(pub Vec<LanLobby>);
+
+struct Sighting {
+    address: SocketAddr,
+    advertisement: Advertisement,
+    seen_at: Instant,
+}
+
+// Asks every host on the discovery ports about their lobby, by broadcast and on this machine.
+#[derive(Resource, Default)]
+pub(crate) struct LanDiscovery {
+    socket: Option<UdpSocket>,
+    last_query: Option<Instant>,
+    sightings: HashMap<u64, Sighting>,
+}
+
+fn open_socket() -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+pub(crate) fn discover_lan_lobbies(
+    mut discovery: ResMut<LanDiscovery>,
+    mut lobbies: ResMut<LanLobbies>,
+    config: Res<UdpConfig>,
+) {
+    let discovery = &mut *discovery;
+    if discovery.socket.is_none() {
+        match open_socket() {
+            Ok(socket) => discovery.socket = Some(socket),
+            Err(err) => {
+                debug!("Could not look for LAN lobbies: {}", err);
+                return;
+            }
+        }
+    }
+    let Some(socket) = &discovery.socket else {
+        return;
+    };
+    let now = Instant::now();
+    if discovery
+        .last_query
+        .is_none_or(|last_query| now.duration_since(last_query) >= config.discovery_interval)
+    {
+        discovery.last_query = Some(now);
+        let query = Packet::Discover.encode();
+        for port in config.discovery_ports.iter() {
+            for ip in [Ipv4Addr::BROADCAST, Ipv4Addr::LOCALHOST] {
+                let _ = socket.send_to(&query, (ip, *port));
+            }
+        }
+    }
+
+    let mut buffer = [0; MAX_DATAGRAM_SIZE];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((len, from)) => {
+                let Some(Packet::Advertise(advertisement)) = Packet::decode(&buffer[..len]) else {
+                    continue;
+                };
+                let sighting =
+                    discovery
+                        .sightings
+                        .entry(advertisement.lobby_id)
+                        .or_insert(Sighting {
+                            address: from,
+                            advertisement: advertisement.clone(),
+                            seen_at: now,
+                        });
+                // A host on this machine also answers on loopback, which others could not join.
+                if !from.ip().is_loopback() || sighting.address.ip().is_loopback() {
+                    sighting.address = from;
+                }
+                sighting.advertisement = advertisement;
+                sighting.seen_at = now;
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            // Some platforms report unreachable ports of earlier queries here.
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => continue,
+            Err(err) => {
+                debug!("Could not read LAN lobbies: {}", err);
+                break;
+            }
+        }
+    }
+
+    let forget_after = config.discovery_interval * MISSED_QUERIES;
+    discovery
+        .sightings
+        .retain(|_, sighting| now.duration_since(sighting.seen_at) < forget_after);
+    let mut found: Vec<LanLobby> = discovery
+        .sightings
+        .values()
+        .map(|sighting| LanLobby {
+            code: sighting.address.to_string(),
+            name: sighting.advertisement.name.clone(),
+            players: sighting.advertisement.players,
+            max_players: sighting.advertisement.max_players,
+        })
+        .collect();
+    found.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.code.cmp(&b.code)));
+    lobbies.set_if_neq(LanLobbies(found));
+}
+
+pub(crate) fn stop_lan_discovery(
+    mut discovery: ResMut<LanDiscovery>,
+    mut lobbies: ResMut<LanLobbies>,
+) {
+    *discovery = LanDiscovery::default();
+    lobbies.set_if_neq(LanLobbies::default());
+}
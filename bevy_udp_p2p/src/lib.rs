@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+mod channel;
+mod discovery;
+mod packet;
+mod transport;
+
+pub use discovery::{LanLobbies, LanLobby};
+pub use transport::UdpTransport;
+
+pub const DEFAULT_PORT: u16 = 7878;
+
+#[derive(Resource, Clone, Debug)]
+pub struct UdpConfig {
+    /// Where a host listens for clients.
+    pub bind_address: String,
+    /// Lobby code handed out by a host, which is the address clients connect to. Defaults to
+    /// the address of the interface the host reaches the internet through.
+    pub advertised_address: Option<String>,
+    /// What LAN peers see in their lobby list.
+    pub lobby_name: String,
+    pub connect_timeout: Duration,
+    /// Peers that stay silent this long are dropped.
+    pub peer_timeout: Duration,
+    /// Ports hosts are looked for on the local network.
+    pub discovery_ports: Vec<u16>,
+    pub discovery_interval: Duration,
+}
+
+impl Default for UdpConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: format!("0.0.0.0:{}", DEFAULT_PORT),
+            advertised_address: None,
+            lobby_name: "LAN lobby".to_string(),
+            connect_timeout: Duration::from_secs(5),
+            peer_timeout: Duration::from_secs(5),
+            discovery_ports: vec![DEFAULT_PORT],
+            discovery_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl UdpConfig {
+    pub fn with_bind_address(mut self, bind_address: impl Into<String>) -> Self {
+        self.bind_address = bind_address.into();
+        self
+    }
+
+    pub fn with_advertised_address(mut self, advertised_address: impl Into<String>) -> Self {
+        self.advertised_address = Some(advertised_address.into());
+        self
+    }
+
+    pub fn with_lobby_name(mut self, lobby_name: impl Into<String>) -> Self {
+        self.lobby_name = lobby_name.into();
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn with_peer_timeout(mut self, peer_timeout: Duration) -> Self {
+        self.peer_timeout = peer_timeout;
+        self
+    }
+
+    pub fn with_discovery_ports(mut self, discovery_ports: Vec<u16>) -> Self {
+        self.discovery_ports = discovery_ports;
+        self
+    }
+
+    pub fn with_discovery_interval(mut self, discovery_interval: Duration) -> Self {
+        self.discovery_interval = discovery_interval;
+        self
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+// Every datagram starts with these so that strangers on the port are ignored.
+const MAGIC: &[u8; 4] = b"EP2P";
+// Small enough to never get fragmented on common networks.
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 1200;
+const DATA_HEADER_SIZE: usize = MAGIC.len() + 1 + 4;
+pub(crate) const MAX_PAYLOAD_SIZE: usize = MAX_DATAGRAM_SIZE - DATA_HEADER_SIZE;
+
+/// What a host tells LAN peers looking for lobbies.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Advertisement {
+    // Tells apart the same lobby answering on several interfaces.
+    pub lobby_id: u64,
+    pub name: String,
+    pub players: usize,
+    pub max_players: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Packet {
+    Connect,
+    Accept,
+    Data {
+        sequence: u32,
+        payload: Vec<u8>,
+    },
+    /// Every sequence before `next` arrived.
+    Ack {
+        next: u32,
+    },
+    Heartbeat,
+    Disconnect,
+    Kick,
+    Discover,
+    Advertise(Advertisement),
+}
+
+impl Packet {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        match self {
+            Packet::Connect => bytes.push(0),
+            Packet::Accept => bytes.push(1),
+            Packet::Data { sequence, payload } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&sequence.to_le_bytes());
+                bytes.extend_from_slice(payload);
+            }
+            Packet::Ack { next } => {
+                bytes.push(3);
+                bytes.extend_from_slice(&next.to_le_bytes());
+            }
+            Packet::Heartbeat => bytes.push(4),
+            Packet::Disconnect => bytes.push(5),
+            Packet::Kick => bytes.push(6),
+            Packet::Discover => bytes.push(7),
+            Packet::Advertise(advertisement) => {
+                bytes.push(8);
+                bytes.extend(serde_json::to_vec(advertisement).unwrap());
+            }
+        }
+        bytes
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Option<Self> {
+        let (&kind, body) = bytes.strip_prefix(MAGIC)?.split_first()?;
+        let sequence = || Some(u32::from_le_bytes(body.get(..4)?.try_into().ok()?));
+        Some(match kind {
+            0 => Packet::Connect,
+            1 => Packet::Accept,
+            2 => Packet::Data {
+                sequence: sequence()?,
+                payload: body[4..].to_vec(),
+            },
+            3 => Packet::Ack { next: sequence()? },
+            4 => Packet::Heartbeat,
+            5 => Packet::Disconnect,
+            6 => Packet::Kick,
+            7 => Packet::Discover,
+            8 => Packet::Advertise(serde_json::from_slice(body).ok()?),
+            _ => return None,
+        })
+    }
+}
@@ -0,0 +1,432 @@
+use bevy::prelude::*;
+use bevy_easy_p2p::net::{code_to_address, routable_address};
+use bevy_easy_p2p::{
+    ClientId, ExitReason, LobbyConfig, LobbyFailureReason, NetworkedId, P2PLobbyState,
+    P2PTransport, SendTarget, TransportError, TransportEvent,
+};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::channel::ReliableChannel;
+use crate::discovery::{LanDiscovery, discover_lan_lobbies, stop_lan_discovery};
+use crate::packet::{Advertisement, MAX_DATAGRAM_SIZE, MAX_PAYLOAD_SIZE, Packet};
+use crate::{DEFAULT_PORT, LanLobbies, UdpConfig};
+
+const RESEND_AFTER: Duration = Duration::from_millis(100);
+const CONNECT_RETRY: Duration = Duration::from_millis(250);
+// Keeps quiet peers from timing out.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+// Kicks are not acked, so they are sent a few times.
+const KICK_REPEATS: usize = 3;
+
+struct Peer {
+    address: SocketAddr,
+    channel: ReliableChannel,
+    last_received: Instant,
+    last_sent: Instant,
+}
+
+impl Peer {
+    fn new(address: SocketAddr, now: Instant) -> Self {
+        Self {
+            address,
+            channel: ReliableChannel::default(),
+            last_received: now,
+            last_sent: now,
+        }
+    }
+
+    fn flush(&mut self, socket: &UdpSocket, now: Instant) {
+        let mut packets = Vec::new();
+        self.channel.due(now, RESEND_AFTER, &mut packets);
+        if packets.is_empty() && now.duration_since(self.last_sent) >= HEARTBEAT_INTERVAL {
+            packets.push(Packet::Heartbeat);
+        }
+        for packet in packets.iter() {
+            send_packet(socket, self.address, packet);
+        }
+        if !packets.is_empty() {
+            self.last_sent = now;
+        }
+    }
+}
+
+enum Role {
+    Host {
+        lobby_id: u64,
+        clients: HashMap<ClientId, Peer>,
+        next_client_id: ClientId,
+    },
+    Connecting {
+        code: String,
+        address: SocketAddr,
+        started: Instant,
+        last_attempt: Instant,
+    },
+    Client(Peer),
+}
+
+struct Session {
+    socket: UdpSocket,
+    role: Role,
+}
+
+/// Reliable, ordered UDP between native builds on the same network, no server involved. Hosts
+/// answer LAN discovery, see `LanLobbies`.
+#[derive(Default)]
+pub struct UdpTransport {
+    session: Option<Session>,
+    pending_events: Vec<TransportEvent>,
+}
+
+// Losses are made up for by resends, so failures to send are ignored.
+fn send_packet(socket: &UdpSocket, address: SocketAddr, packet: &Packet) {
+    if let Err(err) = socket.send_to(&packet.encode(), address)
+        && err.kind() != ErrorKind::WouldBlock
+    {
+        debug!("Could not send to {}: {}", address, err);
+    }
+}
+
+fn bind(address: impl ToSocketAddrs) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(address)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+impl UdpTransport {
+    fn close(&mut self) {
+        let Some(session) = self.session.take() else {
+            return;
+        };
+        match &session.role {
+            Role::Host { clients, .. } => {
+                for peer in clients.values() {
+                    send_packet(&session.socket, peer.address, &Packet::Disconnect);
+                }
+            }
+            Role::Client(host) => send_packet(&session.socket, host.address, &Packet::Disconnect),
+            Role::Connecting { .. } => {}
+        }
+    }
+
+    fn resolve(code: &str) -> Option<SocketAddr> {
+        code_to_address(code, DEFAULT_PORT)
+            .to_socket_addrs()
+            .ok()?
+            .next()
+    }
+
+    fn handle(&mut self, world: &World, from: SocketAddr, packet: Packet, now: Instant) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        let socket = &session.socket;
+        match &mut session.role {
+            Role::Host {
+                lobby_id,
+                clients,
+                next_client_id,
+            } => {
+                let client = clients.iter_mut().find(|(_, peer)| peer.address == from);
+                match (client, packet) {
+                    (_, Packet::Discover) => {
+                        let config = world.resource::<UdpConfig>();
+                        let lobby = world.get_resource::<LobbyConfig>();
+                        let host_slots = match lobby {
+                            Some(lobby) if lobby.dedicated_host => 0,
+                            _ => 1,
+                        };
+                        let advertisement = Advertisement {
+                            lobby_id: *lobby_id,
+                            name: config.lobby_name.clone(),
+                            players: clients.len() + host_slots,
+                            max_players: lobby.and_then(|lobby| lobby.max_players),
+                        };
+                        send_packet(socket, from, &Packet::Advertise(advertisement));
+                    }
+                    (Some((client_id, peer)), packet) => {
+                        let client_id = *client_id;
+                        peer.last_received = now;
+                        match packet {
+                            // Our accept got lost.
+                            Packet::Connect => send_packet(socket, from, &Packet::Accept),
+                            Packet::Data { sequence, payload } => {
+                                let mut delivered = Vec::new();
+                                peer.channel.receive(sequence, payload, &mut delivered);
+                                self.pending_events
+                                    .extend(delivered.into_iter().map(|payload| {
+                                        TransportEvent::Received {
+                                            from: NetworkedId::ClientId(client_id),
+                                            payload,
+                                        }
+                                    }));
+                            }
+                            Packet::Ack { next } => peer.channel.acknowledge(next),
+                            Packet::Disconnect => {
+                                clients.remove(&client_id);
+                                self.pending_events
+                                    .push(TransportEvent::PeerDisconnected(client_id));
+                            }
+                            _ => {}
+                        }
+                    }
+                    (None, Packet::Connect) => {
+                        *next_client_id += 1;
+                        debug!("Client {} connected from {}", next_client_id, from);
+                        clients.insert(*next_client_id, Peer::new(from, now));
+                        send_packet(socket, from, &Packet::Accept);
+                        self.pending_events
+                            .push(TransportEvent::PeerConnected(*next_client_id));
+                    }
+                    (None, _) => {}
+                }
+            }
+            Role::Connecting { code, address, .. } => {
+                if from == *address && packet == Packet::Accept {
+                    let code = code.clone();
+                    session.role = Role::Client(Peer::new(from, now));
+                    self.pending_events
+                        .push(TransportEvent::LobbyJoined(code.clone()));
+                    self.pending_events.push(TransportEvent::LobbyEntered(code));
+                }
+            }
+            Role::Client(host) => {
+                if from != host.address {
+                    return;
+                }
+                host.last_received = now;
+                match packet {
+                    Packet::Data { sequence, payload } => {
+                        let mut delivered = Vec::new();
+                        host.channel.receive(sequence, payload, &mut delivered);
+                        self.pending_events
+                            .extend(delivered.into_iter().map(|payload| {
+                                TransportEvent::Received {
+                                    from: NetworkedId::Host,
+                                    payload,
+                                }
+                            }));
+                    }
+                    Packet::Ack { next } => host.channel.acknowledge(next),
+                    Packet::Kick => {
+                        self.session = None;
+                        self.pending_events
+                            .push(TransportEvent::LobbyExited(ExitReason::Kicked));
+                    }
+                    Packet::Disconnect => {
+                        self.session = None;
+                        self.pending_events
+                            .push(TransportEvent::LobbyExited(ExitReason::Disconnected));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Resends, heartbeats, timeouts and connection attempts.
+    fn tick(&mut self, config: &UdpConfig, now: Instant) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        let socket = &session.socket;
+        match &mut session.role {
+            Role::Host { clients, .. } => {
+                let timed_out: Vec<ClientId> = clients
+                    .iter()
+                    .filter(|(_, peer)| {
+                        now.duration_since(peer.last_received) > config.peer_timeout
+                    })
+                    .map(|(client_id, _)| *client_id)
+                    .collect();
+                for client_id in timed_out {
+                    clients.remove(&client_id);
+                    self.pending_events
+                        .push(TransportEvent::PeerDisconnected(client_id));
+                }
+                for peer in clients.values_mut() {
+                    peer.flush(socket, now);
+                }
+            }
+            Role::Connecting {
+                address,
+                started,
+                last_attempt,
+                ..
+            } => {
+                if now.duration_since(*started) > config.connect_timeout {
+                    self.session = None;
+                    self.pending_events
+                        .push(TransportEvent::JoinFailed(LobbyFailureReason::RoomNotFound));
+                } else if now.duration_since(*last_attempt) >= CONNECT_RETRY {
+                    *last_attempt = now;
+                    send_packet(socket, *address, &Packet::Connect);
+                }
+            }
+            Role::Client(host) => {
+                if now.duration_since(host.last_received) > config.peer_timeout {
+                    self.session = None;
+                    self.pending_events
+                        .push(TransportEvent::LobbyExited(ExitReason::TimedOut));
+                } else {
+                    host.flush(socket, now);
+                }
+            }
+        }
+    }
+}
+
+impl P2PTransport for UdpTransport {
+    fn setup(app: &mut App) {
+        app.init_resource::<UdpConfig>()
+            .init_resource::<LanLobbies>()
+            .init_resource::<LanDiscovery>()
+            .add_systems(
+                Update,
+                discover_lan_lobbies.run_if(in_state(P2PLobbyState::OutOfLobby)),
+            )
+            .add_systems(OnExit(P2PLobbyState::OutOfLobby), stop_lan_discovery);
+    }
+
+    fn max_message_size(&self) -> usize {
+        MAX_PAYLOAD_SIZE
+    }
+
+    fn create_lobby(&mut self, world: &mut World) -> Result<(), TransportError> {
+        self.close();
+        let config = world.resource::<UdpConfig>();
+        let socket = bind(config.bind_address.as_str())
+            .map_err(|err| TransportError::Other(err.to_string()))?;
+        let code = match &config.advertised_address {
+            Some(address) => address.clone(),
+            None => routable_address(
+                socket
+                    .local_addr()
+                    .map_err(|err| TransportError::Other(err.to_string()))?,
+            )
+            .to_string(),
+        };
+        self.session = Some(Session {
+            socket,
+            role: Role::Host {
+                lobby_id: RandomState::new().hash_one(Instant::now()),
+                clients: HashMap::new(),
+                next_client_id: 0,
+            },
+        });
+        self.pending_events
+            .push(TransportEvent::LobbyCreated(code.clone()));
+        self.pending_events.push(TransportEvent::LobbyEntered(code));
+        Ok(())
+    }
+
+    fn join_lobby(&mut self, _world: &mut World, code: &str) -> Result<(), TransportError> {
+        self.close();
+        let Some(address) = Self::resolve(code) else {
+            self.pending_events
+                .push(TransportEvent::JoinFailed(LobbyFailureReason::RoomNotFound));
+            return Ok(());
+        };
+        let socket = bind((Ipv4Addr::UNSPECIFIED, 0))
+            .map_err(|err| TransportError::Other(err.to_string()))?;
+        let now = Instant::now();
+        send_packet(&socket, address, &Packet::Connect);
+        self.session = Some(Session {
+            socket,
+            role: Role::Connecting {
+                code: code.trim().to_string(),
+                address,
+                started: now,
+                last_attempt: now,
+            },
+        });
+        Ok(())
+    }
+
+    fn exit_lobby(&mut self, _world: &mut World) {
+        self.close();
+        self.pending_events.clear();
+    }
+
+    fn kick(&mut self, _world: &mut World, client_id: ClientId) {
+        let Some(Session {
+            socket,
+            role: Role::Host { clients, .. },
+        }) = &mut self.session
+        else {
+            return;
+        };
+        let Some(peer) = clients.remove(&client_id) else {
+            return;
+        };
+        for _ in 0..KICK_REPEATS {
+            send_packet(socket, peer.address, &Packet::Kick);
+        }
+        self.pending_events
+            .push(TransportEvent::PeerDisconnected(client_id));
+    }
+
+    fn send(
+        &mut self,
+        _world: &mut World,
+        target: SendTarget,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        let Some(Session { socket, role }) = &mut self.session else {
+            return Err(TransportError::NotConnected);
+        };
+        let now = Instant::now();
+        match (role, target) {
+            (Role::Client(host), SendTarget::Host | SendTarget::All) => {
+                host.channel.queue(payload.to_vec());
+                host.flush(socket, now);
+            }
+            (Role::Host { clients, .. }, SendTarget::Client(client_id)) => {
+                let peer = clients
+                    .get_mut(&client_id)
+                    .ok_or(TransportError::UnknownPeer(client_id))?;
+                peer.channel.queue(payload.to_vec());
+                peer.flush(socket, now);
+            }
+            (Role::Host { clients, .. }, SendTarget::All | SendTarget::AllExcept(_)) => {
+                for (client_id, peer) in clients.iter_mut() {
+                    if target == SendTarget::AllExcept(*client_id) {
+                        continue;
+                    }
+                    peer.channel.queue(payload.to_vec());
+                    peer.flush(socket, now);
+                }
+            }
+            _ => return Err(TransportError::NotConnected),
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self, world: &mut World, events: &mut Vec<TransportEvent>) {
+        let now = Instant::now();
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        while let Some(session) = &self.session {
+            match session.socket.recv_from(&mut buffer) {
+                Ok((len, from)) => {
+                    if let Some(packet) = Packet::decode(&buffer[..len]) {
+                        self.handle(world, from, packet, now);
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                // Some platforms report unreachable ports of earlier datagrams here.
+                Err(err) if err.kind() == ErrorKind::ConnectionReset => {}
+                Err(err) => {
+                    warn!("Could not receive: {}", err);
+                    break;
+                }
+            }
+        }
+        let config = world.resource::<UdpConfig>().clone();
+        self.tick(&config, now);
+        events.append(&mut self.pending_events);
+    }
+}
@@ -12,9 +12,10 @@ use wasm_bindgen_futures::spawn_local;
 use web_sys::RtcDataChannel;
 use web_sys::RtcDataChannelEvent;
 use web_sys::RtcDataChannelState;
-use web_sys::RtcIceGatheringState;
+use web_sys::RtcIceCandidateInit;
 use web_sys::RtcIceServer;
 use web_sys::RtcPeerConnection;
+use web_sys::RtcPeerConnectionIceEvent;
 use web_sys::RtcSdpType;
 use web_sys::RtcSessionDescriptionInit;
 
@@ -51,6 +52,20 @@ pub struct LocalSdpReady {
     pub sdp: String,
 }
 
+// ICE candidates travel as the JSON of an RTCIceCandidateInit, found as soon as the local
+// description is set and usable once the remote one is.
+#[derive(Message)]
+pub struct LocalIceCandidate {
+    pub id: ConnectionId,
+    pub candidate: String,
+}
+
+#[derive(Message)]
+pub struct AddIceCandidate {
+    pub id: ConnectionId,
+    pub candidate: String,
+}
+
 #[derive(Message)]
 pub struct IncomingData {
     pub id: ConnectionId,
@@ -80,6 +95,8 @@ struct ConnState {
     pending_closed: Rc<Cell<bool>>,
     pending_messages: Rc<RefCell<Vec<String>>>,
     pending_local_sdp: Rc<RefCell<Vec<String>>>,
+    pending_local_candidates: Rc<RefCell<Vec<String>>>,
+    remote_candidates: Rc<RemoteCandidates>,
 }
 
 impl ConnState {
@@ -91,6 +108,32 @@ impl ConnState {
             pending_closed: Rc::new(Cell::new(false)),
             pending_messages: Rc::new(RefCell::new(Vec::new())),
             pending_local_sdp: Rc::new(RefCell::new(Vec::new())),
+            pending_local_candidates: Rc::new(RefCell::new(Vec::new())),
+            remote_candidates: Rc::new(RemoteCandidates::default()),
+        }
+    }
+}
+
+// Candidates can only be added once the remote description is set, earlier ones wait here.
+#[derive(Default)]
+struct RemoteCandidates {
+    described: Cell<bool>,
+    queued: RefCell<Vec<String>>,
+}
+
+impl RemoteCandidates {
+    fn add(&self, pc: &RtcPeerConnection, candidate: String) {
+        if self.described.get() {
+            add_ice_candidate(pc, &candidate);
+        } else {
+            self.queued.borrow_mut().push(candidate);
+        }
+    }
+
+    fn remote_described(&self, pc: &RtcPeerConnection) {
+        self.described.set(true);
+        for candidate in self.queued.borrow_mut().drain(..) {
+            add_ice_candidate(pc, &candidate);
         }
     }
 }
@@ -118,6 +161,8 @@ impl Plugin for WebRtcPlugin {
             .add_message::<SetRemote>()
             .add_message::<SendData>()
             .add_message::<LocalSdpReady>()
+            .add_message::<LocalIceCandidate>()
+            .add_message::<AddIceCandidate>()
             .add_message::<IncomingData>()
             .add_message::<ConnectionOpen>()
             .add_message::<CloseConnection>()
@@ -133,6 +178,10 @@ impl Plugin for WebRtcPlugin {
                     handle_create_offer,
                     handle_create_answer,
                     handle_set_remote,
+                    // Candidates may arrive in the same frame as the connection they are for.
+                    handle_add_ice_candidate
+                        .after(handle_create_answer)
+                        .after(handle_set_remote),
                     handle_send_data,
                     handle_close_connection,
                     handle_close_all,
@@ -152,28 +201,35 @@ fn make_peer_connection() -> Result<RtcPeerConnection, JsValue> {
     RtcPeerConnection::new_with_configuration(&cfg)
 }
 
-// Await until ICE gathering state becomes Complete (non-trickle)
-async fn await_ice_complete(pc: RtcPeerConnection) {
-    if pc.ice_gathering_state() == RtcIceGatheringState::Complete {
-        return;
-    }
-    // One-shot using JS callback and a Promise-like resolver
-    let (tx, rx) = futures_channel::oneshot::channel::<()>();
-    let tx = Rc::new(Cell::new(Some(tx)));
-    let pc_clone = pc.clone();
-    let closure = Closure::wrap(Box::new(move || {
-        if pc_clone.ice_gathering_state() == RtcIceGatheringState::Complete {
-            if let Some(sender) = tx.take() {
-                let _ = sender.send(());
-            }
+// Collect local candidates as they are gathered (trickle ICE)
+fn hook_ice_candidates(pending: Rc<RefCell<Vec<String>>>, pc: &RtcPeerConnection) {
+    let closure = Closure::wrap(Box::new(move |ev: RtcPeerConnectionIceEvent| {
+        // No candidate marks the end of gathering, which the remote side does not need.
+        let Some(candidate) = ev.candidate() else {
+            return;
+        };
+        if let Ok(json) = js_sys::JSON::stringify(&candidate.to_json()) {
+            pending.borrow_mut().push(json.into());
         }
-    }) as Box<dyn FnMut()>);
-    pc.set_onicegatheringstatechange(Some(closure.as_ref().unchecked_ref()));
-    let _ = rx.await;
-    pc.set_onicegatheringstatechange(None);
+    }) as Box<dyn FnMut(RtcPeerConnectionIceEvent)>);
+    pc.set_onicecandidate(Some(closure.as_ref().unchecked_ref()));
     closure.forget();
 }
 
+fn add_ice_candidate(pc: &RtcPeerConnection, candidate: &str) {
+    let Ok(init) = js_sys::JSON::parse(candidate) else {
+        warn!("Ignoring malformed ICE candidate: {}", candidate);
+        return;
+    };
+    let init: RtcIceCandidateInit = init.unchecked_into();
+    let promise = pc.add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&init));
+    spawn_local(async move {
+        if let Err(err) = wasm_bindgen_futures::JsFuture::from(promise).await {
+            debug!("addIceCandidate failed: {:?}", err);
+        }
+    });
+}
+
 // Ensure local tab/window closing proactively closes PC/DC so remote detects it immediately
 fn register_unload_close(pc: RtcPeerConnection, dc: Option<RtcDataChannel>) {
     if let Some(window) = web_sys::window() {
@@ -310,6 +366,7 @@ fn handle_create_offer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<Cr
 
         // Detect disconnects via peer connection state changes
         hook_peer_connection(state.pending_closed.clone(), &pc);
+        hook_ice_candidates(state.pending_local_candidates.clone(), &pc);
 
         // Create data channel immediately (offerer)
         let dc = pc.create_data_channel("data");
@@ -323,10 +380,9 @@ fn handle_create_offer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<Cr
         // Proactively close on page unload (offerer side has DC now)
         register_unload_close(pc.clone(), Some(dc.clone()));
 
-        // Prepare to emit local SDP after ICE completes
+        // Emit local SDP right away, candidates follow on their own
         let sdp_buf = state.pending_local_sdp.clone();
         let pc_clone = pc.clone();
-        let this_id = id;
 
         spawn_local(async move {
             // Create offer
@@ -347,12 +403,9 @@ fn handle_create_offer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<Cr
             {
                 return;
             }
-            await_ice_complete(pc_clone.clone()).await;
             if let Some(local) = pc_clone.local_description() {
                 sdp_buf.borrow_mut().push(local.sdp());
             }
-            // sdp will be pumped with id later
-            let _ = this_id; // silence unused warning in some builds
         });
 
         state.dc_slot.borrow_mut().replace(dc);
@@ -380,6 +433,7 @@ fn handle_create_answer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<C
         // Listen for datachannel from offerer
         // Also detect disconnects via peer connection state changes
         hook_peer_connection(state.pending_closed.clone(), &pc);
+        hook_ice_candidates(state.pending_local_candidates.clone(), &pc);
         let pc_for_dc = pc.clone();
         // Proactively close on page unload (answerer may not have DC yet)
         register_unload_close(pc.clone(), None);
@@ -406,8 +460,8 @@ fn handle_create_answer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<C
 
         let sdp_text = remote_sdp.clone();
         let sdp_buf = state.pending_local_sdp.clone();
+        let remote_candidates = state.remote_candidates.clone();
         let pc_clone = pc.clone();
-        let _this_id = id;
         spawn_local(async move {
             // Apply remote offer
             let remote = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
@@ -418,6 +472,7 @@ fn handle_create_answer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<C
             {
                 return;
             }
+            remote_candidates.remote_described(&pc_clone);
             // Create and set local answer
             let answer_val =
                 match wasm_bindgen_futures::JsFuture::from(pc_clone.create_answer()).await {
@@ -431,7 +486,6 @@ fn handle_create_answer(mut ctx: NonSendMut<RtcContext>, mut ev: MessageReader<C
             {
                 return;
             }
-            await_ice_complete(pc_clone.clone()).await;
             if let Some(local) = pc_clone.local_description() {
                 sdp_buf.borrow_mut().push(local.sdp());
             }
@@ -448,13 +502,18 @@ fn handle_set_remote(ctx: NonSend<RtcContext>, mut ev: MessageReader<SetRemote>)
             if let Some(pc) = state.pc_slot.borrow().clone() {
                 let pc_clone = pc.clone();
                 let sdp_text = sdp.clone();
+                let remote_candidates = state.remote_candidates.clone();
                 spawn_local(async move {
                     let desc = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
                     desc.set_sdp(&sdp_text);
-                    let _ = wasm_bindgen_futures::JsFuture::from(
+                    if wasm_bindgen_futures::JsFuture::from(
                         pc_clone.set_remote_description(&desc),
                     )
-                    .await;
+                    .await
+                    .is_ok()
+                    {
+                        remote_candidates.remote_described(&pc_clone);
+                    }
                 });
             }
         }
@@ -462,14 +521,38 @@ fn handle_set_remote(ctx: NonSend<RtcContext>, mut ev: MessageReader<SetRemote>)
     ev.clear();
 }
 
+fn handle_add_ice_candidate(ctx: NonSend<RtcContext>, mut ev: MessageReader<AddIceCandidate>) {
+    for AddIceCandidate { id, candidate } in ev.read() {
+        let Some(state) = ctx.conns.get(id) else {
+            continue;
+        };
+        let Some(pc) = state.pc_slot.borrow().clone() else {
+            continue;
+        };
+        state.remote_candidates.add(&pc, candidate.clone());
+    }
+    ev.clear();
+}
+
 fn handle_send_data(ctx: NonSend<RtcContext>, mut ev: MessageReader<SendData>) {
     for SendData { id, text } in ev.read() {
-        if let Some(state) = ctx.conns.get(&id) {
-            if let Some(dc) = state.dc_slot.borrow().as_ref() {
-                if dc.ready_state() == RtcDataChannelState::Open {
-                    let _ = dc.send_with_str(text.as_str());
-                }
-            }
+        let Some(state) = ctx.conns.get(id) else {
+            continue;
+        };
+        let Some(dc) = state.dc_slot.borrow().clone() else {
+            continue;
+        };
+        if dc.ready_state() != RtcDataChannelState::Open {
+            debug!("Dropping message for {:?}: data channel is not open", id);
+            continue;
+        }
+        if let Err(err) = dc.send_with_str(text.as_str()) {
+            warn!(
+                "Failed to send {} bytes on connection {:?}: {:?}",
+                text.len(),
+                id,
+                err
+            );
         }
     }
     ev.clear();
@@ -479,6 +562,7 @@ fn handle_send_data(ctx: NonSend<RtcContext>, mut ev: MessageReader<SendData>) {
 fn pump_js_callbacks(
     ctx: NonSendMut<RtcContext>,
     mut sdp_writer: MessageWriter<LocalSdpReady>,
+    mut candidate_writer: MessageWriter<LocalIceCandidate>,
     mut msg_writer: MessageWriter<IncomingData>,
     mut open_writer: MessageWriter<ConnectionOpen>,
     mut closed_writer: MessageWriter<ConnectionClosed>,
@@ -502,6 +586,12 @@ fn pump_js_callbacks(
             for s in sdp.drain(..) {
                 sdp_writer.write(LocalSdpReady { id, sdp: s });
             }
+            drop(sdp);
+            // After the SDP, so that readers know which connection they belong to.
+            let mut candidates = state.pending_local_candidates.borrow_mut();
+            for candidate in candidates.drain(..) {
+                candidate_writer.write(LocalIceCandidate { id, candidate });
+            }
         }
     }
 }
@@ -0,0 +1,37 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+mod socket;
+mod transport;
+
+pub use p2p_relay::DEFAULT_PORT;
+pub use transport::WebSocketRelayTransport;
+
+#[derive(Resource, Clone, Debug)]
+pub struct RelayConfig {
+    /// WebSocket URL of a `p2p_relay` server, `ws://` or `wss://`.
+    pub url: String,
+    /// Native builds only, browsers apply their own.
+    pub connect_timeout: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            url: format!("ws://127.0.0.1:{}", DEFAULT_PORT),
+            connect_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RelayConfig {
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+}
@@ -0,0 +1,12 @@
+// A text-only WebSocket that never blocks once connected. Outgoing messages are buffered until
+// the socket can take them, incoming ones until the next poll.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use native::Socket;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web::Socket;
@@ -0,0 +1,145 @@
+use bevy::log::warn;
+use std::io::ErrorKind;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+use tungstenite::client::IntoClientRequest;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Error, Message, WebSocket};
+
+type Stream = WebSocket<MaybeTlsStream<TcpStream>>;
+
+enum State {
+    // The handshake runs on a helper thread, messages wait in the outbox until it is done.
+    Connecting {
+        attempt: Receiver<Result<Stream, String>>,
+        outbox: Vec<String>,
+    },
+    Open(Box<Stream>),
+    Closed,
+}
+
+pub(crate) struct Socket {
+    state: State,
+}
+
+fn would_block(err: &Error) -> bool {
+    matches!(err, Error::Io(err) if err.kind() == ErrorKind::WouldBlock)
+}
+
+// Blocks until the handshake is done, `wss://` URLs go through rustls.
+fn open(url: &str, timeout: Duration) -> Result<Stream, String> {
+    let request = url.into_client_request().map_err(|err| err.to_string())?;
+    let uri = request.uri();
+    let default_port = match uri.scheme_str() {
+        Some("ws") => 80,
+        Some("wss") => 443,
+        _ => return Err(format!("Unsupported relay URL: {}", url)),
+    };
+    let host = uri
+        .host()
+        .ok_or_else(|| format!("Missing host in relay URL: {}", url))?;
+    let address = (host, uri.port_u16().unwrap_or(default_port))
+        .to_socket_addrs()
+        .map_err(|err| err.to_string())?
+        .next()
+        .ok_or_else(|| format!("Could not resolve {}", host))?;
+    let stream = TcpStream::connect_timeout(&address, timeout).map_err(|err| err.to_string())?;
+    stream.set_nodelay(true).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(|err| err.to_string())?;
+    let (socket, _) = tungstenite::client_tls_with_config(request, stream, None, None)
+        .map_err(|err| err.to_string())?;
+    let stream = match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream,
+        MaybeTlsStream::Rustls(stream) => stream.get_ref(),
+        _ => return Err(format!("Unsupported relay URL: {}", url)),
+    };
+    stream
+        .set_nonblocking(true)
+        .map_err(|err| err.to_string())?;
+    Ok(socket)
+}
+
+impl Socket {
+    // Returns right away, the socket opens in the background.
+    pub(crate) fn connect(url: &str, timeout: Duration) -> Result<Self, String> {
+        let (sender, attempt) = std::sync::mpsc::channel();
+        let url = url.to_string();
+        std::thread::Builder::new()
+            .name("ws-connect".to_string())
+            .spawn(move || {
+                let _ = sender.send(open(&url, timeout));
+            })
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            state: State::Connecting {
+                attempt,
+                outbox: Vec::new(),
+            },
+        })
+    }
+
+    pub(crate) fn send(&mut self, text: String) {
+        match &mut self.state {
+            State::Connecting { outbox, .. } => outbox.push(text),
+            State::Open(socket) => match socket.send(Message::text(text)) {
+                Ok(()) => {}
+                // Queued, flushed on a later poll.
+                Err(err) if would_block(&err) => {}
+                Err(_) => self.state = State::Closed,
+            },
+            State::Closed => {}
+        }
+    }
+
+    /// Returns false once the connection is closed or could not be opened.
+    pub(crate) fn poll(&mut self, incoming: &mut Vec<String>) -> bool {
+        if let State::Connecting { attempt, outbox } = &mut self.state {
+            match attempt.try_recv() {
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => self.state = State::Closed,
+                Ok(Err(err)) => {
+                    warn!("Could not reach the relay: {}", err);
+                    self.state = State::Closed;
+                }
+                Ok(Ok(socket)) => {
+                    let outbox = std::mem::take(outbox);
+                    self.state = State::Open(Box::new(socket));
+                    for text in outbox {
+                        self.send(text);
+                    }
+                }
+            }
+        }
+        let State::Open(socket) = &mut self.state else {
+            return false;
+        };
+        let mut closed = match socket.flush() {
+            Ok(()) => false,
+            Err(err) => !would_block(&err),
+        };
+        while !closed {
+            match socket.read() {
+                Ok(Message::Text(text)) => incoming.push(text),
+                Ok(Message::Close(_)) => closed = true,
+                Ok(_) => {}
+                Err(err) if would_block(&err) => break,
+                Err(_) => closed = true,
+            }
+        }
+        if closed {
+            self.state = State::Closed;
+        }
+        !closed
+    }
+
+    pub(crate) fn close(self) {
+        if let State::Open(mut socket) = self.state {
+            let _ = socket.close(None);
+            let _ = socket.flush();
+        }
+    }
+}
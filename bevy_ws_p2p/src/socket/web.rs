@@ -0,0 +1,88 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::{CloseEvent, Event, MessageEvent, WebSocket};
+
+pub(crate) struct Socket {
+    socket: WebSocket,
+    // Sent once the socket opens.
+    outbox: Vec<String>,
+    inbox: Rc<RefCell<Vec<String>>>,
+    closed: Rc<Cell<bool>>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+    _on_error: Closure<dyn FnMut(Event)>,
+}
+
+impl Socket {
+    // Returns right away, the socket opens in the background.
+    pub(crate) fn connect(url: &str, _timeout: Duration) -> Result<Self, String> {
+        let socket = WebSocket::new(url).map_err(|err| format!("{:?}", err))?;
+        let inbox = Rc::new(RefCell::new(Vec::new()));
+        let closed = Rc::new(Cell::new(false));
+
+        let on_message_inbox = inbox.clone();
+        let on_message = Closure::wrap(Box::new(move |ev: MessageEvent| {
+            if let Some(text) = ev.data().as_string() {
+                on_message_inbox.borrow_mut().push(text);
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let on_close_flag = closed.clone();
+        let on_close = Closure::wrap(Box::new(move |_ev: CloseEvent| {
+            on_close_flag.set(true);
+        }) as Box<dyn FnMut(CloseEvent)>);
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        let on_error_flag = closed.clone();
+        let on_error = Closure::wrap(Box::new(move |_ev: Event| {
+            on_error_flag.set(true);
+        }) as Box<dyn FnMut(Event)>);
+        socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            socket,
+            outbox: Vec::new(),
+            inbox,
+            closed,
+            _on_message: on_message,
+            _on_close: on_close,
+            _on_error: on_error,
+        })
+    }
+
+    pub(crate) fn send(&mut self, text: String) {
+        self.outbox.push(text);
+        self.flush();
+    }
+
+    /// Returns false once the connection is closed.
+    pub(crate) fn poll(&mut self, incoming: &mut Vec<String>) -> bool {
+        self.flush();
+        incoming.append(&mut self.inbox.borrow_mut());
+        !self.closed.get()
+    }
+
+    pub(crate) fn close(self) {
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        self.socket.set_onerror(None);
+        let _ = self.socket.close();
+    }
+
+    fn flush(&mut self) {
+        if self.socket.ready_state() != WebSocket::OPEN {
+            return;
+        }
+        for text in self.outbox.drain(..) {
+            if self.socket.send_with_str(&text).is_err() {
+                self.closed.set(true);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use bevy::prelude::*;
+use bevy_easy_p2p::{
+    ClientId, ExitReason, LobbyFailureReason, NetworkedId, P2PTransport, SendTarget,
+    TransportError, TransportEvent,
+};
+use p2p_relay::{FromRelay, JoinRejection, RelayTarget, ToRelay};
+
+use crate::RelayConfig;
+use crate::socket::Socket;
+
+// Keeps one peer from hogging the relay; bigger payloads get fragmented.
+const MAX_RELAY_MESSAGE_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Creating,
+    Joining,
+    InRoom,
+}
+
+/// Every message goes through a `p2p_relay` server, which hands out the lobby codes. Works from
+/// browsers and native builds alike, and for peers that cannot reach each other directly.
+#[derive(Default)]
+pub struct WebSocketRelayTransport {
+    connection: Option<(Socket, Stage)>,
+    pending_events: Vec<TransportEvent>,
+}
+
+impl WebSocketRelayTransport {
+    fn close(&mut self) {
+        if let Some((socket, _)) = self.connection.take() {
+            socket.close();
+        }
+    }
+
+    fn open(
+        &mut self,
+        world: &World,
+        request: ToRelay,
+        stage: Stage,
+    ) -> Result<(), TransportError> {
+        self.close();
+        let config = world.resource::<RelayConfig>();
+        let mut socket =
+            Socket::connect(&config.url, config.connect_timeout).map_err(TransportError::Other)?;
+        socket.send(serde_json::to_string(&request).unwrap());
+        self.connection = Some((socket, stage));
+        Ok(())
+    }
+
+    fn relay(&mut self, message: ToRelay) -> Result<(), TransportError> {
+        let Some((socket, Stage::InRoom)) = &mut self.connection else {
+            return Err(TransportError::NotConnected);
+        };
+        socket.send(serde_json::to_string(&message).unwrap());
+        Ok(())
+    }
+
+    fn handle(&mut self, message: FromRelay) {
+        let Some((_, stage)) = &mut self.connection else {
+            return;
+        };
+        match message {
+            FromRelay::RoomCreated { code } => {
+                *stage = Stage::InRoom;
+                self.pending_events
+                    .push(TransportEvent::LobbyCreated(code.clone()));
+                self.pending_events.push(TransportEvent::LobbyEntered(code));
+            }
+            FromRelay::RoomJoined { code } => {
+                *stage = Stage::InRoom;
+                self.pending_events
+                    .push(TransportEvent::LobbyJoined(code.clone()));
+                self.pending_events.push(TransportEvent::LobbyEntered(code));
+            }
+            FromRelay::JoinRejected { reason } => {
+                self.close();
+                let reason = match reason {
+                    JoinRejection::RoomNotFound => LobbyFailureReason::RoomNotFound,
+                };
+                self.pending_events.push(TransportEvent::JoinFailed(reason));
+            }
+            FromRelay::PeerJoined { client_id } => self
+                .pending_events
+                .push(TransportEvent::PeerConnected(client_id)),
+            FromRelay::PeerLeft { client_id } => self
+                .pending_events
+                .push(TransportEvent::PeerDisconnected(client_id)),
+            FromRelay::Received { from, payload } => {
+                self.pending_events.push(TransportEvent::Received {
+                    from: from.map_or(NetworkedId::Host, NetworkedId::ClientId),
+                    payload: payload.into_bytes(),
+                })
+            }
+            FromRelay::Kicked => {
+                self.close();
+                self.pending_events
+                    .push(TransportEvent::LobbyExited(ExitReason::Kicked));
+            }
+            FromRelay::RoomClosed => {
+                self.close();
+                self.pending_events
+                    .push(TransportEvent::LobbyExited(ExitReason::Disconnected));
+            }
+        }
+    }
+}
+
+impl P2PTransport for WebSocketRelayTransport {
+    fn setup(app: &mut App) {
+        app.init_resource::<RelayConfig>();
+    }
+
+    fn max_message_size(&self) -> usize {
+        MAX_RELAY_MESSAGE_SIZE
+    }
+
+    fn create_lobby(&mut self, world: &mut World) -> Result<(), TransportError> {
+        self.open(world, ToRelay::CreateRoom, Stage::Creating)
+    }
+
+    fn join_lobby(&mut self, world: &mut World, code: &str) -> Result<(), TransportError> {
+        let request = ToRelay::JoinRoom {
+            code: code.to_string(),
+        };
+        self.open(world, request, Stage::Joining)
+    }
+
+    fn exit_lobby(&mut self, _world: &mut World) {
+        self.close();
+        self.pending_events.clear();
+    }
+
+    fn kick(&mut self, _world: &mut World, client_id: ClientId) {
+        let _ = self.relay(ToRelay::Kick { client_id });
+    }
+
+    fn send(
+        &mut self,
+        _world: &mut World,
+        target: SendTarget,
+        payload: &[u8],
+    ) -> Result<(), TransportError> {
+        let payload = std::str::from_utf8(payload)
+            .map_err(|_| TransportError::InvalidPayload)?
+            .to_string();
+        let target = match target {
+            SendTarget::Host => RelayTarget::Host,
+            SendTarget::All => RelayTarget::All,
+            SendTarget::Client(client_id) => RelayTarget::Client(client_id),
+            SendTarget::AllExcept(client_id) => RelayTarget::AllExcept(client_id),
+        };
+        self.relay(ToRelay::Send { target, payload })
+    }
+
+    fn poll(&mut self, _world: &mut World, events: &mut Vec<TransportEvent>) {
+        if let Some((socket, _)) = &mut self.connection {
+            let mut incoming = Vec::new();
+            let open = socket.poll(&mut incoming);
+            for text in incoming {
+                match serde_json::from_str::<FromRelay>(&text) {
+                    Ok(message) => self.handle(message),
+                    Err(err) => warn!("Invalid message from the relay: {}", err),
+                }
+            }
+            // Unless a message above already closed it.
+            if !open && let Some((socket, stage)) = self.connection.take() {
+                socket.close();
+                let lost = || LobbyFailureReason::TransportError("lost the relay".to_string());
+                self.pending_events.push(match stage {
+                    Stage::Creating => TransportEvent::CreateFailed(lost()),
+                    Stage::Joining => TransportEvent::JoinFailed(lost()),
+                    Stage::InRoom => TransportEvent::LobbyExited(ExitReason::Disconnected),
+                });
+            }
+        }
+        events.append(&mut self.pending_events);
+    }
+}
@@ -0,0 +1,74 @@
+//! Rooms hosted by a relay server that forwards messages between one host and its clients, for
+//! peers that cannot reach each other directly. Peers talk to the relay with JSON text messages
+//! over a WebSocket.
+
+use serde::{Deserialize, Serialize};
+
+mod relay;
+
+pub use relay::{PeerId, Relay};
+
+pub const DEFAULT_PORT: u16 = 9001;
+
+pub type ClientId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayTarget {
+    Host,
+    All,
+    Client(ClientId),
+    AllExcept(ClientId),
+}
+
+/// Sent by peers to the relay.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ToRelay {
+    CreateRoom,
+    JoinRoom {
+        code: String,
+    },
+    Send {
+        target: RelayTarget,
+        payload: String,
+    },
+    /// Host only.
+    Kick {
+        client_id: ClientId,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinRejection {
+    RoomNotFound,
+}
+
+/// Sent by the relay to peers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FromRelay {
+    RoomCreated {
+        code: String,
+    },
+    RoomJoined {
+        code: String,
+    },
+    JoinRejected {
+        reason: JoinRejection,
+    },
+    /// Host only.
+    PeerJoined {
+        client_id: ClientId,
+    },
+    /// Host only.
+    PeerLeft {
+        client_id: ClientId,
+    },
+    /// `from` is `None` when the host sent it.
+    Received {
+        from: Option<ClientId>,
+        payload: String,
+    },
+    /// The relay closes the connection right after.
+    Kicked,
+    /// The host left, taking the room with it.
+    RoomClosed,
+}
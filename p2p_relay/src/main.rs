@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use p2p_relay::{DEFAULT_PORT, FromRelay, PeerId, Relay, ToRelay};
+use tungstenite::{Error, Message};
+
+const USAGE: &str = "Usage: p2p_relay [BIND_ADDRESS]";
+const ROOM_CODE_LENGTH: usize = 4;
+// How long a connection waits for incoming messages before forwarding its outgoing ones.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Default)]
+struct Shared {
+    relay: Relay,
+    outboxes: HashMap<PeerId, Sender<FromRelay>>,
+}
+
+impl Shared {
+    fn dispatch(&self, outgoing: Vec<(PeerId, FromRelay)>) {
+        for (peer, message) in outgoing {
+            if let Some(outbox) = self.outboxes.get(&peer) {
+                let _ = outbox.send(message);
+            }
+        }
+    }
+}
+
+fn main() {
+    let address = match std::env::args().nth(1) {
+        Some(arg) if arg == "--help" => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(address) => address,
+        None => format!("0.0.0.0:{}", DEFAULT_PORT),
+    };
+    let listener = TcpListener::bind(&address).unwrap_or_else(|err| {
+        eprintln!("Could not listen on {}: {}", address, err);
+        std::process::exit(1);
+    });
+    println!("Relay listening on {}", address);
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    for (peer, stream) in (1..).zip(listener.incoming()) {
+        match stream {
+            Ok(stream) => {
+                let shared = shared.clone();
+                thread::spawn(move || serve(peer, stream, shared));
+            }
+            Err(err) => eprintln!("Could not accept a connection: {}", err),
+        }
+    }
+}
+
+fn serve(peer: PeerId, stream: TcpStream, shared: Arc<Mutex<Shared>>) {
+    let _ = stream.set_nodelay(true);
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("Handshake with peer {} failed: {}", peer, err);
+            return;
+        }
+    };
+    if let Err(err) = socket.get_mut().set_read_timeout(Some(POLL_INTERVAL)) {
+        eprintln!("Dropping peer {}: {}", peer, err);
+        return;
+    }
+    let (outbox, inbox) = mpsc::channel();
+    shared.lock().unwrap().outboxes.insert(peer, outbox);
+
+    'connection: loop {
+        for message in inbox.try_iter() {
+            let kicked = message == FromRelay::Kicked;
+            let text = serde_json::to_string(&message).unwrap();
+            if socket.send(Message::text(text)).is_err() {
+                break 'connection;
+            }
+            if kicked {
+                let _ = socket.close(None);
+                let _ = socket.flush();
+                break 'connection;
+            }
+        }
+        match socket.read() {
+            Ok(Message::Text(text)) => match serde_json::from_str::<ToRelay>(&text) {
+                Ok(message) => {
+                    let mut shared = shared.lock().unwrap();
+                    let outgoing = shared.relay.handle(peer, message, generate_room_code);
+                    shared.dispatch(outgoing);
+                }
+                Err(err) => eprintln!("Peer {} sent an invalid message: {}", peer, err),
+            },
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+
+    let mut shared = shared.lock().unwrap();
+    shared.outboxes.remove(&peer);
+    let outgoing = shared.relay.disconnect(peer);
+    shared.dispatch(outgoing);
+}
+
+fn generate_room_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    // Every `RandomState` is seeded differently, which is random enough for room codes.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    let mut bits = hasher.finish();
+    (0..ROOM_CODE_LENGTH)
+        .map(|_| {
+            let letter = ALPHABET[(bits % ALPHABET.len() as u64) as usize];
+            bits /= ALPHABET.len() as u64;
+            letter as char
+        })
+        .collect()
+}
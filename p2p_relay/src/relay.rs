@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+
+use crate::{ClientId, FromRelay, JoinRejection, RelayTarget, ToRelay};
+
+/// A connection to the relay, numbered by the server.
+pub type PeerId = u64;
+
+struct Room {
+    host: PeerId,
+    clients: HashMap<ClientId, PeerId>,
+}
+
+enum Membership {
+    Host(String),
+    Client(String, ClientId),
+}
+
+/// Room bookkeeping of the relay, independent of how peers are connected. Every call returns
+/// the messages to deliver and who to deliver them to.
+#[derive(Default)]
+pub struct Relay {
+    rooms: HashMap<String, Room>,
+    memberships: HashMap<PeerId, Membership>,
+    next_client_id: ClientId,
+}
+
+impl Relay {
+    /// `generate_code` is called until it returns a code no open room uses.
+    pub fn handle(
+        &mut self,
+        peer: PeerId,
+        message: ToRelay,
+        mut generate_code: impl FnMut() -> String,
+    ) -> Vec<(PeerId, FromRelay)> {
+        let mut outgoing = Vec::new();
+        match message {
+            ToRelay::CreateRoom => {
+                self.leave(peer, &mut outgoing);
+                let code = loop {
+                    let code = generate_code();
+                    if !self.rooms.contains_key(&code) {
+                        break code;
+                    }
+                };
+                self.rooms.insert(
+                    code.clone(),
+                    Room {
+                        host: peer,
+                        clients: HashMap::new(),
+                    },
+                );
+                self.memberships
+                    .insert(peer, Membership::Host(code.clone()));
+                outgoing.push((peer, FromRelay::RoomCreated { code }));
+            }
+            ToRelay::JoinRoom { code } => {
+                self.leave(peer, &mut outgoing);
+                let code = code.trim().to_uppercase();
+                let Some(room) = self.rooms.get_mut(&code) else {
+                    outgoing.push((
+                        peer,
+                        FromRelay::JoinRejected {
+                            reason: JoinRejection::RoomNotFound,
+                        },
+                    ));
+                    return outgoing;
+                };
+                self.next_client_id += 1;
+                let client_id = self.next_client_id;
+                room.clients.insert(client_id, peer);
+                outgoing.push((room.host, FromRelay::PeerJoined { client_id }));
+                outgoing.push((peer, FromRelay::RoomJoined { code: code.clone() }));
+                self.memberships
+                    .insert(peer, Membership::Client(code, client_id));
+            }
+            ToRelay::Send { target, payload } => match self.memberships.get(&peer) {
+                Some(Membership::Host(code)) => {
+                    let room = &self.rooms[code];
+                    for (client_id, client) in room.clients.iter() {
+                        let addressed = match target {
+                            RelayTarget::Host => false,
+                            RelayTarget::All => true,
+                            RelayTarget::Client(id) => id == *client_id,
+                            RelayTarget::AllExcept(id) => id != *client_id,
+                        };
+                        if addressed {
+                            outgoing.push((
+                                *client,
+                                FromRelay::Received {
+                                    from: None,
+                                    payload: payload.clone(),
+                                },
+                            ));
+                        }
+                    }
+                }
+                // Clients only ever talk to their host.
+                Some(Membership::Client(code, client_id)) => {
+                    outgoing.push((
+                        self.rooms[code].host,
+                        FromRelay::Received {
+                            from: Some(*client_id),
+                            payload,
+                        },
+                    ));
+                }
+                None => {}
+            },
+            ToRelay::Kick { client_id } => {
+                let Some(Membership::Host(code)) = self.memberships.get(&peer) else {
+                    return outgoing;
+                };
+                let room = self.rooms.get_mut(code).unwrap();
+                if let Some(client) = room.clients.remove(&client_id) {
+                    self.memberships.remove(&client);
+                    outgoing.push((client, FromRelay::Kicked));
+                    outgoing.push((peer, FromRelay::PeerLeft { client_id }));
+                }
+            }
+        }
+        outgoing
+    }
+
+    /// Forgets a peer whose connection closed.
+    pub fn disconnect(&mut self, peer: PeerId) -> Vec<(PeerId, FromRelay)> {
+        let mut outgoing = Vec::new();
+        self.leave(peer, &mut outgoing);
+        outgoing
+    }
+
+    fn leave(&mut self, peer: PeerId, outgoing: &mut Vec<(PeerId, FromRelay)>) {
+        match self.memberships.remove(&peer) {
+            Some(Membership::Host(code)) => {
+                let Some(room) = self.rooms.remove(&code) else {
+                    return;
+                };
+                for client in room.clients.into_values() {
+                    self.memberships.remove(&client);
+                    outgoing.push((client, FromRelay::RoomClosed));
+                }
+            }
+            Some(Membership::Client(code, client_id)) => {
+                let Some(room) = self.rooms.get_mut(&code) else {
+                    return;
+                };
+                room.clients.remove(&client_id);
+                outgoing.push((room.host, FromRelay::PeerLeft { client_id }));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: PeerId = 1;
+    const FIRST: PeerId = 2;
+    const SECOND: PeerId = 3;
+
+    fn send(target: RelayTarget, payload: &str) -> ToRelay {
+        ToRelay::Send {
+            target,
+            payload: payload.to_string(),
+        }
+    }
+
+    fn received(from: Option<ClientId>, payload: &str) -> FromRelay {
+        FromRelay::Received {
+            from,
+            payload: payload.to_string(),
+        }
+    }
+
+    // A room with two clients, numbered 1 and 2 in the order they joined.
+    fn room() -> Relay {
+        let mut relay = Relay::default();
+        let created = relay.handle(HOST, ToRelay::CreateRoom, || "ABCD".to_string());
+        assert_eq!(
+            created,
+            vec![(
+                HOST,
+                FromRelay::RoomCreated {
+                    code: "ABCD".to_string()
+                }
+            )]
+        );
+        for peer in [FIRST, SECOND] {
+            let code = "abcd ".to_string();
+            relay.handle(peer, ToRelay::JoinRoom { code }, String::new);
+        }
+        relay
+    }
+
+    #[test]
+    fn joining_tells_the_host() {
+        let mut relay = room();
+        let joined = relay.handle(
+            4,
+            ToRelay::JoinRoom {
+                code: "ABCD".to_string(),
+            },
+            String::new,
+        );
+        assert_eq!(
+            joined,
+            vec![
+                (HOST, FromRelay::PeerJoined { client_id: 3 }),
+                (
+                    4,
+                    FromRelay::RoomJoined {
+                        code: "ABCD".to_string()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_rooms_are_rejected() {
+        let mut relay = Relay::default();
+        let joined = relay.handle(
+            FIRST,
+            ToRelay::JoinRoom {
+                code: "WXYZ".to_string(),
+            },
+            String::new,
+        );
+        assert_eq!(
+            joined,
+            vec![(
+                FIRST,
+                FromRelay::JoinRejected {
+                    reason: JoinRejection::RoomNotFound
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn taken_codes_are_generated_again() {
+        let mut relay = room();
+        let mut codes = ["ABCD", "EFGH"].into_iter();
+        let created = relay.handle(4, ToRelay::CreateRoom, || codes.next().unwrap().to_string());
+        assert_eq!(
+            created,
+            vec![(
+                4,
+                FromRelay::RoomCreated {
+                    code: "EFGH".to_string()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn host_messages_reach_the_addressed_clients() {
+        let mut relay = room();
+        let mut deliveries = |target| relay.handle(HOST, send(target, "hi"), String::new);
+        assert_eq!(
+            deliveries(RelayTarget::Client(2)),
+            vec![(SECOND, received(None, "hi"))]
+        );
+        assert_eq!(
+            deliveries(RelayTarget::AllExcept(2)),
+            vec![(FIRST, received(None, "hi"))]
+        );
+        let mut all = deliveries(RelayTarget::All);
+        all.sort_by_key(|(peer, _)| *peer);
+        assert_eq!(
+            all,
+            vec![
+                (FIRST, received(None, "hi")),
+                (SECOND, received(None, "hi"))
+            ]
+        );
+        assert!(deliveries(RelayTarget::Host).is_empty());
+    }
+
+    #[test]
+    fn client_messages_only_reach_the_host() {
+        let mut relay = room();
+        for target in [RelayTarget::All, RelayTarget::Client(1), RelayTarget::Host] {
+            let delivered = relay.handle(SECOND, send(target, "hi"), String::new);
+            assert_eq!(delivered, vec![(HOST, received(Some(2), "hi"))]);
+        }
+    }
+
+    #[test]
+    fn peers_outside_rooms_cannot_send() {
+        let mut relay = room();
+        assert!(
+            relay
+                .handle(4, send(RelayTarget::All, "hi"), String::new)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn kicked_clients_stop_receiving() {
+        let mut relay = room();
+        let kicked = relay.handle(HOST, ToRelay::Kick { client_id: 1 }, String::new);
+        assert_eq!(
+            kicked,
+            vec![
+                (FIRST, FromRelay::Kicked),
+                (HOST, FromRelay::PeerLeft { client_id: 1 }),
+            ]
+        );
+        let delivered = relay.handle(HOST, send(RelayTarget::All, "hi"), String::new);
+        assert_eq!(delivered, vec![(SECOND, received(None, "hi"))]);
+        assert!(
+            relay
+                .handle(FIRST, send(RelayTarget::Host, "hi"), String::new)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn clients_cannot_kick() {
+        let mut relay = room();
+        assert!(
+            relay
+                .handle(FIRST, ToRelay::Kick { client_id: 2 }, String::new)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn departed_clients_are_announced() {
+        let mut relay = room();
+        assert_eq!(
+            relay.disconnect(FIRST),
+            vec![(HOST, FromRelay::PeerLeft { client_id: 1 })]
+        );
+    }
+
+    #[test]
+    fn the_room_closes_with_its_host() {
+        let mut relay = room();
+        let mut closed = relay.disconnect(HOST);
+        closed.sort_by_key(|(peer, _)| *peer);
+        assert_eq!(
+            closed,
+            vec![
+                (FIRST, FromRelay::RoomClosed),
+                (SECOND, FromRelay::RoomClosed)
+            ]
+        );
+        assert!(
+            relay
+                .handle(FIRST, send(RelayTarget::Host, "hi"), String::new)
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,122 @@
+// Just enough HTTP/1.1 for browsers to fetch JSON: one request per connection, bodies sized by
+// `Content-Length`.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+// Offers and answers are a few kilobytes, anything much bigger is not for us.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    /// From an `Authorization: Bearer` header.
+    pub bearer_token: Option<String>,
+    pub body: Vec<u8>,
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    pub body: Option<serde_json::Value>,
+}
+
+impl Response {
+    pub(crate) fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            body: Some(body),
+        }
+    }
+
+    pub(crate) fn empty(status: u16) -> Self {
+        Self { status, body: None }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub(crate) fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let method = method.to_string();
+    // Queries are not used by the API.
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut content_length = 0;
+    let mut bearer_token = None;
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid("bad content length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            bearer_token = value
+                .trim()
+                .strip_prefix("Bearer ")
+                .map(|token| token.trim().to_string());
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(invalid("body too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request {
+        method,
+        path,
+        bearer_token,
+        body,
+    })
+}
+
+pub(crate) fn write_response(mut stream: &TcpStream, response: Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "",
+    };
+    let body = response
+        .body
+        .map(|body| body.to_string())
+        .unwrap_or_default();
+    // Games are served from other origins than the signaling server.
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST, PUT, DELETE, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Content-Type, Authorization\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        response.status,
+        reason,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,369 @@
+//! Stores rooms and the offers and answers exchanged to join them, for WebRTC games that would
+//! rather not depend on Firestore.
+//!
+//! - `GET /rooms/{code}`: `{"offers": {client_id: sdp}, "answers": {client_id: sdp},
+//!   "offer_candidates": {client_id: [candidate]}, "answer_candidates": {client_id: [candidate]}}`
+//! - `POST /rooms/{code}` with `{"ttl_secs": seconds}` as body: creates the room and replies
+//!   `{"host_token": token}`, `409` if it is taken
+//! - `PUT /rooms/{code}` with `{"ttl_secs": seconds}` as body: keeps the room alive that much
+//!   longer
+//! - `DELETE /rooms/{code}`
+//!
+//! Refreshing and deleting a room take its host token as `Authorization: Bearer {token}`, `403`
+//! without it.
+//! - `PUT /rooms/{code}/offers/{client_id}` and `PUT /rooms/{code}/answers/{client_id}`, with
+//!   `{"sdp": sdp}` as body
+//! - `POST /rooms/{code}/offers/{client_id}/candidates` and
+//!   `POST /rooms/{code}/answers/{client_id}/candidates`, with `{"candidates": [candidate]}` as
+//!   body: adds ICE candidates as they are gathered
+//!
+//! Rooms that are not kept alive are gone once their time runs out.
+
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::http::{Request, Response, read_request, write_response};
+
+mod http;
+
+const USAGE: &str = "Usage: p2p_signaling [BIND_ADDRESS]";
+const DEFAULT_PORT: u16 = 9002;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Room {
+    offers: Map<String, Value>,
+    answers: Map<String, Value>,
+    offer_candidates: Map<String, Value>,
+    answer_candidates: Map<String, Value>,
+    expires_at: Instant,
+    host_token: String,
+}
+
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+fn main() {
+    let address = match std::env::args().nth(1) {
+        Some(arg) if arg == "--help" => {
+            println!("{}", USAGE);
+            return;
+        }
+        Some(address) => address,
+        None => format!("0.0.0.0:{}", DEFAULT_PORT),
+    };
+    let listener = TcpListener::bind(&address).unwrap_or_else(|err| {
+        eprintln!("Could not listen on {}: {}", address, err);
+        std::process::exit(1);
+    });
+    println!("Signaling server listening on {}", address);
+    let rooms = Rooms::default();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let rooms = rooms.clone();
+                thread::spawn(move || serve(stream, rooms));
+            }
+            Err(err) => eprintln!("Could not accept a connection: {}", err),
+        }
+    }
+}
+
+fn serve(stream: TcpStream, rooms: Rooms) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+    let response = match read_request(&stream) {
+        Ok(request) => route(&request, &mut rooms.lock().unwrap()),
+        Err(err) => Response::json(400, json!({ "error": err.to_string() })),
+    };
+    let _ = write_response(&stream, response);
+}
+
+fn route(request: &Request, rooms: &mut HashMap<String, Room>) -> Response {
+    if request.method == "OPTIONS" {
+        return Response::empty(204);
+    }
+    let now = Instant::now();
+    rooms.retain(|_, room| room.expires_at > now);
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["rooms", code]) => match rooms.get(*code) {
+            Some(room) => Response::json(
+                200,
+                json!({
+                    "offers": room.offers,
+                    "answers": room.answers,
+                    "offer_candidates": room.offer_candidates,
+                    "answer_candidates": room.answer_candidates,
+                }),
+            ),
+            None => not_found(),
+        },
+        ("POST", ["rooms", code]) => {
+            let Some(ttl) = ttl(request) else {
+                return bad_request("expected {\"ttl_secs\": number}");
+            };
+            if rooms.contains_key(*code) {
+                return Response::json(409, json!({ "error": "room code taken" }));
+            }
+            let token = host_token();
+            rooms.insert(
+                code.to_string(),
+                Room {
+                    offers: Map::new(),
+                    answers: Map::new(),
+                    offer_candidates: Map::new(),
+                    answer_candidates: Map::new(),
+                    expires_at: now + ttl,
+                    host_token: token.clone(),
+                },
+            );
+            Response::json(200, json!({ "host_token": token }))
+        }
+        ("PUT", ["rooms", code]) => {
+            let Some(ttl) = ttl(request) else {
+                return bad_request("expected {\"ttl_secs\": number}");
+            };
+            match rooms.get_mut(*code) {
+                Some(room) if !is_host(request, room) => forbidden(),
+                Some(room) => {
+                    room.expires_at = now + ttl;
+                    Response::json(200, json!({}))
+                }
+                None => not_found(),
+            }
+        }
+        ("DELETE", ["rooms", code]) => match rooms.get(*code) {
+            Some(room) if !is_host(request, room) => forbidden(),
+            Some(_) => {
+                rooms.remove(*code);
+                Response::json(200, json!({}))
+            }
+            None => not_found(),
+        },
+        ("PUT", ["rooms", code, kind @ ("offers" | "answers"), client_id]) => {
+            let Some(room) = rooms.get_mut(*code) else {
+                return not_found();
+            };
+            let Some(sdp) = serde_json::from_slice::<Value>(&request.body)
+                .ok()
+                .and_then(|body| body.get("sdp").and_then(Value::as_str).map(str::to_string))
+            else {
+                return bad_request("expected {\"sdp\": string}");
+            };
+            let entries = if *kind == "offers" {
+                &mut room.offers
+            } else {
+                &mut room.answers
+            };
+            entries.insert(client_id.to_string(), Value::String(sdp));
+            Response::json(200, json!({}))
+        }
+        (
+            "POST",
+            [
+                "rooms",
+                code,
+                kind @ ("offers" | "answers"),
+                client_id,
+                "candidates",
+            ],
+        ) => {
+            let Some(room) = rooms.get_mut(*code) else {
+                return not_found();
+            };
+            let Some(candidates) = serde_json::from_slice::<Value>(&request.body)
+                .ok()
+                .and_then(|body| body.get("candidates").and_then(Value::as_array).cloned())
+                .filter(|candidates| candidates.iter().all(Value::is_string))
+            else {
+                return bad_request("expected {\"candidates\": [string]}");
+            };
+            let entries = if *kind == "offers" {
+                &mut room.offer_candidates
+            } else {
+                &mut room.answer_candidates
+            };
+            if let Value::Array(known) = entries
+                .entry(client_id.to_string())
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                known.extend(candidates);
+            }
+            Response::json(200, json!({}))
+        }
+        (_, ["rooms", ..]) => Response::json(405, json!({ "error": "method not allowed" })),
+        _ => not_found(),
+    }
+}
+
+fn ttl(request: &Request) -> Option<Duration> {
+    let body = serde_json::from_slice::<Value>(&request.body).ok()?;
+    body.get("ttl_secs")?.as_u64().map(Duration::from_secs)
+}
+
+// Every `RandomState` is seeded differently, so only whoever created the room knows its token.
+fn host_token() -> String {
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            );
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+fn is_host(request: &Request, room: &Room) -> bool {
+    request.bearer_token.as_deref() == Some(room.host_token.as_str())
+}
+
+fn bad_request(error: &str) -> Response {
+    Response::json(400, json!({ "error": error }))
+}
+
+fn forbidden() -> Response {
+    Response::json(403, json!({ "error": "not the host of this room" }))
+}
+
+fn not_found() -> Response {
+    Response::json(404, json!({ "error": "not found" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_with_token(
+        rooms: &mut HashMap<String, Room>,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: Value,
+    ) -> Response {
+        let request = Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            bearer_token: token.map(str::to_string),
+            body: serde_json::to_vec(&body).unwrap(),
+        };
+        route(&request, rooms)
+    }
+
+    fn send(rooms: &mut HashMap<String, Room>, method: &str, path: &str, body: Value) -> Response {
+        send_with_token(rooms, method, path, None, body)
+    }
+
+    // Returns the host token.
+    fn create(rooms: &mut HashMap<String, Room>, ttl_secs: u64) -> String {
+        let created = send(
+            rooms,
+            "POST",
+            "/rooms/ABCD",
+            json!({ "ttl_secs": ttl_secs }),
+        );
+        assert_eq!(created.status, 200);
+        created.body.unwrap()["host_token"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn created_rooms_can_be_read() {
+        let mut rooms = HashMap::new();
+        create(&mut rooms, 60);
+        let offer = send(
+            &mut rooms,
+            "PUT",
+            "/rooms/ABCD/offers/1",
+            json!({ "sdp": "offer" }),
+        );
+        assert_eq!(offer.status, 200);
+        let room = send(&mut rooms, "GET", "/rooms/ABCD", Value::Null);
+        assert_eq!(room.status, 200);
+        assert_eq!(room.body.unwrap()["offers"]["1"], "offer");
+    }
+
+    #[test]
+    fn taken_codes_are_refused() {
+        let mut rooms = HashMap::new();
+        create(&mut rooms, 60);
+        let taken = send(&mut rooms, "POST", "/rooms/ABCD", json!({ "ttl_secs": 60 }));
+        assert_eq!(taken.status, 409);
+    }
+
+    #[test]
+    fn unknown_rooms_are_not_found() {
+        let mut rooms = HashMap::new();
+        let room = send(&mut rooms, "GET", "/rooms/ABCD", Value::Null);
+        assert_eq!(room.status, 404);
+    }
+
+    #[test]
+    fn rooms_expire_unless_refreshed() {
+        let mut rooms = HashMap::new();
+        create(&mut rooms, 0);
+        assert_eq!(
+            send(&mut rooms, "GET", "/rooms/ABCD", Value::Null).status,
+            404
+        );
+
+        let token = create(&mut rooms, 60);
+        let refreshed = send_with_token(
+            &mut rooms,
+            "PUT",
+            "/rooms/ABCD",
+            Some(&token),
+            json!({ "ttl_secs": 3600 }),
+        );
+        assert_eq!(refreshed.status, 200);
+        assert!(rooms["ABCD"].expires_at > Instant::now() + Duration::from_secs(60));
+        assert_eq!(
+            send(&mut rooms, "GET", "/rooms/ABCD", Value::Null).status,
+            200
+        );
+    }
+
+    #[test]
+    fn deleted_rooms_are_gone() {
+        let mut rooms = HashMap::new();
+        let token = create(&mut rooms, 60);
+        let delete = |rooms: &mut HashMap<String, Room>| {
+            send_with_token(rooms, "DELETE", "/rooms/ABCD", Some(&token), Value::Null).status
+        };
+        assert_eq!(delete(&mut rooms), 200);
+        assert_eq!(
+            send(&mut rooms, "GET", "/rooms/ABCD", Value::Null).status,
+            404
+        );
+        assert_eq!(delete(&mut rooms), 404);
+    }
+
+    #[test]
+    fn only_the_host_refreshes_and_deletes() {
+        let mut rooms = HashMap::new();
+        let token = create(&mut rooms, 60);
+        let other = host_token();
+        assert_ne!(token, other);
+        for bearer in [None, Some(other.as_str())] {
+            let refreshed = send_with_token(
+                &mut rooms,
+                "PUT",
+                "/rooms/ABCD",
+                bearer,
+                json!({ "ttl_secs": 3600 }),
+            );
+            assert_eq!(refreshed.status, 403);
+            let deleted = send_with_token(&mut rooms, "DELETE", "/rooms/ABCD", bearer, Value::Null);
+            assert_eq!(deleted.status, 403);
+        }
+        assert!(rooms.contains_key("ABCD"));
+    }
+}
@@ -1,7 +1,7 @@
-use crate::{AppP2PUpdate, KartEasyP2P};
+use crate::KartProtocol;
 use avian2d::prelude::*;
 use bevy::prelude::*;
-use bevy_easy_p2p::EasyP2PUpdate;
+use bevy_easy_p2p::{EasyP2P, PlayerInputs};
 
 pub struct CarController2dPlugin;
 
@@ -53,19 +53,12 @@ fn car_controller_power(
         ),
     >,
     wheels: Query<(&GlobalTransform, &CarController2dWheel)>,
-    mut param_set: ParamSet<(KartEasyP2P, MessageReader<AppP2PUpdate>)>,
+    easy: EasyP2P<KartProtocol>,
+    inputs: Res<PlayerInputs<KartProtocol>>,
 ) {
-    let inputs = param_set
-        .p1()
-        .read()
-        .filter_map(|AppP2PUpdate(update)| match update {
-            EasyP2PUpdate::ClientInput { sender, input } => Some((sender.clone(), input.clone())),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
-    for (sender, input) in inputs {
+    for (sender, input) in inputs.iter() {
         for (mut force, entity, children, car) in cars.iter_mut() {
-            if !param_set.p0().inputs_belong_to_player(entity, &sender) {
+            if !easy.inputs_belong_to_player(entity, sender) {
                 continue;
             }
             let mut dir = None;
@@ -97,19 +90,12 @@ fn car_controller_power(
 fn car_controller_steering(
     mut cars: Query<(Entity, &Children), With<CarController2d>>,
     mut wheels: Query<(&mut Transform, &CarController2dWheel)>,
-    mut param_set: ParamSet<(KartEasyP2P, MessageReader<AppP2PUpdate>)>,
+    easy: EasyP2P<KartProtocol>,
+    inputs: Res<PlayerInputs<KartProtocol>>,
 ) {
-    let inputs = param_set
-        .p1()
-        .read()
-        .filter_map(|AppP2PUpdate(update)| match update {
-            EasyP2PUpdate::ClientInput { sender, input } => Some((sender.clone(), input.clone())),
-            _ => None,
-        })
-        .collect::<Vec<_>>();
-    for (sender, input) in inputs {
+    for (sender, input) in inputs.iter() {
         for (entity, children) in cars.iter_mut() {
-            if !param_set.p0().inputs_belong_to_player(entity, &sender) {
+            if !easy.inputs_belong_to_player(entity, sender) {
                 continue;
             }
             let mut dir: f32 = 0.;
@@ -0,0 +1,120 @@
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy_easy_p2p::prelude::*;
+use bevy_easy_p2p::{EasyP2PSystemSet, EasyP2PUpdate};
+use std::time::Duration;
+
+use crate::launch::join_hint;
+use crate::{AppState, KartProtocol};
+
+// Seconds between the first player showing up in the lobby and the race starting.
+const RACE_START_DELAY: f32 = 10.;
+// Seconds before trying again when the lobby could not be created.
+const LOBBY_RETRY_DELAY: f32 = 5.;
+const TICK_RATE: f64 = 60.;
+
+/// Runs the game without a window, audio or local player: the host keeps a lobby open, starts a
+/// race whenever players are waiting in it, and simulates it for them.
+pub struct DedicatedHostPlugin;
+
+impl Plugin for DedicatedHostPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1. / TICK_RATE,
+            ))),
+            StatesPlugin,
+            TransformPlugin,
+            // Asset and mesh storage avian2d expects to find.
+            AssetPlugin::default(),
+            bevy::mesh::MeshPlugin,
+            bevy::scene::ScenePlugin,
+        ))
+        .init_resource::<LobbyRetry>()
+        .init_resource::<RaceCountdown>()
+        .add_systems(OnEnter(P2PLobbyState::OutOfLobby), open_lobby)
+        .add_systems(
+            Update,
+            (
+                watch_lobby.after(EasyP2PSystemSet::Emit),
+                retry_lobby.run_if(in_state(P2PLobbyState::OutOfLobby)),
+                start_races
+                    .run_if(in_state(P2PLobbyState::InLobby).and(in_state(AppState::OutOfGame))),
+                abandon_empty_races.run_if(in_state(AppState::Game)),
+            ),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+struct LobbyRetry(Option<f32>);
+
+#[derive(Resource, Default)]
+struct RaceCountdown(Option<f32>);
+
+fn open_lobby(mut easy: EasyP2P<KartProtocol>) {
+    easy.create_lobby();
+}
+
+fn watch_lobby(
+    time: Res<Time>,
+    mut retry: ResMut<LobbyRetry>,
+    mut events: MessageReader<EasyP2PUpdate<KartProtocol>>,
+) {
+    for update in events.read() {
+        match update {
+            EasyP2PUpdate::LobbyCreated { code } => {
+                info!("Dedicated host room: {}", code);
+                if let Some(hint) = join_hint(code) {
+                    info!("{}", hint);
+                }
+            }
+            EasyP2PUpdate::CreateFailed { reason } => {
+                warn!(
+                    "Could not create the room ({}), retrying in {}s",
+                    reason, LOBBY_RETRY_DELAY
+                );
+                retry.0 = Some(time.elapsed_secs() + LOBBY_RETRY_DELAY);
+            }
+            EasyP2PUpdate::LobbyExited { reason } => warn!("Room closed: {:?}", reason),
+            _ => {}
+        }
+    }
+}
+
+fn retry_lobby(time: Res<Time>, mut retry: ResMut<LobbyRetry>, mut easy: EasyP2P<KartProtocol>) {
+    if retry.0.is_some_and(|at| time.elapsed_secs() >= at) {
+        retry.0 = None;
+        easy.create_lobby();
+    }
+}
+
+fn start_races(
+    time: Res<Time>,
+    mut easy: EasyP2P<KartProtocol>,
+    mut countdown: ResMut<RaceCountdown>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if easy.get_players().is_empty() {
+        countdown.0 = None;
+        return;
+    }
+    let now = time.elapsed_secs();
+    let Some(started) = countdown.0 else {
+        countdown.0 = Some(now);
+        easy.send_message_all(format!("Race starting in {} seconds", RACE_START_DELAY));
+        return;
+    };
+    if now - started >= RACE_START_DELAY {
+        countdown.0 = None;
+        next_state.set(AppState::Game);
+    }
+}
+
+fn abandon_empty_races(easy: EasyP2P<KartProtocol>, mut next_state: ResMut<NextState<AppState>>) {
+    if easy.get_players().is_empty() {
+        info!("Every player left, back to the lobby");
+        next_state.set(AppState::OutOfGame);
+    }
+}
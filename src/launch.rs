@@ -0,0 +1,269 @@
+use bevy::prelude::*;
+use bevy_easy_p2p::prelude::*;
+use std::path::PathBuf;
+
+use crate::{AppPlayerData, CAR_COLORS_COUNT, KartColor, KartProtocol, MAX_NAME_LENGTH};
+
+const USAGE: &str = "\
+Usage: bevy_kart [OPTIONS]
+
+Options:
+  --host          Create a lobby right away
+  --join CODE     Join the lobby with that code right away
+  --name NAME     Player name
+  --color INDEX   Kart color, from 0 to 9
+  --dedicated     Run a headless host that is not a player itself, for builds with the
+                  relay feature
+  --relay URL     Relay server to go through, for builds with the relay feature
+  --replay PATH   Play back a network capture saved with F10 instead of going online
+  --help          Print this message";
+
+/// How the game was asked to start: from the command line natively, from the page URL in
+/// browsers.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct LaunchOptions {
+    pub host: bool,
+    pub join: Option<String>,
+    pub name: Option<String>,
+    pub color: Option<KartColor>,
+    pub dedicated: bool,
+    pub relay: Option<String>,
+    /// Native only: capture fed to the plugin instead of a live transport.
+    pub replay: Option<PathBuf>,
+    /// Browsers only: base URL of a `p2p_signaling` server to use instead of Firestore.
+    pub signaling: Option<String>,
+}
+
+impl LaunchOptions {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn current() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(options) => options,
+            Err(err) => {
+                eprintln!("{}\n\n{}", err, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn current() -> Self {
+        Self {
+            join: extract_query_param("room"),
+            relay: extract_query_param("relay"),
+            signaling: extract_query_param("signaling"),
+            ..default()
+        }
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--host" => options.host = true,
+                "--join" => options.join = Some(value()?),
+                "--name" => options.name = Some(value()?),
+                "--color" => {
+                    let color = value()?;
+                    let index = color
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|index| *index < CAR_COLORS_COUNT)
+                        .ok_or_else(|| format!("Invalid color: {}", color))?;
+                    options.color = Some(KartColor(index));
+                }
+                "--dedicated" => options.dedicated = true,
+                "--relay" => options.relay = Some(value()?),
+                "--replay" => options.replay = Some(value()?.into()),
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("Unknown option: {}", arg)),
+            }
+        }
+        if options.join.is_some() && (options.host || options.dedicated) {
+            return Err("--join cannot be combined with --host or --dedicated".to_string());
+        }
+        if options.replay.is_some()
+            && (options.host
+                || options.join.is_some()
+                || options.dedicated
+                || options.relay.is_some())
+        {
+            return Err(
+                "--replay cannot be combined with --host, --join, --dedicated or --relay"
+                    .to_string(),
+            );
+        }
+        if cfg!(not(feature = "relay")) && options.relay.is_some() {
+            return Err("--relay needs a build with the relay feature".to_string());
+        }
+        // Browsers cannot reach the native transports, only a relay gets players to the host.
+        if cfg!(not(feature = "relay")) && options.dedicated {
+            return Err(
+                "--dedicated needs a build with the relay feature, browsers cannot reach a native host"
+                    .to_string(),
+            );
+        }
+        Ok(options)
+    }
+
+    // Player data the menu starts from.
+    pub(crate) fn player_data(&self) -> AppPlayerData {
+        AppPlayerData {
+            name: self
+                .name
+                .as_deref()
+                .unwrap_or("YOUR_NAME")
+                .chars()
+                .take(MAX_NAME_LENGTH)
+                .collect(),
+            kart_color: self.color.unwrap_or_default(),
+        }
+    }
+}
+
+pub(crate) fn apply_launch_options(options: Res<LaunchOptions>, mut easy: EasyP2P<KartProtocol>) {
+    if options.name.is_some() || options.color.is_some() {
+        easy.set_local_player_data(options.player_data());
+    }
+    if options.host {
+        easy.create_lobby();
+    } else if let Some(code) = &options.join
+        && !code.trim().is_empty()
+    {
+        info!("room code from launch options: {}", code);
+        easy.join_lobby(code);
+    }
+}
+
+/// How others can join the lobby with that code.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn join_hint(code: &str) -> Option<String> {
+    Some(format!("Join with: --join {}", code))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn join_hint(code: &str) -> Option<String> {
+    let mut link = format!("{}?room={}", current_base_url()?, code);
+    // Whoever follows the link has to meet us on the same servers.
+    for param in ["relay", "signaling"] {
+        if let Some(value) = extract_query_param(param) {
+            link.push_str(&format!("&{}={}", param, value));
+        }
+    }
+    Some(format!("Share link: {}", link))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn get_url() -> Option<String> {
+    web_sys::window()?.location().href().ok()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn current_base_url() -> Option<String> {
+    let source = get_url()?;
+    let no_hash = source.split('#').next().unwrap_or(source.as_str());
+    let base = no_hash.split('?').next().unwrap_or(no_hash);
+    Some(base.trim_end_matches('/').to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn extract_query_param(target: &str) -> Option<String> {
+    let href = get_url()?;
+    let no_hash = href.split('#').next().unwrap_or(href.as_str());
+    let query = no_hash.split('?').nth(1)?;
+    for pair in query.split('&') {
+        let mut it = pair.splitn(2, '=');
+        let key = it.next()?;
+        if key == target {
+            let val = it.next().unwrap_or("");
+            return Some(val.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<LaunchOptions, String> {
+        LaunchOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse(&["--join", "ABCD", "--name", "Kim", "--color", "3"]).unwrap();
+        assert_eq!(options.join.as_deref(), Some("ABCD"));
+        assert_eq!(options.name.as_deref(), Some("Kim"));
+        assert_eq!(options.color, Some(KartColor(3)));
+        assert!(!options.host);
+        assert!(parse(&[]).unwrap().join.is_none());
+    }
+
+    #[test]
+    fn missing_values_are_reported() {
+        assert_eq!(parse(&["--join"]).unwrap_err(), "Missing value for --join");
+        assert_eq!(
+            parse(&["--host", "--name"]).unwrap_err(),
+            "Missing value for --name"
+        );
+    }
+
+    #[test]
+    fn colors_must_be_in_range() {
+        let last = (CAR_COLORS_COUNT - 1).to_string();
+        assert!(parse(&["--color", &last]).is_ok());
+        let past = CAR_COLORS_COUNT.to_string();
+        assert_eq!(
+            parse(&["--color", &past]).unwrap_err(),
+            format!("Invalid color: {}", past)
+        );
+        assert!(parse(&["--color", "red"]).is_err());
+    }
+
+    #[test]
+    fn join_excludes_hosting() {
+        let err = "--join cannot be combined with --host or --dedicated";
+        assert_eq!(parse(&["--join", "ABCD", "--host"]).unwrap_err(), err);
+        assert_eq!(parse(&["--dedicated", "--join", "ABCD"]).unwrap_err(), err);
+    }
+
+    #[test]
+    fn replay_excludes_going_online() {
+        assert!(parse(&["--replay", "capture.jsonl"]).is_ok());
+        assert!(parse(&["--replay", "capture.jsonl", "--host"]).is_err());
+        assert!(parse(&["--join", "ABCD", "--replay", "capture.jsonl"]).is_err());
+    }
+
+    #[cfg(not(feature = "relay"))]
+    #[test]
+    fn relay_options_need_the_relay_feature() {
+        assert_eq!(
+            parse(&["--relay", "ws://localhost:9000"]).unwrap_err(),
+            "--relay needs a build with the relay feature"
+        );
+        assert!(parse(&["--dedicated"]).is_err());
+    }
+
+    #[cfg(feature = "relay")]
+    #[test]
+    fn relay_options_are_accepted_with_the_relay_feature() {
+        let options = parse(&["--dedicated", "--relay", "ws://localhost:9000"]).unwrap();
+        assert!(options.dedicated);
+        assert_eq!(options.relay.as_deref(), Some("ws://localhost:9000"));
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert_eq!(parse(&["--fast"]).unwrap_err(), "Unknown option: --fast");
+        assert!(parse(&["ABCD"]).is_err());
+    }
+}
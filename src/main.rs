@@ -4,25 +4,53 @@ use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
 use bevy::window::PrimaryWindow;
 use bevy_easy_p2p::prelude::*;
-use bevy_easy_p2p::{EasyP2PSystemSet, EasyP2PUpdate, NetworkedId, NetworkedStatesExt};
-use bevy_firestore_p2p::FirestoreP2PPlugin;
-use bevy_firestore_p2p::FirestoreWebRtcTransport;
+use bevy_easy_p2p::{
+    ClientId, EasyP2PSystemSet, EasyP2PUpdate, NetworkRecorder, NetworkedId, NetworkedStatesExt,
+};
 use bevy_text_input::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 use crate::car_controller_2d::{CarController2d, CarController2dWheel, CarControllerDisabled};
+use crate::dedicated::DedicatedHostPlugin;
+use crate::launch::{LaunchOptions, apply_launch_options, join_hint};
 use crate::menu::MenuPlugin;
 use crate::menu::lobby::spawn_lobby;
-use crate::menu::start::spawn_menu;
-use crate::track::{TrackPlugin, spawn_track};
+use crate::menu::start::{spawn_joining, spawn_menu};
+use crate::network_physics::NetworkPhysicsPlugin;
+use crate::track::{TrackPlugin, spawn_track, spawn_track_visuals};
 
 pub mod car_controller_2d;
+pub mod dedicated;
+pub mod launch;
 pub mod menu;
+pub mod network_physics;
 pub mod track;
 use car_controller_2d::CarController2dPlugin;
 
-pub type KartEasyP2P<'w, 's> =
-    EasyP2P<'w, 's, FirestoreWebRtcTransport, AppPlayerData, AppPlayerInputData, AppInstantiations>;
+// WebRTC between browsers, UDP or TCP between native builds, unless everything goes through a
+// relay.
+#[cfg(feature = "relay")]
+type GameTransport = bevy_ws_p2p::WebSocketRelayTransport;
+#[cfg(all(target_arch = "wasm32", not(feature = "relay")))]
+type GameTransport = bevy_firestore_p2p::FirestoreWebRtcTransport;
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "relay"), feature = "tcp"))]
+type GameTransport = bevy_tcp_p2p::TcpTransport;
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(feature = "relay"),
+    not(feature = "tcp")
+))]
+type GameTransport = bevy_udp_p2p::UdpTransport;
+
+#[derive(Clone, Debug)]
+pub struct KartProtocol;
+
+impl P2PProtocol for KartProtocol {
+    type PlayerData = AppPlayerData;
+    type PlayerInputData = AppPlayerInputData;
+    type Instantiations = AppInstantiations;
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
 pub struct AppPlayerData {
@@ -34,10 +62,6 @@ pub struct AppPlayerData {
 pub struct KartColor(u32);
 
 impl KartColor {
-    fn new() -> Self {
-        Self(0)
-    }
-
     fn right(&self) -> KartColor {
         Self((self.0 + 1) % CAR_COLORS_COUNT)
     }
@@ -52,6 +76,10 @@ impl KartColor {
     fn to_u32(&self) -> u32 {
         self.0
     }
+
+    fn clamped(&self) -> KartColor {
+        Self(self.0.min(CAR_COLORS_COUNT - 1))
+    }
 }
 
 #[derive(States, Default, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -103,264 +131,307 @@ impl SpriteLayers {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum WheelRotation {
-    Left,
-    Right,
-    Straight,
-}
-
-#[derive(Message, Clone, Debug, Serialize, Deserialize)]
-struct WheelPositionUpdate(NetworkedId, WheelRotation);
-
-#[derive(Clone, Message)]
-struct AppP2PUpdate(EasyP2PUpdate<AppPlayerData, AppPlayerInputData, AppInstantiations>);
-
 const LAPS_TO_WIN: u32 = 3;
 const CAR_COLORS_COUNT: u32 = 10;
 const CAR_SIZE: UVec2 = UVec2::new(4, 8);
+const MAX_PLAYERS: usize = 8;
+pub const MAX_NAME_LENGTH: usize = 10;
+const MAX_CHAT_LENGTH: usize = 200;
+// Bump whenever networked messages change shape so older clients get turned away.
+const PROTOCOL_VERSION: u32 = 1;
 
 fn main() {
-    App::new()
-        .add_plugins((
-            DefaultPlugins.set(ImagePlugin::default_nearest()),
-            PhysicsPlugins::default(),
-        ))
-        .insert_resource(Gravity::ZERO)
-        .add_plugins((
-            EasyP2PPlugin::<
-                FirestoreWebRtcTransport,
-                AppPlayerData,
-                AppPlayerInputData,
-                AppInstantiations,
-            >::default(),
-            FirestoreP2PPlugin::<AppPlayerData, AppPlayerInputData, AppInstantiations>::default(),
-            TextInputPlugin,
-            CarController2dPlugin,
-            AudioManagerPlugin::default(),
-        ))
-        .add_plugins((MenuPlugin, TrackPlugin))
-        .add_systems(Startup, (auto_join_from_url, setup))
-        .init_state::<AppState>()
-        .init_networked_state::<AppState>()
-        .init_networked_event::<WheelPositionUpdate>()
-        .add_message::<AppP2PUpdate>()
-        .insert_resource(FinishTimes {
-            times: HashMap::new(),
+    let options = LaunchOptions::current();
+    let mut app = App::new();
+    #[cfg(feature = "relay")]
+    if let Some(url) = &options.relay {
+        app.insert_resource(bevy_ws_p2p::RelayConfig::default().with_url(url));
+    }
+    #[cfg(all(target_arch = "wasm32", not(feature = "relay")))]
+    if let Some(url) = &options.signaling {
+        use bevy_firestore_p2p::{SignalingBackend, SignalingConfig, SignalingServerConfig};
+        app.insert_resource(
+            SignalingConfig::default()
+                .with_backend(SignalingBackend::Server(SignalingServerConfig::new(url))),
+        );
+    }
+    if options.dedicated {
+        app.add_plugins(DedicatedHostPlugin);
+        add_gameplay(&mut app, LobbyConfig::default().with_dedicated_host(true));
+        app.run();
+        return;
+    }
+    app.add_plugins((
+        DefaultPlugins.set(ImagePlugin::default_nearest()),
+        NetworkDebugOverlayPlugin::<KartProtocol>::default(),
+        TextInputPlugin,
+        AudioManagerPlugin::default(),
+    ));
+    add_gameplay(&mut app, LobbyConfig::default());
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = &options.replay {
+        match bevy_easy_p2p::NetworkReplay::load(path) {
+            Ok(replay) => {
+                // The capture stands in for the peers, nothing goes out.
+                app.world_mut().remove_non_send_resource::<GameTransport>();
+                app.insert_resource(replay);
+            }
+            Err(err) => {
+                eprintln!("Could not load {}: {}", path.display(), err);
+                std::process::exit(2);
+            }
+        }
+    }
+    app.add_plugins(MenuPlugin)
+        .insert_resource(options)
+        .add_systems(Startup, (apply_launch_options, setup))
+        .insert_resource(NetworkConditions {
+            enabled: false,
+            ..NetworkConditions::symmetric(
+                LinkConditions::new(Duration::from_millis(120), Duration::from_millis(60))
+                    .with_loss(0.02)
+                    .with_duplication(0.01)
+                    .with_reordering(0.02),
+            )
         })
         .insert_resource(AssetHandles {
             karts_texture: Handle::default(),
             wheel_texture: Handle::default(),
         })
-        .add_systems(Update, emit_easy_updates.in_set(EasyP2PSystemSet::Emit))
         .add_systems(Update, on_lobby_created.after(EasyP2PSystemSet::Emit))
-        .add_systems(Update, on_instantiation.after(EasyP2PSystemSet::Core))
         .add_systems(OnEnter(P2PLobbyState::OutOfLobby), spawn_menu)
+        .add_systems(OnEnter(P2PLobbyState::JoiningLobby), spawn_joining)
         .add_systems(OnEnter(P2PLobbyState::InLobby), spawn_lobby)
-        .add_systems(OnEnter(AppState::Game), spawn_track)
+        .add_systems(OnEnter(AppState::Game), spawn_track_visuals)
         .add_systems(OnExit(AppState::Game), spawn_lobby)
-        .add_systems(Update, (send_inputs, follow_transform, cursor_positon_log))
         .add_systems(
             Update,
             (
-                sync_wheel_rotation.after(EasyP2PSystemSet::Emit),
-                receive_wheel_rotation.after(EasyP2PSystemSet::Emit),
+                decorate_karts,
+                follow_transform,
+                cursor_positon_log,
+                toggle_network_simulation,
+                toggle_network_recording,
             ),
         )
+        // Once per fixed step so the host's input buffer receives inputs at a steady rate.
+        .add_systems(FixedUpdate, send_inputs)
         .run();
 }
 
-fn sync_wheel_rotation(
-    mut updates: MessageReader<AppP2PUpdate>,
-    mut w: MessageWriter<WheelPositionUpdate>,
+// Everything the race needs to run, with or without anyone watching.
+fn add_gameplay(app: &mut App, lobby_config: LobbyConfig) {
+    app.add_plugins(PhysicsPlugins::default())
+        .insert_resource(Gravity::ZERO)
+        .add_plugins((
+            EasyP2PPlugin::<GameTransport, KartProtocol>::default(),
+            CarController2dPlugin,
+            NetworkPhysicsPlugin::default(),
+            TrackPlugin,
+        ))
+        .init_state::<AppState>()
+        .init_networked_state::<AppState>()
+        .insert_resource(FinishTimes {
+            times: HashMap::new(),
+        })
+        .insert_resource(
+            lobby_config
+                .with_max_players(MAX_PLAYERS)
+                .with_protocol_version(PROTOCOL_VERSION),
+        )
+        .insert_resource(InputBufferConfig::enabled())
+        .insert_resource(
+            ClientValidation::<KartProtocol>::default()
+                .with_player_data(sanitize_player_data)
+                .with_chat(|_, text| {
+                    *text = text.chars().take(MAX_CHAT_LENGTH).collect();
+                    Validation::Accept
+                }),
+        )
+        .register_prefab(
+            Prefab::<KartProtocol>::new(
+                |instantiation| matches!(instantiation, AppInstantiations::Kart(_)),
+                spawn_kart,
+            )
+            .despawn_on_exit(AppState::Game),
+        )
+        .add_systems(OnEnter(AppState::Game), spawn_track);
+}
+
+fn send_inputs(mut easy: EasyP2P<KartProtocol>, keyboard: Res<ButtonInput<KeyCode>>) {
+    easy.send_inputs(AppPlayerInputData {
+        forward: keyboard.pressed(KeyCode::KeyW),
+        backward: keyboard.pressed(KeyCode::KeyS),
+        left: keyboard.pressed(KeyCode::KeyA),
+        right: keyboard.pressed(KeyCode::KeyD),
+    });
+}
+
+fn toggle_network_simulation(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut conditions: ResMut<NetworkConditions>,
 ) {
-    for AppP2PUpdate(update) in updates.read() {
-        if let EasyP2PUpdate::ClientInput { sender, input } = update {
-            let update = WheelPositionUpdate(
-                sender.clone(),
-                if input.right {
-                    WheelRotation::Right
-                } else if input.left {
-                    WheelRotation::Left
-                } else {
-                    WheelRotation::Straight
-                },
-            );
-            w.write(update);
-        }
+    if keyboard.just_pressed(KeyCode::F9) {
+        conditions.enabled = !conditions.enabled;
+        info!("Network simulation enabled: {}", conditions.enabled);
     }
 }
 
-fn receive_wheel_rotation(
-    easy: KartEasyP2P,
-    mut r: MessageReader<WheelPositionUpdate>,
-    mut wheels: Query<(Entity, &mut Transform, &CarController2dWheel)>,
+const NETWORK_CAPTURE_FILE: &str = "network_capture.jsonl";
+
+fn toggle_network_recording(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut recorder: ResMut<NetworkRecorder>,
 ) {
-    if easy.is_host() {
+    if !keyboard.just_pressed(KeyCode::F10) {
         return;
     }
-    for WheelPositionUpdate(target, rotation) in r.read() {
-        for (entity, mut transform, wheel) in wheels.iter_mut() {
-            if easy.get_closest_networked_id(entity) != Some(target.clone()) {
-                continue;
-            }
-            if wheel.steerable {
-                match rotation {
-                    WheelRotation::Left => {
-                        transform.rotation = Quat::from_rotation_z(45_f32.to_radians());
-                    }
-                    WheelRotation::Right => {
-                        transform.rotation = Quat::from_rotation_z(-45_f32.to_radians());
-                    }
-                    WheelRotation::Straight => {
-                        transform.rotation = Quat::from_rotation_z(0_f32.to_radians());
-                    }
-                }
-            }
-        }
+    if !recorder.is_recording() {
+        recorder.start();
+        return;
+    }
+    recorder.stop();
+    let count = recorder.entries().len();
+    match export_network_capture(&recorder.to_json_lines()) {
+        Ok(()) => info!(
+            "Saved {} recorded payloads to {}",
+            count, NETWORK_CAPTURE_FILE
+        ),
+        Err(err) => warn!("Could not save the network capture: {}", err),
     }
 }
 
-fn get_url() -> Option<String> {
-    web_sys::window()?.location().href().ok()
+#[cfg(not(target_arch = "wasm32"))]
+fn export_network_capture(capture: &str) -> Result<(), String> {
+    std::fs::write(NETWORK_CAPTURE_FILE, capture).map_err(|err| err.to_string())
 }
 
-fn current_base_url() -> Option<String> {
-    let source = get_url()?;
-    let no_hash = source.split('#').next().unwrap_or(source.as_str());
-    let base = no_hash.split('?').next().unwrap_or(no_hash);
-    Some(base.trim_end_matches('/').to_string())
+// Browsers have no file system, so the capture is offered as a download instead.
+#[cfg(target_arch = "wasm32")]
+fn export_network_capture(capture: &str) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+
+    let parts = js_sys::Array::of1(&capture.into());
+    let blob = web_sys::Blob::new_with_str_sequence(&parts).map_err(|err| format!("{:?}", err))?;
+    let url =
+        web_sys::Url::create_object_url_with_blob(&blob).map_err(|err| format!("{:?}", err))?;
+    let anchor = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.create_element("a").ok())
+        .and_then(|element| element.dyn_into::<web_sys::HtmlAnchorElement>().ok())
+        .ok_or("no document to download from")?;
+    anchor.set_href(&url);
+    anchor.set_download(NETWORK_CAPTURE_FILE);
+    anchor.click();
+    let _ = web_sys::Url::revoke_object_url(&url);
+    Ok(())
 }
 
-fn extract_query_param(target: &str) -> Option<String> {
-    let href = get_url()?;
-    let no_hash = href.split('#').next().unwrap_or(href.as_str());
-    let query = no_hash.split('?').nth(1)?;
-    for pair in query.split('&') {
-        let mut it = pair.splitn(2, '=');
-        let key = it.next()?;
-        if key == target {
-            let val = it.next().unwrap_or("");
-            return Some(val.to_string());
+fn on_lobby_created(mut events: MessageReader<EasyP2PUpdate<KartProtocol>>) {
+    for update in events.read() {
+        if let EasyP2PUpdate::LobbyCreated { code } = update {
+            info!("Hosting room: {}", code);
+            if let Some(hint) = join_hint(code) {
+                info!("{}", hint);
+            }
         }
     }
-    None
-}
-
-fn send_inputs(mut easy: KartEasyP2P, keyboard: Res<ButtonInput<KeyCode>>) {
-    easy.send_inputs(AppPlayerInputData {
-        forward: keyboard.pressed(KeyCode::KeyW),
-        backward: keyboard.pressed(KeyCode::KeyS),
-        left: keyboard.pressed(KeyCode::KeyA),
-        right: keyboard.pressed(KeyCode::KeyD),
-    });
 }
 
-fn auto_join_from_url(mut easy: KartEasyP2P) {
-    if let Some(room) = extract_query_param("room") {
-        info!("room code in url: {}", room);
-        if !room.trim().is_empty() {
-            easy.join_lobby(&room);
-        }
+fn sanitize_player_data(_: ClientId, data: &mut AppPlayerData) -> Validation {
+    data.name = data.name.trim().chars().take(MAX_NAME_LENGTH).collect();
+    if data.name.is_empty() {
+        return Validation::Reject;
     }
+    data.kart_color = data.kart_color.clamped();
+    Validation::Accept
 }
 
-fn on_lobby_created(mut events: MessageReader<AppP2PUpdate>) {
-    for AppP2PUpdate(update) in events.read() {
-        if let EasyP2PUpdate::LobbyCreated { code } = update {
-            info!("Hosting room: {}", code);
-            if let Some(base) = current_base_url() {
-                info!("Share link: {}?room={}", base, code);
-            }
-        }
-    }
+fn spawn_kart(In(spawn): In<PrefabSpawn<KartProtocol>>, mut commands: Commands) {
+    let half_car_width = 2.5;
+    let half_car_length = 3.;
+    commands.entity(spawn.entity).insert((
+        Mass(1.),
+        RigidBody::Dynamic,
+        Collider::rectangle(4., 8.),
+        NetworkedTransform,
+        ReplicatedVelocity::default(),
+        CarController2d::new(1.),
+        CarControllerDisabled,
+        LapsCounter(0),
+        children![
+            (
+                Transform::from_xyz(
+                    half_car_width,
+                    half_car_length - 1.,
+                    SpriteLayers::Wheels.to_z()
+                ),
+                CarController2dWheel::new(true, true),
+                NetworkedTransform,
+            ),
+            (
+                Transform::from_xyz(
+                    -half_car_width,
+                    half_car_length - 1.,
+                    SpriteLayers::Wheels.to_z()
+                ),
+                CarController2dWheel::new(true, true),
+                NetworkedTransform,
+            ),
+            (
+                Transform::from_xyz(
+                    half_car_width,
+                    -half_car_length,
+                    SpriteLayers::Wheels.to_z()
+                ),
+                CarController2dWheel::new(false, false),
+            ),
+            (
+                Transform::from_xyz(
+                    -half_car_width,
+                    -half_car_length,
+                    SpriteLayers::Wheels.to_z()
+                ),
+                CarController2dWheel::new(false, false),
+            ),
+        ],
+    ));
 }
 
-fn on_instantiation(
+// Sprites and name labels for karts, left out on a dedicated host.
+fn decorate_karts(
     mut commands: Commands,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
-    mut easy: KartEasyP2P,
+    easy: EasyP2P<KartProtocol>,
     asset_handles: Res<AssetHandles>,
+    karts: Query<(Entity, &NetworkedEntity), Added<CarController2d>>,
+    wheels: Query<Entity, Added<CarController2dWheel>>,
 ) {
-    for data in easy.get_instantiations() {
-        match &data.instantiation {
-            AppInstantiations::Kart(id) => {
-                let player = easy.get_player_data(id.clone());
-                let layout =
-                    TextureAtlasLayout::from_grid(CAR_SIZE, CAR_COLORS_COUNT, 1, None, None);
-                let texture_atlas_layout = texture_atlas_layouts.add(layout);
-                let half_car_width = 2.5;
-                let half_car_length = 3.;
-                let id = commands
-                    .spawn((
-                        DespawnOnExit(AppState::Game),
-                        Mass(1.),
-                        RigidBody::Dynamic,
-                        Collider::rectangle(4., 8.),
-                        Sprite::from_atlas_image(
-                            asset_handles.karts_texture.clone(),
-                            TextureAtlas {
-                                layout: texture_atlas_layout,
-                                index: player.kart_color.to_u32() as usize,
-                            },
-                        ),
-                        data.transform,
-                        NetworkedTransform,
-                        NetworkedEntity::new(id.clone()),
-                        CarController2d::new(1.),
-                        CarControllerDisabled,
-                        LapsCounter(0),
-                        children![
-                            (
-                                Transform::from_xyz(
-                                    half_car_width,
-                                    half_car_length - 1.,
-                                    SpriteLayers::Wheels.to_z()
-                                ),
-                                CarController2dWheel::new(true, true),
-                                Sprite::from_image(asset_handles.wheel_texture.clone()),
-                            ),
-                            (
-                                Transform::from_xyz(
-                                    -half_car_width,
-                                    half_car_length - 1.,
-                                    SpriteLayers::Wheels.to_z()
-                                ),
-                                CarController2dWheel::new(true, true),
-                                Sprite::from_image(asset_handles.wheel_texture.clone()),
-                            ),
-                            (
-                                Transform::from_xyz(
-                                    half_car_width,
-                                    -half_car_length,
-                                    SpriteLayers::Wheels.to_z()
-                                ),
-                                CarController2dWheel::new(false, false),
-                                Sprite::from_image(asset_handles.wheel_texture.clone()),
-                            ),
-                            (
-                                Transform::from_xyz(
-                                    -half_car_width,
-                                    -half_car_length,
-                                    SpriteLayers::Wheels.to_z()
-                                ),
-                                CarController2dWheel::new(false, false),
-                                Sprite::from_image(asset_handles.wheel_texture.clone()),
-                            ),
-                        ],
-                    ))
-                    .id();
-                commands.spawn((
-                    DespawnOnExit(AppState::Game),
-                    FollowTransform(id),
-                    children![(
-                        Text2d::new(player.name),
-                        Transform::from_xyz(0., 5., SpriteLayers::AboveCar.to_z())
-                            .with_scale(Vec3::splat(0.1)),
-                    )],
-                ));
-            }
-        }
+    for (entity, networked) in karts.iter() {
+        let player = easy.get_player_data(networked.id());
+        let layout = TextureAtlasLayout::from_grid(CAR_SIZE, CAR_COLORS_COUNT, 1, None, None);
+        let texture_atlas_layout = texture_atlas_layouts.add(layout);
+        commands.entity(entity).insert(Sprite::from_atlas_image(
+            asset_handles.karts_texture.clone(),
+            TextureAtlas {
+                layout: texture_atlas_layout,
+                index: player.kart_color.to_u32() as usize,
+            },
+        ));
+        commands.spawn((
+            DespawnOnExit(AppState::Game),
+            FollowTransform(entity),
+            children![(
+                Text2d::new(player.name),
+                Transform::from_xyz(0., 5., SpriteLayers::AboveCar.to_z())
+                    .with_scale(Vec3::splat(0.1)),
+            )],
+        ));
+    }
+    for entity in wheels.iter() {
+        commands
+            .entity(entity)
+            .insert(Sprite::from_image(asset_handles.wheel_texture.clone()));
     }
 }
 
@@ -456,9 +527,3 @@ impl FinishTimes {
         Some(rank)
     }
 }
-
-fn emit_easy_updates(mut easy: KartEasyP2P, mut writer: MessageWriter<AppP2PUpdate>) {
-    for update in easy.read_updates() {
-        writer.write(AppP2PUpdate(update));
-    }
-}
@@ -0,0 +1,103 @@
+use crate::{AppState, KartProtocol};
+use bevy::prelude::*;
+use bevy_easy_p2p::prelude::*;
+use bevy_udp_p2p::{LanLobbies, LanLobby, UdpConfig};
+
+pub struct LanLobbyListPlugin;
+
+impl Plugin for LanLobbyListPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(P2PLobbyState::OutOfLobby), spawn_lan_lobby_list)
+            .add_systems(Update, (name_lan_lobby, refresh_lan_lobby_list));
+    }
+}
+
+#[derive(Component)]
+struct LanLobbyList;
+
+// What others on the network see our lobby as.
+fn name_lan_lobby(easy: EasyP2P<KartProtocol>, mut config: ResMut<UdpConfig>) {
+    let name = format!("{}'s lobby", easy.get_local_player_data().name);
+    if config.lobby_name != name {
+        config.lobby_name = name;
+    }
+}
+
+fn spawn_lan_lobby_list(mut commands: Commands) {
+    commands.spawn((
+        DespawnOnExit(P2PLobbyState::OutOfLobby),
+        DespawnOnExit(AppState::OutOfGame),
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(60),
+            left: px(15),
+            flex_direction: FlexDirection::Column,
+            row_gap: px(5),
+            ..default()
+        },
+        children![
+            Text::new("LAN Lobbies:"),
+            (
+                LanLobbyList,
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    row_gap: px(5),
+                    ..default()
+                },
+            )
+        ],
+    ));
+}
+
+fn refresh_lan_lobby_list(
+    mut commands: Commands,
+    lobbies: Res<LanLobbies>,
+    lists: Query<(Entity, Option<&Children>), With<LanLobbyList>>,
+) {
+    for (list, children) in lists.iter() {
+        if !lobbies.is_changed() && children.is_some() {
+            continue;
+        }
+        commands.entity(list).despawn_children();
+        if lobbies.0.is_empty() {
+            commands.entity(list).with_child((
+                Text::new("None found"),
+                TextColor(Color::srgb(0.6, 0.6, 0.6)),
+            ));
+        }
+        for lobby in lobbies.0.iter() {
+            let button = lobby_button(&mut commands, lobby);
+            commands.entity(list).add_child(button);
+        }
+    }
+}
+
+fn lobby_button(commands: &mut Commands, lobby: &LanLobby) -> Entity {
+    let players = match lobby.max_players {
+        Some(max_players) => format!("{}/{}", lobby.players, max_players),
+        None => lobby.players.to_string(),
+    };
+    let code = lobby.code.clone();
+    commands
+        .spawn((
+            Button,
+            Node {
+                padding: UiRect::axes(px(10), px(5)),
+                border: UiRect::all(px(2)),
+                ..default()
+            },
+            BorderColor::all(Color::WHITE),
+            BorderRadius::all(px(5)),
+            BackgroundColor(Color::BLACK),
+            children![(
+                Text::new(format!("{} ({})", lobby.name, players)),
+                TextColor(Color::srgb(0.9, 0.9, 0.9)),
+            )],
+        ))
+        .observe(
+            move |_trigger: On<Pointer<Press>>, mut easy: EasyP2P<KartProtocol>| {
+                easy.join_lobby(&code);
+            },
+        )
+        .id()
+}
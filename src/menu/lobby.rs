@@ -1,6 +1,6 @@
 use crate::{
-    AppP2PUpdate, AppPlayerData, AppState, AssetHandles, CAR_COLORS_COUNT, CAR_SIZE, FinishTimes,
-    KartColor, KartEasyP2P,
+    AppPlayerData, AppState, AssetHandles, CAR_COLORS_COUNT, CAR_SIZE, FinishTimes, KartColor,
+    KartProtocol,
 };
 use bevy::prelude::*;
 use bevy_easy_p2p::prelude::*;
@@ -70,7 +70,7 @@ impl KartPreview {
 
 impl Default for KartPreview {
     fn default() -> Self {
-        Self::new(KartColor::new())
+        Self::new(KartColor::default())
     }
 }
 
@@ -96,11 +96,11 @@ fn lobby_chat_input_history(
 }
 
 fn on_client_message_received(
-    mut events: MessageReader<AppP2PUpdate>,
+    mut events: MessageReader<EasyP2PUpdate<KartProtocol>>,
     mut history: ResMut<LobbyChatInputHistory>,
-    easy: KartEasyP2P,
+    easy: EasyP2P<KartProtocol>,
 ) {
-    for AppP2PUpdate(update) in events.read() {
+    for update in events.read() {
         if let EasyP2PUpdate::ClientChat { client_id, text } = update {
             history.add(format!(
                 "{}: {}",
@@ -112,17 +112,19 @@ fn on_client_message_received(
 }
 
 fn on_host_message_received(
-    mut events: MessageReader<AppP2PUpdate>,
+    mut events: MessageReader<EasyP2PUpdate<KartProtocol>>,
     mut history: ResMut<LobbyChatInputHistory>,
-    easy: KartEasyP2P,
+    easy: EasyP2P<KartProtocol>,
 ) {
-    for AppP2PUpdate(update) in events.read() {
+    for update in events.read() {
         if let EasyP2PUpdate::HostChat { text } = update {
-            history.add(format!(
-                "{}: {}",
-                easy.get_player_data(NetworkedId::Host).name,
-                text
-            ));
+            // A dedicated host is not among the players.
+            let name = easy
+                .get_players()
+                .into_iter()
+                .find(|player| player.id == NetworkedId::Host)
+                .map_or_else(|| "Host".to_string(), |player| player.data.name);
+            history.add(format!("{}: {}", name, text));
         }
     }
 }
@@ -131,11 +133,11 @@ fn on_host_message_received(
 struct KickTarget(NetworkedId);
 
 fn on_lobby_exit(
-    mut events: MessageReader<AppP2PUpdate>,
+    mut events: MessageReader<EasyP2PUpdate<KartProtocol>>,
     mut inputs: Query<&mut Text, With<TextInput>>,
     mut history: ResMut<LobbyChatInputHistory>,
 ) {
-    for AppP2PUpdate(update) in events.read() {
+    for update in events.read() {
         if let EasyP2PUpdate::LobbyExited { reason } = update {
             info!("Lobby exit: {:?}", reason);
             for mut input in inputs.iter_mut() {
@@ -194,7 +196,7 @@ fn players_buttons(
             ))
             .observe(
                 |trigger: On<Pointer<Press>>,
-                 mut easy: KartEasyP2P,
+                 mut easy: EasyP2P<KartProtocol>,
                  kick_targets: Query<&KickTarget>| {
                     let target = kick_targets.get(trigger.entity).unwrap();
                     if let NetworkedId::ClientId(cid) = target.0 {
@@ -244,21 +246,20 @@ fn players_buttons(
 }
 
 fn spawn_lobby_players_buttons(
-    mut set: ParamSet<(KartEasyP2P, MessageReader<AppP2PUpdate>)>,
+    easy: EasyP2P<KartProtocol>,
+    mut updates: MessageReader<EasyP2PUpdate<KartProtocol>>,
     mut commands: Commands,
     buttons: Query<(Entity, Option<&Children>), With<LobbyPlayersButtons>>,
     finish_times: Res<FinishTimes>,
 ) {
-    let has_update = set
-        .p1()
+    let has_update = updates
         .read()
-        .any(|AppP2PUpdate(update)| matches!(update, EasyP2PUpdate::RosterUpdated { .. }));
+        .any(|update| matches!(update, EasyP2PUpdate::RosterUpdated { .. }));
     let already_spawned = buttons.iter().all(|(_, children)| children.is_some());
     if !has_update && already_spawned {
         return;
     }
 
-    let easy = set.p0();
     let players = easy.get_players();
     let is_host = easy.is_host();
 
@@ -271,7 +272,7 @@ fn spawn_lobby_players_buttons(
     }
 }
 
-pub fn spawn_lobby(mut commands: Commands, easy: KartEasyP2P) {
+pub fn spawn_lobby(mut commands: Commands, easy: EasyP2P<KartProtocol>) {
     let is_host = easy.is_host();
     let lobby = commands
         .spawn((
@@ -311,7 +312,7 @@ pub fn spawn_lobby(mut commands: Commands, easy: KartEasyP2P) {
                 TextShadow::default(),
             )],
         ))
-        .observe(|_: On<Pointer<Press>>, mut easy: KartEasyP2P| {
+        .observe(|_: On<Pointer<Press>>, mut easy: EasyP2P<KartProtocol>| {
             easy.exit_lobby();
         })
         .id();
@@ -354,7 +355,7 @@ pub fn spawn_lobby(mut commands: Commands, easy: KartEasyP2P) {
         ))
         .observe(
             |trigger: On<InputFieldSubmit>,
-             mut easy: KartEasyP2P,
+             mut easy: EasyP2P<KartProtocol>,
              mut history: ResMut<LobbyChatInputHistory>| {
                 if easy.is_host() {
                     easy.send_message_all(trigger.text().to_string());
@@ -402,7 +403,7 @@ pub fn spawn_lobby(mut commands: Commands, easy: KartEasyP2P) {
         .id();
     let left_kart_button = commands
         .spawn((Button, Text::new("<")))
-        .observe(|_: On<Pointer<Press>>, mut easy: KartEasyP2P| {
+        .observe(|_: On<Pointer<Press>>, mut easy: EasyP2P<KartProtocol>| {
             let current_kart = easy.get_local_player_data().kart_color;
             easy.set_local_player_data(AppPlayerData {
                 kart_color: current_kart.left(),
@@ -412,7 +413,7 @@ pub fn spawn_lobby(mut commands: Commands, easy: KartEasyP2P) {
         .id();
     let right_kart_button = commands
         .spawn((Button, Text::new(">")))
-        .observe(|_: On<Pointer<Press>>, mut easy: KartEasyP2P| {
+        .observe(|_: On<Pointer<Press>>, mut easy: EasyP2P<KartProtocol>| {
             let current_kart = easy.get_local_player_data().kart_color;
             easy.set_local_player_data(AppPlayerData {
                 kart_color: current_kart.right(),
@@ -523,7 +524,7 @@ fn handle_kart_preview(mut image_nodes: Query<(&mut ImageNode, &KartPreview)>) {
 }
 
 fn handle_local_kart_preview(
-    easy: KartEasyP2P,
+    easy: EasyP2P<KartProtocol>,
     mut image_nodes: Query<&mut KartPreview, With<LocalKartPreview>>,
 ) {
     let current_kart = easy.get_local_player_data().kart_color;
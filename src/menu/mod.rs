@@ -1,12 +1,30 @@
 use bevy::prelude::*;
+use bevy_easy_p2p::EasyP2PSystemSet;
 
 pub struct MenuPlugin;
 
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(feature = "relay"),
+    not(feature = "tcp")
+))]
+pub mod lan;
 pub mod lobby;
 pub mod start;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(lobby::LobbyPlugin);
+        #[cfg(all(
+            not(target_arch = "wasm32"),
+            not(feature = "relay"),
+            not(feature = "tcp")
+        ))]
+        app.add_plugins(lan::LanLobbyListPlugin);
+        app.add_plugins(lobby::LobbyPlugin)
+            .init_resource::<start::LobbyFailureMessage>()
+            .add_systems(
+                Update,
+                start::record_lobby_failure.after(EasyP2PSystemSet::Emit),
+            );
     }
 }
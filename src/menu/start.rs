@@ -1,14 +1,62 @@
-use crate::{AppPlayerData, AppState, KartColor, KartEasyP2P};
+use crate::launch::LaunchOptions;
+use crate::{AppPlayerData, AppState, KartProtocol, MAX_NAME_LENGTH};
 use bevy::prelude::*;
 use bevy_easy_p2p::prelude::*;
 use bevy_text_input::prelude::*;
 
-pub fn spawn_menu(mut commands: Commands, mut easy: KartEasyP2P) {
+// Why the last create or join attempt failed, shown on the start menu until a lobby is entered.
+#[derive(Resource, Default)]
+pub struct LobbyFailureMessage(pub Option<String>);
+
+pub fn record_lobby_failure(
+    mut events: MessageReader<EasyP2PUpdate<KartProtocol>>,
+    mut failure: ResMut<LobbyFailureMessage>,
+) {
+    for update in events.read() {
+        match update {
+            EasyP2PUpdate::JoinFailed { reason } => {
+                failure.0 = Some(format!("Could not join lobby: {}", reason));
+            }
+            EasyP2PUpdate::CreateFailed { reason } => {
+                failure.0 = Some(format!("Could not create lobby: {}", reason));
+            }
+            EasyP2PUpdate::LobbyEntered { .. } => {
+                failure.0 = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn spawn_joining(mut commands: Commands) {
+    commands.spawn((
+        DespawnOnExit(P2PLobbyState::JoiningLobby),
+        Node {
+            width: percent(100),
+            height: percent(100),
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        children![(
+            Text::new("Joining lobby..."),
+            TextFont {
+                font_size: 33.0,
+                ..default()
+            },
+            TextColor(Color::srgb(0.9, 0.9, 0.9)),
+        )],
+    ));
+}
+
+pub fn spawn_menu(
+    mut commands: Commands,
+    mut easy: EasyP2P<KartProtocol>,
+    failure: Res<LobbyFailureMessage>,
+    options: Res<LaunchOptions>,
+) {
     if easy.get_local_player_data().name.is_empty() {
-        easy.set_local_player_data(AppPlayerData {
-            name: "YOUR_NAME".to_string(),
-            kart_color: KartColor::new(),
-        });
+        easy.set_local_player_data(options.player_data());
     }
     let menu = commands
         .spawn((
@@ -48,9 +96,11 @@ pub fn spawn_menu(mut commands: Commands, mut easy: KartEasyP2P) {
                 TextShadow::default(),
             )],
         ))
-        .observe(|_trigger: On<Pointer<Press>>, mut easy: KartEasyP2P| {
-            easy.create_lobby();
-        })
+        .observe(
+            |_trigger: On<Pointer<Press>>, mut easy: EasyP2P<KartProtocol>| {
+                easy.create_lobby();
+            },
+        )
         .id();
     let code_input = commands
         .spawn((
@@ -61,9 +111,11 @@ pub fn spawn_menu(mut commands: Commands, mut easy: KartEasyP2P) {
                 ..default()
             },
         ))
-        .observe(|trigger: On<InputFieldSubmit>, mut easy: KartEasyP2P| {
-            easy.join_lobby(&trigger.text().to_string());
-        })
+        .observe(
+            |trigger: On<InputFieldSubmit>, mut easy: EasyP2P<KartProtocol>| {
+                easy.join_lobby(&trigger.text().to_string());
+            },
+        )
         .id();
     let mut code_parent = commands.spawn((
         Node {
@@ -79,19 +131,21 @@ pub fn spawn_menu(mut commands: Commands, mut easy: KartEasyP2P) {
     let code_parent_id = code_parent.id();
     let name_input = commands
         .spawn((
-            TextInput::new(false, false, false).with_max_characters(10),
+            TextInput::new(false, false, false).with_max_characters(MAX_NAME_LENGTH),
             Text::new(easy.get_local_player_data().name.clone()),
             Node {
                 height: px(25),
                 ..default()
             },
         ))
-        .observe(|trigger: On<InputFieldChange>, mut easy: KartEasyP2P| {
-            easy.set_local_player_data(AppPlayerData {
-                name: trigger.text().to_string(),
-                kart_color: KartColor::new(),
-            });
-        })
+        .observe(
+            |trigger: On<InputFieldChange>, mut easy: EasyP2P<KartProtocol>| {
+                easy.set_local_player_data(AppPlayerData {
+                    name: trigger.text().to_string(),
+                    kart_color: easy.get_local_player_data().kart_color,
+                });
+            },
+        )
         .id();
     let mut name_parent = commands.spawn((
         Node {
@@ -108,4 +162,20 @@ pub fn spawn_menu(mut commands: Commands, mut easy: KartEasyP2P) {
     commands
         .entity(menu)
         .add_children(&[button, code_parent_id, name_parent_id]);
+    if let Some(message) = &failure.0 {
+        let failure_text = commands
+            .spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    bottom: px(15),
+                    ..default()
+                },
+                children![(
+                    Text::new(message.clone()),
+                    TextColor(Color::srgb(1.0, 0.4, 0.4)),
+                )],
+            ))
+            .id();
+        commands.entity(menu).add_child(failure_text);
+    }
 }
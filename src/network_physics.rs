@@ -0,0 +1,76 @@
+use avian2d::prelude::*;
+use bevy::prelude::*;
+use bevy_easy_p2p::EasyP2P;
+use bevy_easy_p2p::networked_transform::{
+    NetworkedTransformSystemSet, RemoteTransform, ReplicatedVelocity,
+};
+
+use crate::KartProtocol;
+
+// Bridges avian2d bodies and the transform replication: the host sends its simulated velocities,
+// clients stop simulating the bodies they receive and only carry the replicated velocities.
+pub struct NetworkPhysicsPlugin {
+    pub kinematic_remote_bodies: bool,
+}
+
+impl Default for NetworkPhysicsPlugin {
+    fn default() -> Self {
+        Self {
+            kinematic_remote_bodies: true,
+        }
+    }
+}
+
+impl Plugin for NetworkPhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                send_body_velocities.before(NetworkedTransformSystemSet),
+                receive_body_velocities.after(NetworkedTransformSystemSet),
+            ),
+        );
+        if self.kinematic_remote_bodies {
+            app.add_systems(Update, make_remote_bodies_kinematic);
+        }
+    }
+}
+
+fn send_body_velocities(
+    easy: EasyP2P<KartProtocol>,
+    mut bodies: Query<(&LinearVelocity, &AngularVelocity, &mut ReplicatedVelocity)>,
+) {
+    if !easy.is_host() {
+        return;
+    }
+    for (linear, angular, mut replicated) in bodies.iter_mut() {
+        replicated.linear = linear.0.extend(0.);
+        replicated.angular = angular.0;
+    }
+}
+
+fn receive_body_velocities(
+    mut bodies: Query<
+        (
+            &ReplicatedVelocity,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+        ),
+        With<RemoteTransform>,
+    >,
+) {
+    for (replicated, mut linear, mut angular) in bodies.iter_mut() {
+        linear.0 = replicated.linear.truncate();
+        angular.0 = replicated.angular;
+    }
+}
+
+// RigidBody is immutable, so the body type is changed by inserting it again.
+fn make_remote_bodies_kinematic(
+    mut commands: Commands,
+    bodies: Query<Entity, (With<RigidBody>, Added<RemoteTransform>)>,
+) {
+    for entity in bodies.iter() {
+        commands.entity(entity).insert(RigidBody::Kinematic);
+    }
+}
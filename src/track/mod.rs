@@ -5,7 +5,7 @@ use bevy_easy_p2p::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    AppInstantiations, AppState, FinishTimes, KartEasyP2P, LAPS_TO_WIN, LapsCounter, SpriteLayers,
+    AppInstantiations, AppState, FinishTimes, KartProtocol, LAPS_TO_WIN, LapsCounter, SpriteLayers,
     car_controller_2d::CarControllerDisabled,
 };
 
@@ -44,30 +44,130 @@ struct StartLight;
 #[derive(Resource)]
 struct RaceStarted(f32);
 
-fn spawn_barriers(
+const OUTER_RING: [Vec2; 41] = [
+    Vec2::new(-97., -61.5),
+    Vec2::new(33., -57.2),
+    Vec2::new(48.2, -47.4),
+    Vec2::new(55.7, -38.),
+    Vec2::new(61.6, -26.),
+    Vec2::new(66., -25.6),
+    Vec2::new(76.6, -45.8),
+    Vec2::new(86., -54.),
+    Vec2::new(99.8, -54.2),
+    Vec2::new(106.5, -51.4),
+    Vec2::new(114.6, -43.4),
+    Vec2::new(119.2, -27.),
+    Vec2::new(119.6, 4.2),
+    Vec2::new(115.4, 48.),
+    Vec2::new(110.6, 57.4),
+    Vec2::new(100.2, 63.2),
+    Vec2::new(87.8, 63.6),
+    Vec2::new(69.3, 53.),
+    Vec2::new(53.4, 41.6),
+    Vec2::new(14., 0.),
+    Vec2::new(7., -1.4),
+    Vec2::new(0.1, -5.5),
+    Vec2::new(-54.2, -10.6),
+    Vec2::new(-63., -6.5),
+    Vec2::new(-59.6, -0.2),
+    Vec2::new(-35.1, 0.2),
+    Vec2::new(-9.6, 2.4),
+    Vec2::new(9.8, 11.),
+    Vec2::new(23.2, 22.6),
+    Vec2::new(27., 31.2),
+    Vec2::new(27., 39.),
+    Vec2::new(13.8, 54.6),
+    Vec2::new(-10., 60.),
+    Vec2::new(-47.2, 58.2),
+    Vec2::new(-90., 57.8),
+    Vec2::new(-106.6, 50.),
+    Vec2::new(-119.6, 37.2),
+    Vec2::new(-124., 26.2),
+    Vec2::new(-123.8, -34.8),
+    Vec2::new(-120.6, -45.6),
+    Vec2::new(-109., -57.8),
+];
+
+const INNER_RING: [Vec2; 33] = [
+    Vec2::new(-92., -37.8),
+    Vec2::new(-50.6, -35.2),
+    Vec2::new(15.8, -33.2),
+    Vec2::new(31.8, -32.),
+    Vec2::new(41.8, -13.8),
+    Vec2::new(54.4, -1.4),
+    Vec2::new(72.4, -1.6),
+    Vec2::new(85.4, -11.8),
+    Vec2::new(94.5, -29.),
+    Vec2::new(95.4, -1.4),
+    Vec2::new(92.4, 20.6),
+    Vec2::new(93.6, 36.4),
+    Vec2::new(89.4, 40.),
+    Vec2::new(83., 33.8),
+    Vec2::new(62.6, 17.8),
+    Vec2::new(26.2, -21.),
+    Vec2::new(5.6, -29.8),
+    Vec2::new(-20.2, -31.2),
+    Vec2::new(-77.6, -30.8),
+    Vec2::new(-84.7, -25.8),
+    Vec2::new(-90.2, -18.8),
+    Vec2::new(-90.4, 5.),
+    Vec2::new(-84.6, 14.8),
+    Vec2::new(-69.8, 23.6),
+    Vec2::new(-26.1, 25.2),
+    Vec2::new(-13., 28.),
+    Vec2::new(-0.8, 31.4),
+    Vec2::new(-0.2, 34.8),
+    Vec2::new(-27.4, 35.6),
+    Vec2::new(-87.2, 33.),
+    Vec2::new(-98.6, 25.8),
+    Vec2::new(-100.6, -22.2),
+    Vec2::new(-98.4, -35.2),
+];
+
+// Position and length of each barrier around a closed ring of points.
+fn barrier_segments(points: &[Vec2]) -> impl Iterator<Item = (Transform, f32)> + '_ {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| {
+            let middle = (*a + *b) / 2.;
+            let length = (*a - *b).length();
+            let angle = (*a - *b).y.atan2((*a - *b).x);
+            let transform = Transform::from_translation(middle.extend(SpriteLayers::Car.to_z()))
+                .with_rotation(Quat::from_rotation_z(angle));
+            (transform, length)
+        })
+}
+
+fn spawn_barriers(commands: &mut Commands, points: &[Vec2]) {
+    for (transform, length) in barrier_segments(points) {
+        commands.spawn((
+            DespawnOnExit(AppState::Game),
+            RigidBody::Static,
+            Collider::rectangle(length, 2.),
+            transform,
+        ));
+    }
+}
+
+fn spawn_barrier_meshes(
     commands: &mut Commands,
     meshes: &mut Assets<Mesh>,
     red_material: Handle<ColorMaterial>,
     white_material: Handle<ColorMaterial>,
-    points: Vec<Vec2>,
+    points: &[Vec2],
 ) {
-    for (i, (a, b)) in points.iter().zip(points.iter().cycle().skip(1)).enumerate() {
+    for (i, (transform, length)) in barrier_segments(points).enumerate() {
         let material = if i % 2 == 0 {
             red_material.clone()
         } else {
             white_material.clone()
         };
-        let middle = (*a + *b) / 2.;
-        let length = (*a - *b).length();
-        let angle = (*a - *b).y.atan2((*a - *b).x);
         commands.spawn((
             DespawnOnExit(AppState::Game),
-            RigidBody::Static,
             Mesh2d(meshes.add(Rectangle::new(length, 2.))),
-            MeshMaterial2d(material.clone()),
-            Collider::rectangle(length, 2.),
-            Transform::from_translation(middle.extend(SpriteLayers::Car.to_z()))
-                .with_rotation(Quat::from_rotation_z(angle)),
+            MeshMaterial2d(material),
+            transform,
         ));
     }
 }
@@ -76,128 +176,11 @@ pub(crate) fn spawn_track(
     mut finish_times: ResMut<FinishTimes>,
     time: Res<Time>,
     mut commands: Commands,
-    mut audio_manager: AudioManager,
-    asset_server: Res<AssetServer>,
-    mut easy: KartEasyP2P,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+    mut easy: EasyP2P<KartProtocol>,
 ) {
     finish_times.times.clear();
-    commands.spawn((
-        DespawnOnExit(AppState::Game),
-        Sprite::from_image(asset_server.load("sprites/track.png")),
-    ));
-    let red_material = materials.add(ColorMaterial::from(Color::srgb(0.68, 0.13, 0.20)));
-    let white_material = materials.add(ColorMaterial::from(Color::srgb(1., 1., 1.)));
-    let outer_ring = vec![
-        Vec2::new(-97., -61.5),
-        Vec2::new(33., -57.2),
-        Vec2::new(48.2, -47.4),
-        Vec2::new(55.7, -38.),
-        Vec2::new(61.6, -26.),
-        Vec2::new(66., -25.6),
-        Vec2::new(76.6, -45.8),
-        Vec2::new(86., -54.),
-        Vec2::new(99.8, -54.2),
-        Vec2::new(106.5, -51.4),
-        Vec2::new(114.6, -43.4),
-        Vec2::new(119.2, -27.),
-        Vec2::new(119.6, 4.2),
-        Vec2::new(115.4, 48.),
-        Vec2::new(110.6, 57.4),
-        Vec2::new(100.2, 63.2),
-        Vec2::new(87.8, 63.6),
-        Vec2::new(69.3, 53.),
-        Vec2::new(53.4, 41.6),
-        Vec2::new(14., 0.),
-        Vec2::new(7., -1.4),
-        Vec2::new(0.1, -5.5),
-        Vec2::new(-54.2, -10.6),
-        Vec2::new(-63., -6.5),
-        Vec2::new(-59.6, -0.2),
-        Vec2::new(-35.1, 0.2),
-        Vec2::new(-9.6, 2.4),
-        Vec2::new(9.8, 11.),
-        Vec2::new(23.2, 22.6),
-        Vec2::new(27., 31.2),
-        Vec2::new(27., 39.),
-        Vec2::new(13.8, 54.6),
-        Vec2::new(-10., 60.),
-        Vec2::new(-47.2, 58.2),
-        Vec2::new(-90., 57.8),
-        Vec2::new(-106.6, 50.),
-        Vec2::new(-119.6, 37.2),
-        Vec2::new(-124., 26.2),
-        Vec2::new(-123.8, -34.8),
-        Vec2::new(-120.6, -45.6),
-        Vec2::new(-109., -57.8),
-    ];
-    let inner_ring = vec![
-        Vec2::new(-92., -37.8),
-        Vec2::new(-50.6, -35.2),
-        Vec2::new(15.8, -33.2),
-        Vec2::new(31.8, -32.),
-        Vec2::new(41.8, -13.8),
-        Vec2::new(54.4, -1.4),
-        Vec2::new(72.4, -1.6),
-        Vec2::new(85.4, -11.8),
-        Vec2::new(94.5, -29.),
-        Vec2::new(95.4, -1.4),
-        Vec2::new(92.4, 20.6),
-        Vec2::new(93.6, 36.4),
-        Vec2::new(89.4, 40.),
-        Vec2::new(83., 33.8),
-        Vec2::new(62.6, 17.8),
-        Vec2::new(26.2, -21.),
-        Vec2::new(5.6, -29.8),
-        Vec2::new(-20.2, -31.2),
-        Vec2::new(-77.6, -30.8),
-        Vec2::new(-84.7, -25.8),
-        Vec2::new(-90.2, -18.8),
-        Vec2::new(-90.4, 5.),
-        Vec2::new(-84.6, 14.8),
-        Vec2::new(-69.8, 23.6),
-        Vec2::new(-26.1, 25.2),
-        Vec2::new(-13., 28.),
-        Vec2::new(-0.8, 31.4),
-        Vec2::new(-0.2, 34.8),
-        Vec2::new(-27.4, 35.6),
-        Vec2::new(-87.2, 33.),
-        Vec2::new(-98.6, 25.8),
-        Vec2::new(-100.6, -22.2),
-        Vec2::new(-98.4, -35.2),
-    ];
-    spawn_barriers(
-        &mut commands,
-        &mut meshes,
-        red_material.clone(),
-        white_material.clone(),
-        outer_ring,
-    );
-    spawn_barriers(
-        &mut commands,
-        &mut meshes,
-        red_material.clone(),
-        white_material.clone(),
-        inner_ring,
-    );
-    let texture = asset_server.load("sprites/start_light.png");
-    let layout = TextureAtlasLayout::from_grid(UVec2::new(15, 7), 5, 1, None, None);
-    let texture_atlas_layout = texture_atlas_layouts.add(layout);
-    commands.spawn((
-        DespawnOnExit(AppState::Game),
-        Transform::from_translation(Vec3::new(-28., -64., SpriteLayers::Car.to_z())),
-        Sprite::from_atlas_image(
-            texture,
-            TextureAtlas {
-                layout: texture_atlas_layout,
-                index: 1,
-            },
-        ),
-        StartLight,
-    ));
-    audio_manager.play_sound(PlayAudio2D::new_once("sounds/countdown.wav"));
+    spawn_barriers(&mut commands, &OUTER_RING);
+    spawn_barriers(&mut commands, &INNER_RING);
     commands.insert_resource(RaceStarted(time.elapsed_secs()));
     if !easy.is_host() {
         return;
@@ -209,10 +192,11 @@ pub(crate) fn spawn_track(
             (-39 + (i % 3) * -7) as f32,
             SpriteLayers::Car.to_z(),
         );
-        easy.instantiate(
+        easy.instantiate_with(
             AppInstantiations::Kart(player.id.clone()),
             Transform::from_translation(position)
                 .with_rotation(Quat::from_rotation_z(-90_f32.to_radians())),
+            InstantiateOptions::default().with_owner(player.id.clone()),
         );
     }
 
@@ -248,7 +232,7 @@ pub(crate) fn spawn_track(
              mut car: Query<(Entity, &mut LapsCounter, Option<&CanFinishLap>)>,
              mut commands: Commands,
              mut finish_times: ResMut<FinishTimes>,
-             easy: KartEasyP2P| {
+             easy: EasyP2P<KartProtocol>| {
                 if let Ok((entity, mut lap_counter, maybe_can_finish_lap)) =
                     car.get_mut(trigger.collider2)
                 {
@@ -289,6 +273,53 @@ pub(crate) fn spawn_track(
         );
 }
 
+// What players see of the track, left out on a dedicated host.
+pub(crate) fn spawn_track_visuals(
+    mut commands: Commands,
+    mut audio_manager: AudioManager,
+    asset_server: Res<AssetServer>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    commands.spawn((
+        DespawnOnExit(AppState::Game),
+        Sprite::from_image(asset_server.load("sprites/track.png")),
+    ));
+    let red_material = materials.add(ColorMaterial::from(Color::srgb(0.68, 0.13, 0.20)));
+    let white_material = materials.add(ColorMaterial::from(Color::srgb(1., 1., 1.)));
+    spawn_barrier_meshes(
+        &mut commands,
+        &mut meshes,
+        red_material.clone(),
+        white_material.clone(),
+        &OUTER_RING,
+    );
+    spawn_barrier_meshes(
+        &mut commands,
+        &mut meshes,
+        red_material.clone(),
+        white_material.clone(),
+        &INNER_RING,
+    );
+    let texture = asset_server.load("sprites/start_light.png");
+    let layout = TextureAtlasLayout::from_grid(UVec2::new(15, 7), 5, 1, None, None);
+    let texture_atlas_layout = texture_atlas_layouts.add(layout);
+    commands.spawn((
+        DespawnOnExit(AppState::Game),
+        Transform::from_translation(Vec3::new(-28., -64., SpriteLayers::Car.to_z())),
+        Sprite::from_atlas_image(
+            texture,
+            TextureAtlas {
+                layout: texture_atlas_layout,
+                index: 1,
+            },
+        ),
+        StartLight,
+    ));
+    audio_manager.play_sound(PlayAudio2D::new_once("sounds/countdown.wav"));
+}
+
 fn on_receive_finish_times(mut commands: Commands, mut r: MessageReader<OnFinishTimeUpdate>) {
     for OnFinishTimeUpdate(finish_times) in r.read() {
         commands.insert_resource(finish_times.clone());
@@ -298,7 +329,7 @@ fn on_receive_finish_times(mut commands: Commands, mut r: MessageReader<OnFinish
 fn handle_end_race(
     time: Res<Time>,
     mut commands: Commands,
-    easy: KartEasyP2P,
+    easy: EasyP2P<KartProtocol>,
     cars: Query<&LapsCounter>,
     mut events_w: MessageWriter<OnFinishTimeUpdate>,
     finish_times: Res<FinishTimes>,
@@ -321,7 +352,7 @@ fn end_with_delay(
     mut commands: Commands,
     time: Res<Time>,
     race_ended: Option<Res<RaceEnded>>,
-    easy: KartEasyP2P,
+    easy: EasyP2P<KartProtocol>,
     mut next_state: ResMut<NextState<AppState>>,
 ) {
     if !easy.is_host() {